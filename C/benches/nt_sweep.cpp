@@ -0,0 +1,125 @@
+#include <algorithm>
+#include <array>
+#include <chrono>
+#include <iostream>
+#include <mpi.h>
+#include <stdlib.h>
+#include <string>
+#include <unistd.h>
+#include <vector>
+
+#define CSV_SEP ","
+
+// Sweeps message sizes on both sides of the non-temporal threshold. Rank 1
+// reads every message right after receiving it, which is what temporal stores
+// are good for until the message no longer fits in the cache. Running it with
+// MPI_USE_NT=0, MPI_USE_NT=1 and without either gives the temporal, the
+// non-temporal and the adaptive curve.
+
+static constexpr int nsamples = 50;
+// The sweep covers threshold / span up to threshold * span.
+static constexpr size_t span = 4;
+
+// Same rule as nt_threshold() in backend::memory.
+static size_t nt_threshold()
+{
+    if (auto env = getenv("MPI_NT_THRESHOLD")) {
+        return std::stoul(env);
+    }
+#ifdef __GLIBC__
+    for (auto name : {_SC_LEVEL3_CACHE_SIZE, _SC_LEVEL2_CACHE_SIZE}) {
+        auto size = sysconf(name);
+        if (size > 0) {
+            return size / 2;
+        }
+    }
+#endif
+    return 1 << 22;
+}
+
+int main(int argc, char** argv)
+{
+    using clock = std::chrono::high_resolution_clock;
+    using std::chrono::duration_cast;
+    using unit = std::chrono::nanoseconds;
+
+    char const* fileName;
+    if (argc >= 2) {
+        fileName = argv[1];
+    } else {
+        fileName = "nt_sweep.csv";
+    }
+
+    if (argc == 3 && std::string(argv[2]) != "0") {
+        setenv("MPI_SIZE", argv[2], 1);
+    }
+
+    MPI_Init(nullptr, nullptr);
+    int rank;
+    MPI_Comm_rank(MPI_COMM_WORLD, &rank);
+
+    auto const threshold = nt_threshold();
+    std::vector<size_t> sizes;
+    for (size_t size = threshold / span; size <= threshold * span; size *= 2) {
+        sizes.push_back(size);
+        if (size + size / 2 <= threshold * span) {
+            sizes.push_back(size + size / 2);
+        }
+    }
+
+    auto msg = new ((std::align_val_t)32) char[sizes.back()];
+    std::array<long, nsamples> times;
+    FILE* csvFile = nullptr;
+    if (rank == 0) {
+        csvFile = fopen(fileName, "w");
+        fprintf(csvFile,
+                "Size" CSV_SEP "Threshold" CSV_SEP "unit" CSV_SEP
+                "Time min" CSV_SEP "Time median\n");
+    }
+
+    for (auto size : sizes) {
+        for (int sample = 0; sample < nsamples; sample++) {
+            int ack = 0;
+            MPI_Barrier(MPI_COMM_WORLD);
+            if (rank == 0) {
+                std::fill(msg, msg + size, (char)sample);
+                auto start = clock::now();
+                MPI_Send(msg, size, MPI_BYTE, 1, 0, MPI_COMM_WORLD);
+                MPI_Recv(&ack, 1, MPI_INT, 1, 1, MPI_COMM_WORLD,
+                         MPI_STATUS_IGNORE);
+                times[sample]
+                        = duration_cast<unit>(clock::now() - start).count();
+            } else if (rank == 1) {
+                MPI_Recv(msg, size, MPI_BYTE, 0, 0, MPI_COMM_WORLD,
+                         MPI_STATUS_IGNORE);
+                long sum = 0;
+                for (size_t l = 0; l < size; l += 64) {
+                    sum += msg[l];
+                }
+                ack = sum != (long)(size + 63) / 64 * (char)sample;
+                MPI_Send(&ack, 1, MPI_INT, 0, 1, MPI_COMM_WORLD);
+            }
+            if (rank == 0 && ack != 0) {
+                std::cerr << "Wrong data for size " << size << "\n";
+                MPI_Abort(MPI_COMM_WORLD, 1);
+            }
+        }
+        if (rank == 0) {
+            std::sort(times.begin(), times.end());
+            fprintf(csvFile,
+                    "%zu" CSV_SEP "%zu" CSV_SEP "%s" CSV_SEP "%ld" CSV_SEP
+                    "%ld\n",
+                    size,
+                    threshold,
+                    "ns",
+                    times[0],
+                    times[nsamples / 2]);
+        }
+    }
+
+    if (rank == 0) {
+        fclose(csvFile);
+    }
+    delete[] msg;
+    MPI_Finalize();
+}
@@ -7,16 +7,47 @@ typedef int32_t MPI_Datatype;
 typedef int32_t MPI_Comm;
 typedef int32_t MPI_Op;
 typedef int32_t MPI_Errhandler;
+typedef intptr_t MPI_Aint;
 typedef int32_t i32;
 
 #define MPI_UNDEFINED -1
+
+#define MPI_ANY_TAG -1
+#define MPI_ANY_SOURCE -2
+#define MPI_PROC_NULL -3
+#define MPI_BSEND_OVERHEAD 32
 #define MPI_COMM_NULL MPI_UNDEFINED
 #define MPI_COMM_SELF 0
 #define MPI_COMM_WORLD 1
 
+#define MPI_DATATYPE_NULL 0
 #define MPI_BYTE 1
+#define MPI_CHAR 2
+#define MPI_SHORT 3
 #define MPI_INT 4
+#define MPI_LONG 5
+#define MPI_LONG_LONG 6
+#define MPI_FLOAT 7
 #define MPI_DOUBLE 8
+#define MPI_UNSIGNED_CHAR 9
+#define MPI_UNSIGNED_SHORT 10
+#define MPI_UNSIGNED 11
+#define MPI_UNSIGNED_LONG 12
+#define MPI_UNSIGNED_LONG_LONG 13
+#define MPI_INT8_T 14
+#define MPI_INT16_T 15
+#define MPI_INT32_T 16
+#define MPI_INT64_T 17
+#define MPI_UINT8_T 18
+#define MPI_UINT16_T 19
+#define MPI_UINT32_T 20
+#define MPI_UINT64_T 21
+#define MPI_C_BOOL 22
+#define MPI_C_DOUBLE_COMPLEX 23
+#define MPI_PACKED 24
+
+#define MPI_ORDER_C 56
+#define MPI_ORDER_FORTRAN 57
 
 #define MPI_MAX 0
 #define MPI_MIN 1
@@ -44,7 +75,9 @@ typedef int32_t i32;
 #define MPI_ERR_INTERN 14
 #define MPI_ERR_PENDING 15
 #define MPI_ERR_IN_STATUS 16
-#define MPI_ERR_LASTCODE 16
+#define MPI_ERR_NO_MEM 17
+#define MPI_ERR_CONVERSION 18
+#define MPI_ERR_LASTCODE 19
 
 extern "C" {
 typedef struct _MPI_Status {
@@ -52,17 +85,18 @@ typedef struct _MPI_Status {
     int32_t MPI_TAG;
     int32_t MPI_ERROR;
     int32_t cnt;
+    int32_t cancelled;
 } MPI_Status;
 
-typedef struct _MPI_Request {
-    void* buf;
-    MPI_Status stat;
-    MPI_Comm comm;
-    int32_t flag;
-    int32_t tag;
-    int32_t cnt;
-    int32_t rank;
-} * MPI_Request;
+typedef struct _MPI_Request* MPI_Request;
+
+typedef struct _MPI_Request* MPI_Message;
+#define MPI_MESSAGE_NULL ((MPI_Message)0)
+#define MPI_MESSAGE_NO_PROC ((MPI_Message)-1)
+#define MPI_REQUEST_NULL ((MPI_Request)0)
+
+#define MPI_STATUS_IGNORE ((MPI_Status*)0)
+#define MPI_STATUSES_IGNORE ((MPI_Status*)0)
 
 MPI_EXPORT i32 MPI_Init(i32*, char***);
 MPI_EXPORT i32 MPI_Finalize();
@@ -84,14 +118,93 @@ MPI_EXPORT i32 MPI_Sendrecv(
         i32,
         MPI_Comm,
         MPI_Status*);
+MPI_EXPORT i32 MPI_Sendrecv_replace(
+        void*, i32, MPI_Datatype, i32, i32, i32, i32, MPI_Comm, MPI_Status*);
 MPI_EXPORT i32
 MPI_Isend(const void*, i32, MPI_Datatype, i32, i32, MPI_Comm, MPI_Request*);
 MPI_EXPORT i32
 MPI_Irecv(void*, i32, MPI_Datatype, i32, i32, MPI_Comm, MPI_Request*);
+MPI_EXPORT i32 MPI_Ssend(const void*, i32, MPI_Datatype, i32, i32, MPI_Comm);
+MPI_EXPORT i32 MPI_Rsend(const void*, i32, MPI_Datatype, i32, i32, MPI_Comm);
+MPI_EXPORT i32 MPI_Bsend(const void*, i32, MPI_Datatype, i32, i32, MPI_Comm);
+MPI_EXPORT i32
+MPI_Issend(const void*, i32, MPI_Datatype, i32, i32, MPI_Comm, MPI_Request*);
+MPI_EXPORT i32
+MPI_Irsend(const void*, i32, MPI_Datatype, i32, i32, MPI_Comm, MPI_Request*);
+MPI_EXPORT i32
+MPI_Ibsend(const void*, i32, MPI_Datatype, i32, i32, MPI_Comm, MPI_Request*);
+MPI_EXPORT i32 MPI_Buffer_attach(void*, i32);
+MPI_EXPORT i32 MPI_Buffer_detach(void*, i32*);
+MPI_EXPORT i32
+MPI_Send_init(const void*, i32, MPI_Datatype, i32, i32, MPI_Comm, MPI_Request*);
+MPI_EXPORT i32
+MPI_Recv_init(void*, i32, MPI_Datatype, i32, i32, MPI_Comm, MPI_Request*);
+MPI_EXPORT i32 MPI_Start(MPI_Request*);
+MPI_EXPORT i32 MPI_Startall(i32, MPI_Request*);
+MPI_EXPORT i32 MPI_Request_free(MPI_Request*);
+MPI_EXPORT i32 MPI_Iprobe(i32, i32, MPI_Comm, i32*, MPI_Status*);
+MPI_EXPORT i32 MPI_Probe(i32, i32, MPI_Comm, MPI_Status*);
+MPI_EXPORT i32 MPI_Improbe(i32, i32, MPI_Comm, i32*, MPI_Message*, MPI_Status*);
+MPI_EXPORT i32 MPI_Mprobe(i32, i32, MPI_Comm, MPI_Message*, MPI_Status*);
+MPI_EXPORT i32 MPI_Imrecv(void*, i32, MPI_Datatype, MPI_Message*, MPI_Request*);
+MPI_EXPORT i32 MPI_Mrecv(void*, i32, MPI_Datatype, MPI_Message*, MPI_Status*);
 MPI_EXPORT i32 MPI_Test(MPI_Request*, i32*, MPI_Status*);
 MPI_EXPORT i32 MPI_Wait(MPI_Request*, MPI_Status*);
 MPI_EXPORT i32 MPI_Waitall(i32, MPI_Request*, MPI_Status*);
+MPI_EXPORT i32 MPI_Cancel(MPI_Request*);
+MPI_EXPORT i32 MPI_Test_cancelled(const MPI_Status*, i32*);
+MPI_EXPORT i32 MPI_Waitany(i32, MPI_Request*, i32*, MPI_Status*);
+MPI_EXPORT i32 MPI_Waitsome(i32, MPI_Request*, i32*, i32*, MPI_Status*);
+MPI_EXPORT i32 MPI_Testall(i32, MPI_Request*, i32*, MPI_Status*);
+MPI_EXPORT i32 MPI_Testany(i32, MPI_Request*, i32*, i32*, MPI_Status*);
+MPI_EXPORT i32 MPI_Testsome(i32, MPI_Request*, i32*, i32*, MPI_Status*);
 MPI_EXPORT i32 MPI_Type_size(MPI_Datatype, i32*);
+MPI_EXPORT i32 MPI_Type_get_extent(MPI_Datatype, MPI_Aint*, MPI_Aint*);
+MPI_EXPORT i32 MPI_Type_contiguous(i32, MPI_Datatype, MPI_Datatype*);
+MPI_EXPORT i32 MPI_Type_vector(i32, i32, i32, MPI_Datatype, MPI_Datatype*);
+MPI_EXPORT i32
+MPI_Type_create_hvector(i32, i32, MPI_Aint, MPI_Datatype, MPI_Datatype*);
+MPI_EXPORT i32
+MPI_Type_indexed(i32, const i32*, const i32*, MPI_Datatype, MPI_Datatype*);
+MPI_EXPORT i32 MPI_Type_create_struct(
+        i32,
+        const i32*,
+        const MPI_Aint*,
+        const MPI_Datatype*,
+        MPI_Datatype*);
+MPI_EXPORT i32 MPI_Type_create_subarray(
+        i32,
+        const i32*,
+        const i32*,
+        const i32*,
+        i32,
+        MPI_Datatype,
+        MPI_Datatype*);
+MPI_EXPORT i32 MPI_Type_commit(MPI_Datatype*);
+MPI_EXPORT i32 MPI_Type_free(MPI_Datatype*);
+MPI_EXPORT i32
+MPI_Pack(const void*, i32, MPI_Datatype, void*, i32, i32*, MPI_Comm);
+MPI_EXPORT i32
+MPI_Unpack(const void*, i32, i32*, void*, i32, MPI_Datatype, MPI_Comm);
+MPI_EXPORT i32 MPI_Pack_size(i32, MPI_Datatype, MPI_Comm, i32*);
+MPI_EXPORT i32 MPI_Pack_external(
+        const char*,
+        const void*,
+        i32,
+        MPI_Datatype,
+        void*,
+        MPI_Aint,
+        MPI_Aint*);
+MPI_EXPORT i32 MPI_Unpack_external(
+        const char*,
+        const void*,
+        MPI_Aint,
+        MPI_Aint*,
+        void*,
+        i32,
+        MPI_Datatype);
+MPI_EXPORT i32
+MPI_Pack_external_size(const char*, i32, MPI_Datatype, MPI_Aint*);
 MPI_EXPORT i32 MPI_Get_count(MPI_Status*, MPI_Datatype, i32*);
 MPI_EXPORT i32 MPI_Barrier(MPI_Comm);
 MPI_EXPORT i32 MPI_Bcast(void*, i32, MPI_Datatype, i32, MPI_Comm);
@@ -116,5 +229,6 @@ MPI_EXPORT i32 MPI_Comm_dup(MPI_Comm, MPI_Comm*);
 MPI_EXPORT i32 MPI_Comm_split(MPI_Comm, i32, i32, MPI_Comm*);
 MPI_EXPORT i32 MPI_Comm_get_errhandler(MPI_Comm, MPI_Errhandler*);
 MPI_EXPORT i32 MPI_Comm_set_errhandler(MPI_Comm, MPI_Errhandler);
-MPI_EXPORT i32 MPI_ntcpy(void* dest, const void* src, size_t size);
+MPI_EXPORT void MPI_cpy(void* dest, const void* src, size_t size);
+MPI_EXPORT void MPI_ntcpy(void* dest, const void* src, size_t size);
 }
@@ -31,6 +31,18 @@ macro_rules! bench_nt_internal {
     };
 }
 
+macro_rules! bench_tmp_internal {
+    ($name:literal, $tasks:literal) => {
+        let _ = create_dir(concat!("C/output/", $name));
+        bench_create_internal!($name, $tasks, concat!("tmp", $name))
+        .env("MPI_USE_NT", "0")
+        .spawn()
+        .unwrap()
+        .wait()
+        .unwrap();
+    };
+}
+
 macro_rules! bench_create {
     ($name:literal, $out:literal, $job:literal, $tasks:literal) => {
         std::process::Command::new("sbatch")
@@ -66,8 +78,25 @@ macro_rules! bench_nt {
     };
 }
 
+macro_rules! bench_tmp {
+    ($name:literal, $out:expr, $job:literal, $tasks:literal) => {
+        let _ = create_dir(concat!("C/output/", $name));
+        bench_create!($name, $out, $job, $tasks)
+            .env("MPI_USE_NT", "0")
+            .spawn()
+            .unwrap()
+            .wait()
+            .unwrap();
+    };
+}
+
 fn bench_slurm()
 {
+    // Temporal, non-temporal and adaptive copies around the threshold.
+    bench!("nt_sweep", "nt_sweep_2_.csv", "p2p", 2);
+    bench_nt!("nt_sweep", "ntnt_sweep_2_.csv", "p2p", 2);
+    bench_tmp!("nt_sweep", "tmpnt_sweep_2_.csv", "p2p", 2);
+
     bench!("bcast", "bcast_4_.csv", "collective", 4);
     bench_nt!("bcast", "ntbcast_4_.csv", "collective", 4);
     bench!("bcast", "bcast_8_.csv", "collective", 8);
@@ -96,6 +125,10 @@ fn bench_slurm()
 
 fn bench_internal()
 {
+    bench_internal!("nt_sweep", 2);
+    bench_nt_internal!("nt_sweep", 2);
+    bench_tmp_internal!("nt_sweep", 2);
+
     bench_internal!("bcast", 4);
     bench_internal!("bcast", 8);
     bench_nt_internal!("bcast", 4);
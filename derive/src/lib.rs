@@ -0,0 +1,88 @@
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{parse_macro_input, parse_quote, Data, DeriveInput, Fields, Index, Type};
+
+/// Implements `mpi::Typed` for a struct with a derived datatype made of its
+/// fields at their offsets. The extent is the size of the struct, so slices
+/// of it skip the padding.
+#[proc_macro_derive(Typed)]
+pub fn derive_typed(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand(input) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+fn expand(mut input: DeriveInput) -> syn::Result<TokenStream2> {
+    let Data::Struct(data) = &input.data else {
+        return Err(syn::Error::new_spanned(
+            &input.ident,
+            "Typed can only be derived for structs",
+        ));
+    };
+    if let Fields::Unit = data.fields {
+        return Err(syn::Error::new_spanned(
+            &input.ident,
+            "Typed can't be derived for a struct without fields",
+        ));
+    }
+
+    let fields: Vec<TokenStream2> = data
+        .fields
+        .iter()
+        .enumerate()
+        .map(|(i, field)| {
+            let member = match &field.ident {
+                Some(ident) => quote!(#ident),
+                None => {
+                    let index = Index::from(i);
+                    quote!(#index)
+                }
+            };
+            let (elem, cnt) = flatten(&field.ty);
+            quote! {
+                (
+                    ::core::mem::offset_of!(Self, #member),
+                    #cnt,
+                    <#elem as ::mpi::Typed>::into_mpi(),
+                )
+            }
+        })
+        .collect();
+
+    for param in input.generics.type_params_mut() {
+        param.bounds.push(parse_quote!(::mpi::Typed));
+        param.bounds.push(parse_quote!('static));
+    }
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::mpi::Typed for #name #ty_generics #where_clause {
+            // `Data` allocates with this, over-aligned structs need more.
+            const ALIGN: usize = if ::core::mem::align_of::<Self>() > 32 {
+                ::core::mem::align_of::<Self>()
+            } else {
+                32
+            };
+            fn into_mpi() -> i32 {
+                ::mpi::derived_type::<Self>(|| ::std::vec![#(#fields),*])
+            }
+        }
+    })
+}
+
+/// The element type of a possibly nested array and the number of elements.
+fn flatten(ty: &Type) -> (&Type, TokenStream2) {
+    match ty {
+        Type::Array(array) => {
+            let (elem, cnt) = flatten(&array.elem);
+            let len = &array.len;
+            (elem, quote!((#len) as i32 * #cnt))
+        }
+        Type::Paren(paren) => flatten(&paren.elem),
+        Type::Group(group) => flatten(&group.elem),
+        _ => (ty, quote!(1)),
+    }
+}
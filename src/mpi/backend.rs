@@ -1,3 +1,5 @@
 pub(crate) mod memory;
 pub(crate) mod reqqueue;
+pub(crate) mod segment;
 pub(crate) mod shm;
+pub(crate) mod wait;
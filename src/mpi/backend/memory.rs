@@ -1,14 +1,16 @@
+#[cfg(target_arch = "x86_64")]
 use std::arch::asm;
 use std::ffi::c_void;
 
 use crate::context::Context;
 
-#[cfg(target_feature = "sse2")]
-pub fn sse2_ntcpy(mut dest: *mut c_void, mut src: *const c_void, mut size: usize) {
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "sse2")]
+pub unsafe fn sse2_ntcpy(mut dest: *mut c_void, mut src: *const c_void, mut size: usize) {
     unsafe {
         if dest as usize % 16 != 0 || src as usize % 16 != 0 {
             if dest as usize % 16 == src as usize % 16 {
-                while dest as usize % 16 != 0 {
+                while dest as usize % 16 != 0 && size != 0 {
                     *(dest as *mut u8) = *(src as *const u8);
                     dest = dest.add(1);
                     src = src.add(1);
@@ -110,12 +112,13 @@ pub fn sse2_ntcpy(mut dest: *mut c_void, mut src: *const c_void, mut size: usize
     }
 }
 
-#[cfg(target_feature = "avx2")]
-pub fn avx2_ntcpy(mut dest: *mut c_void, mut src: *const c_void, mut size: usize) {
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+pub unsafe fn avx2_ntcpy(mut dest: *mut c_void, mut src: *const c_void, mut size: usize) {
     unsafe {
         if dest as usize % 32 != 0 || src as usize % 32 != 0 {
             if dest as usize % 32 == src as usize % 32 {
-                while dest as usize % 32 != 0 {
+                while dest as usize % 32 != 0 && size != 0 {
                     *(dest as *mut u8) = *(src as *const u8);
                     dest = dest.add(1);
                     src = src.add(1);
@@ -126,8 +129,8 @@ pub fn avx2_ntcpy(mut dest: *mut c_void, mut src: *const c_void, mut size: usize
                 return;
             }
         }
-        debug_assert!(dest as usize % 32 == 0);
-        debug_assert!(src as usize % 32 == 0);
+        debug_assert!(size == 0 || dest as usize % 32 == 0);
+        debug_assert!(size == 0 || src as usize % 32 == 0);
         while size >= 256 {
             asm!(
                 "vmovdqa {temp0}, [{src} + 0]",
@@ -232,12 +235,13 @@ pub fn avx2_ntcpy(mut dest: *mut c_void, mut src: *const c_void, mut size: usize
     }
 }
 
-#[cfg(target_feature = "avx")]
-pub fn avx_ntcpy(mut dest: *mut c_void, mut src: *const c_void, mut n: usize) {
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx")]
+pub unsafe fn avx_ntcpy(mut dest: *mut c_void, mut src: *const c_void, mut n: usize) {
     unsafe {
         if dest as usize % 16 != 0 || src as usize % 16 != 0 {
             if dest as usize % 16 == src as usize % 16 {
-                while dest as usize % 16 != 0 {
+                while dest as usize % 16 != 0 && n != 0 {
                     *(dest as *mut u8) = *(src as *const u8);
                     dest = dest.add(1);
                     src = src.add(1);
@@ -248,8 +252,8 @@ pub fn avx_ntcpy(mut dest: *mut c_void, mut src: *const c_void, mut n: usize) {
                 return;
             }
         }
-        debug_assert!(dest as usize % 16 == 0);
-        debug_assert!(src as usize % 16 == 0);
+        debug_assert!(n == 0 || dest as usize % 16 == 0);
+        debug_assert!(n == 0 || src as usize % 16 == 0);
         while n >= 128 {
             asm!(
                 "vmovdqa {temp0}, [{src} + 0]",
@@ -342,77 +346,13 @@ pub fn avx_ntcpy(mut dest: *mut c_void, mut src: *const c_void, mut n: usize) {
     }
 }
 
-#[cfg(target_feature = "avx512f")]
-pub fn avx512_ntcpy(mut dest: *mut c_void, mut src: *mut c_void, size: usize) {
-    unsafe {
-        while size >= 128 {
-            asm!(
-                "vmovdqa {temp0}, [{src} + 0]",
-                "vmovdqa {temp0}, [{src} + 64]",
-                "vmovntdq [{dest} + 0], {temp0}",
-                "vmovntdq [{dest} + 64], {temp1}",
-                dest = inout(reg) dest,
-                src = inout(reg) src,
-                temp0 = out(zmm_reg) _,
-                temp1 = out(zmm_reg) _,
-            );
-            dest = dest.add(128);
-            src = src.add(128);
-            size -= 128;
-        }
-        if size >= 64 {
-            asm!(
-                "vmovdqa {temp0}, [{src} + 0]",
-                "vmovntdq [{dest} + 0], {temp0}",
-                dest = inout(reg) dest,
-                src = inout(reg) src,
-                temp0 = out(zmm_reg) _,
-            );
-            dest = dest.add(64);
-            src = src.add(64);
-            size -= 64;
-        }
-        if size >= 32 {
-            asm!(
-                "vmovdqa {temp0}, [{src} + 0]",
-                "vmovntdq [{dest} + 0], {temp0}",
-                dest = inout(reg) dest,
-                src = inout(reg) src,
-                temp0 = out(ymm_reg) _,
-            );
-            dest = dest.add(32);
-            src = src.add(32);
-            size -= 32;
-        }
-        if size >= 16 {
-            asm!(
-                "vmovdqa {temp0}, [{src} + 0]",
-                "vmovntdq [{dest} + 0], {temp0}",
-                dest = inout(reg) dest,
-                src = inout(reg) src,
-                temp0 = out(xmm_reg) _,
-            );
-            dest = dest.add(16);
-            src = src.add(16);
-            size -= 32;
-        }
-        while size != 0 {
-            *(dest as *mut u8) = *(src as *const u8);
-            dest = dest.add(1);
-            src = src.add(1);
-            size -= 1;
-        }
-        asm!("sfence");
-        asm!("vzeroupper");
-    }
-}
-
-#[cfg(target_feature = "sse2")]
-pub fn sse2_cpy(mut dest: *mut c_void, mut src: *const c_void, mut size: usize) {
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "sse2")]
+pub unsafe fn sse2_cpy(mut dest: *mut c_void, mut src: *const c_void, mut size: usize) {
     unsafe {
         if dest as usize % 16 != 0 || src as usize % 16 != 0 {
             if dest as usize % 16 == src as usize % 16 {
-                while dest as usize % 16 != 0 {
+                while dest as usize % 16 != 0 && size != 0 {
                     *(dest as *mut u8) = *(src as *const u8);
                     dest = dest.add(1);
                     src = src.add(1);
@@ -513,12 +453,13 @@ pub fn sse2_cpy(mut dest: *mut c_void, mut src: *const c_void, mut size: usize)
     }
 }
 
-#[cfg(target_feature = "avx2")]
-pub fn avx2_cpy(mut dest: *mut c_void, mut src: *const c_void, mut size: usize) {
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+pub unsafe fn avx2_cpy(mut dest: *mut c_void, mut src: *const c_void, mut size: usize) {
     unsafe {
         if dest as usize % 32 != 0 || src as usize % 32 != 0 {
             if dest as usize % 32 == src as usize % 32 {
-                while dest as usize % 32 != 0 {
+                while dest as usize % 32 != 0 && size != 0 {
                     *(dest as *mut u8) = *(src as *const u8);
                     dest = dest.add(1);
                     src = src.add(1);
@@ -529,8 +470,8 @@ pub fn avx2_cpy(mut dest: *mut c_void, mut src: *const c_void, mut size: usize)
                 return;
             }
         }
-        debug_assert!(dest as usize % 32 == 0);
-        debug_assert!(src as usize % 32 == 0);
+        debug_assert!(size == 0 || dest as usize % 32 == 0);
+        debug_assert!(size == 0 || src as usize % 32 == 0);
         while size >= 256 {
             asm!(
                 "vmovdqa {temp0}, [{src} + 0]",
@@ -634,12 +575,13 @@ pub fn avx2_cpy(mut dest: *mut c_void, mut src: *const c_void, mut size: usize)
     }
 }
 
-#[cfg(target_feature = "avx")]
-pub fn avx_cpy(mut dest: *mut c_void, mut src: *const c_void, mut n: usize) {
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx")]
+pub unsafe fn avx_cpy(mut dest: *mut c_void, mut src: *const c_void, mut n: usize) {
     unsafe {
         if dest as usize % 16 != 0 || src as usize % 16 != 0 {
             if dest as usize % 16 == src as usize % 16 {
-                while dest as usize % 16 != 0 {
+                while dest as usize % 16 != 0 && n != 0 {
                     *(dest as *mut u8) = *(src as *const u8);
                     dest = dest.add(1);
                     src = src.add(1);
@@ -650,8 +592,8 @@ pub fn avx_cpy(mut dest: *mut c_void, mut src: *const c_void, mut n: usize) {
                 return;
             }
         }
-        debug_assert!(dest as usize % 16 == 0);
-        debug_assert!(src as usize % 16 == 0);
+        debug_assert!(n == 0 || dest as usize % 16 == 0);
+        debug_assert!(n == 0 || src as usize % 16 == 0);
         while n >= 128 {
             asm!(
                 "vmovdqa {temp0}, [{src} + 0]",
@@ -743,17 +685,52 @@ pub fn avx_cpy(mut dest: *mut c_void, mut src: *const c_void, mut n: usize) {
     }
 }
 
-#[cfg(target_feature = "avx512f")]
-pub fn avx512_cpy(mut dest: *mut c_void, mut src: *mut c_void, size: usize) {
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx512f")]
+pub unsafe fn avx512_ntcpy(mut dest: *mut c_void, mut src: *const c_void, mut size: usize) {
     unsafe {
-        while size >= 128 {
+        if dest as usize % 64 != 0 || src as usize % 64 != 0 {
+            if dest as usize % 64 == src as usize % 64 {
+                while dest as usize % 64 != 0 && size != 0 {
+                    *(dest as *mut u8) = *(src as *const u8);
+                    dest = dest.add(1);
+                    src = src.add(1);
+                    size -= 1;
+                }
+            } else {
+                std::ptr::copy(src, dest, size);
+                return;
+            }
+        }
+        while size >= 256 {
             asm!(
-                "vmovdqa {temp0}, [{src} + 0]",
-                "vmovdqa {temp0}, [{src} + 64]",
-                "vmovdqa [{dest} + 0], {temp0}",
-                "vmovdqa [{dest} + 64], {temp1}",
-                dest = inout(reg) dest,
-                src = inout(reg) src,
+                "vmovdqa64 {temp0}, [{src} + 0]",
+                "vmovdqa64 {temp1}, [{src} + 64]",
+                "vmovdqa64 {temp2}, [{src} + 128]",
+                "vmovdqa64 {temp3}, [{src} + 192]",
+                "vmovntdq [{dest} + 0], {temp0}",
+                "vmovntdq [{dest} + 64], {temp1}",
+                "vmovntdq [{dest} + 128], {temp2}",
+                "vmovntdq [{dest} + 192], {temp3}",
+                dest = in(reg) dest,
+                src = in(reg) src,
+                temp0 = out(zmm_reg) _,
+                temp1 = out(zmm_reg) _,
+                temp2 = out(zmm_reg) _,
+                temp3 = out(zmm_reg) _,
+            );
+            dest = dest.add(256);
+            src = src.add(256);
+            size -= 256;
+        }
+        if size >= 128 {
+            asm!(
+                "vmovdqa64 {temp0}, [{src} + 0]",
+                "vmovdqa64 {temp1}, [{src} + 64]",
+                "vmovntdq [{dest} + 0], {temp0}",
+                "vmovntdq [{dest} + 64], {temp1}",
+                dest = in(reg) dest,
+                src = in(reg) src,
                 temp0 = out(zmm_reg) _,
                 temp1 = out(zmm_reg) _,
             );
@@ -762,11 +739,111 @@ pub fn avx512_cpy(mut dest: *mut c_void, mut src: *mut c_void, size: usize) {
             size -= 128;
         }
         if size >= 64 {
+            asm!(
+                "vmovdqa64 {temp0}, [{src} + 0]",
+                "vmovntdq [{dest} + 0], {temp0}",
+                dest = in(reg) dest,
+                src = in(reg) src,
+                temp0 = out(zmm_reg) _,
+            );
+            dest = dest.add(64);
+            src = src.add(64);
+            size -= 64;
+        }
+        if size >= 32 {
             asm!(
                 "vmovdqa {temp0}, [{src} + 0]",
-                "vmovdqa [{dest} + 0], {temp0}",
-                dest = inout(reg) dest,
-                src = inout(reg) src,
+                "vmovntdq [{dest} + 0], {temp0}",
+                dest = in(reg) dest,
+                src = in(reg) src,
+                temp0 = out(ymm_reg) _,
+            );
+            dest = dest.add(32);
+            src = src.add(32);
+            size -= 32;
+        }
+        if size >= 16 {
+            asm!(
+                "vmovdqa {temp0}, [{src} + 0]",
+                "vmovntdq [{dest} + 0], {temp0}",
+                dest = in(reg) dest,
+                src = in(reg) src,
+                temp0 = out(xmm_reg) _,
+            );
+            dest = dest.add(16);
+            src = src.add(16);
+            size -= 16;
+        }
+        while size != 0 {
+            *(dest as *mut u8) = *(src as *const u8);
+            dest = dest.add(1);
+            src = src.add(1);
+            size -= 1;
+        }
+        asm!("sfence");
+        asm!("vzeroupper");
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx512f")]
+pub unsafe fn avx512_cpy(mut dest: *mut c_void, mut src: *const c_void, mut size: usize) {
+    unsafe {
+        if dest as usize % 64 != 0 || src as usize % 64 != 0 {
+            if dest as usize % 64 == src as usize % 64 {
+                while dest as usize % 64 != 0 && size != 0 {
+                    *(dest as *mut u8) = *(src as *const u8);
+                    dest = dest.add(1);
+                    src = src.add(1);
+                    size -= 1;
+                }
+            } else {
+                std::ptr::copy(src, dest, size);
+                return;
+            }
+        }
+        while size >= 256 {
+            asm!(
+                "vmovdqa64 {temp0}, [{src} + 0]",
+                "vmovdqa64 {temp1}, [{src} + 64]",
+                "vmovdqa64 {temp2}, [{src} + 128]",
+                "vmovdqa64 {temp3}, [{src} + 192]",
+                "vmovdqa64 [{dest} + 0], {temp0}",
+                "vmovdqa64 [{dest} + 64], {temp1}",
+                "vmovdqa64 [{dest} + 128], {temp2}",
+                "vmovdqa64 [{dest} + 192], {temp3}",
+                dest = in(reg) dest,
+                src = in(reg) src,
+                temp0 = out(zmm_reg) _,
+                temp1 = out(zmm_reg) _,
+                temp2 = out(zmm_reg) _,
+                temp3 = out(zmm_reg) _,
+            );
+            dest = dest.add(256);
+            src = src.add(256);
+            size -= 256;
+        }
+        if size >= 128 {
+            asm!(
+                "vmovdqa64 {temp0}, [{src} + 0]",
+                "vmovdqa64 {temp1}, [{src} + 64]",
+                "vmovdqa64 [{dest} + 0], {temp0}",
+                "vmovdqa64 [{dest} + 64], {temp1}",
+                dest = in(reg) dest,
+                src = in(reg) src,
+                temp0 = out(zmm_reg) _,
+                temp1 = out(zmm_reg) _,
+            );
+            dest = dest.add(128);
+            src = src.add(128);
+            size -= 128;
+        }
+        if size >= 64 {
+            asm!(
+                "vmovdqa64 {temp0}, [{src} + 0]",
+                "vmovdqa64 [{dest} + 0], {temp0}",
+                dest = in(reg) dest,
+                src = in(reg) src,
                 temp0 = out(zmm_reg) _,
             );
             dest = dest.add(64);
@@ -777,8 +854,8 @@ pub fn avx512_cpy(mut dest: *mut c_void, mut src: *mut c_void, size: usize) {
             asm!(
                 "vmovdqa {temp0}, [{src} + 0]",
                 "vmovdqa [{dest} + 0], {temp0}",
-                dest = inout(reg) dest,
-                src = inout(reg) src,
+                dest = in(reg) dest,
+                src = in(reg) src,
                 temp0 = out(ymm_reg) _,
             );
             dest = dest.add(32);
@@ -789,8 +866,8 @@ pub fn avx512_cpy(mut dest: *mut c_void, mut src: *mut c_void, size: usize) {
             asm!(
                 "vmovdqa {temp0}, [{src} + 0]",
                 "vmovdqa [{dest} + 0], {temp0}",
-                dest = inout(reg) dest,
-                src = inout(reg) src,
+                dest = in(reg) dest,
+                src = in(reg) src,
                 temp0 = out(xmm_reg) _,
             );
             dest = dest.add(16);
@@ -807,80 +884,140 @@ pub fn avx512_cpy(mut dest: *mut c_void, mut src: *mut c_void, size: usize) {
     }
 }
 
-use crate::debug_bkd;
+/// Copies with whatever the compiler makes of it, for CPUs without the
+/// extensions above.
+pub unsafe fn portable_cpy(dest: *mut c_void, src: *const c_void, size: usize) {
+    unsafe { std::ptr::copy(src, dest, size) }
+}
 
-pub fn memcpy(dest: *mut c_void, src: *const c_void, size: usize) {
-    if cfg!(feature = "ntcpy") || Context::use_nt() {
-        debug_bkd!("memory", "Copy non-temporal {size} bytes");
-        if size == 0 {
-            return;
+pub type CopyFn = unsafe fn(*mut c_void, *const c_void, usize);
+
+/// The set of copy routines in use, the widest the CPU supports unless forced
+/// through `MPI_COPY`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CopyVariant {
+    Portable,
+    Sse2,
+    Avx,
+    Avx2,
+    Avx512,
+}
+
+impl CopyVariant {
+    pub fn parse(val: &str) -> Option<CopyVariant> {
+        match val {
+            "portable" => Some(CopyVariant::Portable),
+            "sse2" => Some(CopyVariant::Sse2),
+            "avx" => Some(CopyVariant::Avx),
+            "avx2" => Some(CopyVariant::Avx2),
+            "avx512" => Some(CopyVariant::Avx512),
+            _ => None,
         }
-        MPI_ntcpy(dest, src, size);
-    } else {
-        debug_bkd!("memory", "Copy default {size} bytes");
-        if size == 0 {
-            return;
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    pub fn is_supported(self) -> bool {
+        match self {
+            CopyVariant::Portable => true,
+            CopyVariant::Sse2 => is_x86_feature_detected!("sse2"),
+            CopyVariant::Avx => is_x86_feature_detected!("avx"),
+            CopyVariant::Avx2 => is_x86_feature_detected!("avx2"),
+            CopyVariant::Avx512 => is_x86_feature_detected!("avx512f"),
         }
-        MPI_cpy(dest, src, size);
     }
-}
 
-pub fn memcpy_slice<T>(dest: &mut [T], src: &[T], size: usize) {
-    memcpy(
-        dest.as_mut_ptr() as *mut c_void,
-        src.as_ptr() as *const c_void,
-        size,
-    )
-}
+    #[cfg(not(target_arch = "x86_64"))]
+    pub fn is_supported(self) -> bool {
+        self == CopyVariant::Portable
+    }
 
-#[cfg(target_feature = "avx512")]
-pub extern "C" fn MPI_ntcpy(dest: *mut c_void, src: *const c_void, size: usize) {
-    avx512_ntcpy(dest, src, size);
-}
+    pub fn detect() -> CopyVariant {
+        [
+            CopyVariant::Avx512,
+            CopyVariant::Avx2,
+            CopyVariant::Avx,
+            CopyVariant::Sse2,
+        ]
+        .into_iter()
+        .find(|variant| variant.is_supported())
+        .unwrap_or(CopyVariant::Portable)
+    }
 
-#[cfg(all(target_feature = "avx", not(target_feature = "avx2")))]
-pub extern "C" fn MPI_ntcpy(dest: *mut c_void, src: *const c_void, size: usize) {
-    avx_ntcpy(dest, src, size);
+    /// The regular and the non-temporal copy, the variant must be supported.
+    pub fn routines(self) -> (CopyFn, CopyFn) {
+        match self {
+            CopyVariant::Portable => (portable_cpy, portable_cpy),
+            #[cfg(target_arch = "x86_64")]
+            CopyVariant::Sse2 => (sse2_cpy, sse2_ntcpy),
+            #[cfg(target_arch = "x86_64")]
+            CopyVariant::Avx => (avx_cpy, avx_ntcpy),
+            #[cfg(target_arch = "x86_64")]
+            CopyVariant::Avx2 => (avx2_cpy, avx2_ntcpy),
+            #[cfg(target_arch = "x86_64")]
+            CopyVariant::Avx512 => (avx512_cpy, avx512_ntcpy),
+            #[cfg(not(target_arch = "x86_64"))]
+            _ => (portable_cpy, portable_cpy),
+        }
+    }
 }
 
-#[cfg(all(target_feature = "avx2", not(target_feature = "avx512")))]
-pub extern "C" fn MPI_ntcpy(dest: *mut c_void, src: *const c_void, size: usize) {
-    avx2_ntcpy(dest, src, size);
-}
+use crate::debug_bkd;
 
-#[cfg(all(target_feature = "sse2", not(target_feature = "avx")))]
-pub extern "C" fn MPI_ntcpy(dest: *mut c_void, src: *const c_void, size: usize) {
-    sse2_ntcpy(dest, src, size);
+/// Transfers bypass the cache from this size on when the cache size is unknown.
+pub const NT_THRESHOLD: usize = 1 << 22;
+
+/// Half the last level cache, a smaller transfer fits in it together with its
+/// copy and is best left there for the receiver.
+#[cfg(target_env = "gnu")]
+pub fn nt_threshold() -> usize {
+    [libc::_SC_LEVEL3_CACHE_SIZE, libc::_SC_LEVEL2_CACHE_SIZE]
+        .into_iter()
+        .map(|name| unsafe { libc::sysconf(name) })
+        .find(|&size| size > 0)
+        .map_or(NT_THRESHOLD, |size| size as usize / 2)
 }
 
-#[cfg(all(target_feature = "sse2", not(target_feature = "avx")))]
-pub extern "C" fn MPI_cpy(dest: *mut c_void, src: *const c_void, size: usize) {
-    sse2_cpy(dest, src, size)
+/// Only glibc reports the cache sizes through `sysconf`.
+#[cfg(not(target_env = "gnu"))]
+pub fn nt_threshold() -> usize {
+    NT_THRESHOLD
 }
 
-#[cfg(all(target_feature = "avx", not(target_feature = "avx2")))]
-pub extern "C" fn MPI_cpy(dest: *mut c_void, src: *const c_void, size: usize) {
-    avx_cpy(dest, src, size)
+/// Copies `size` bytes of a transfer of `total` bytes, the stores bypass the
+/// cache if the whole transfer reaches the non-temporal threshold.
+pub fn memcpy_part(dest: *mut c_void, src: *const c_void, size: usize, total: usize) {
+    if size == 0 {
+        return;
+    }
+    if total >= Context::nt_threshold() {
+        debug_bkd!("memory", "Copy non-temporal {size} of {total} bytes");
+        MPI_ntcpy(dest, src, size);
+    } else {
+        debug_bkd!("memory", "Copy default {size} of {total} bytes");
+        MPI_cpy(dest, src, size);
+    }
 }
 
-#[cfg(all(target_feature = "avx2", not(target_feature = "avx512f")))]
-pub extern "C" fn MPI_cpy(dest: *mut c_void, src: *const c_void, size: usize) {
-    avx2_cpy(dest, src, size)
+pub fn memcpy(dest: *mut c_void, src: *const c_void, size: usize) {
+    memcpy_part(dest, src, size, size)
 }
 
-#[cfg(all(target_feature = "avx512f"))]
-pub extern "C" fn MPI_cpy(dest: *mut c_void, src: *const c_void, size: usize) {
-    avx512_cpy(dest, src, size)
+pub fn memcpy_slice<T>(dest: &mut [T], src: &[T], size: usize) {
+    memcpy(
+        dest.as_mut_ptr() as *mut c_void,
+        src.as_ptr() as *const c_void,
+        size,
+    )
 }
 
-#[cfg(all(
-    not(target_feature = "avx2"),
-    not(target_feature = "avx512"),
-    not(target_feature = "avx"),
-    not(target_feature = "sse2")
-))]
+#[no_mangle]
 pub extern "C" fn MPI_ntcpy(dest: *mut c_void, src: *const c_void, size: usize) {
-    unsafe { std::ptr::copy(src, dest, size) }
+    unsafe { (Context::ntcpy())(dest, src, size) }
+}
+
+#[no_mangle]
+pub extern "C" fn MPI_cpy(dest: *mut c_void, src: *const c_void, size: usize) {
+    unsafe { (Context::cpy())(dest, src, size) }
 }
 
 #[allow(unused_imports)]
@@ -890,40 +1027,81 @@ use std::{
 };
 
 macro_rules! test_cpy {
-    ($name:ident, $func:ident) => {
+    ($name:ident, $variant:expr, $func:ident) => {
         #[test]
         fn $name() {
+            if !$variant.is_supported() {
+                return;
+            }
             for size in [
-                15, 16, 17, 19, 3, 6, 20, 32, 63, 89, 105, 500, 512, 1024, 1500, 2123,
+                0, 15, 16, 17, 19, 3, 6, 20, 32, 63, 89, 105, 500, 512, 1024, 1500, 2123,
             ] {
-                let layout = Layout::from_size_align(size, 32).unwrap();
-                unsafe {
-                    let a = from_raw_parts_mut(alloc(layout) as *mut i32, size / 4);
-                    for (i, val) in a.iter_mut().enumerate() {
-                        *val = i as i32;
+                // Equally misaligned pointers go through the prologue, the
+                // others fall back to a plain copy.
+                for (src_off, dest_off) in [(0, 0), (1, 1), (5, 5), (3, 0)] {
+                    let len = size + 128;
+                    let layout = Layout::from_size_align(len, 64).unwrap();
+                    unsafe {
+                        let a = from_raw_parts_mut(alloc(layout), len);
+                        for (i, val) in a.iter_mut().enumerate() {
+                            *val = (i % 251) as u8 + 1;
+                        }
+                        let b = from_raw_parts_mut(alloc(layout), len);
+                        b.fill(0);
+                        $func(
+                            b[dest_off..].as_mut_ptr() as *mut c_void,
+                            a[src_off..].as_ptr() as *const c_void,
+                            size,
+                        );
+                        assert_eq!(
+                            b[dest_off..dest_off + size],
+                            a[src_off..src_off + size],
+                            "Size: {}, offsets: {} {}",
+                            size,
+                            src_off,
+                            dest_off
+                        );
+                        assert!(
+                            b[..dest_off]
+                                .iter()
+                                .chain(&b[dest_off + size..])
+                                .all(|v| *v == 0),
+                            "Size: {}, offsets: {} {}",
+                            size,
+                            src_off,
+                            dest_off
+                        );
+                        dealloc(a.as_mut_ptr(), layout);
+                        dealloc(b.as_mut_ptr(), layout);
                     }
-                    let b = from_raw_parts_mut(alloc(layout) as *mut i32, size / 4);
-                    $func(
-                        b.as_mut_ptr() as *mut c_void,
-                        a.as_ptr() as *const c_void,
-                        size,
-                    );
-                    for (i, val) in b.into_iter().enumerate() {
-                        assert_eq!(*val, i as i32, "Size: {}", size);
-                    }
-                    dealloc(a.as_mut_ptr() as *mut u8, layout);
-                    dealloc(b.as_mut_ptr() as *mut u8, layout);
                 }
             }
         }
     };
 }
 
-#[cfg(target_feature = "sse2")]
-test_cpy!(sse2cpy_test, sse2_ntcpy);
-#[cfg(target_feature = "avx")]
-test_cpy!(avxcpy_test, avx_ntcpy);
-#[cfg(target_feature = "avx2")]
-test_cpy!(avx2cpy_test, avx2_ntcpy);
-#[cfg(target_feature = "avx512")]
-test_cpy!(avx512cpy_test, avx512_ntcpy);
+test_cpy!(portablecpy_test, CopyVariant::Portable, portable_cpy);
+#[cfg(target_arch = "x86_64")]
+test_cpy!(sse2cpy_test, CopyVariant::Sse2, sse2_ntcpy);
+#[cfg(target_arch = "x86_64")]
+test_cpy!(avxcpy_test, CopyVariant::Avx, avx_ntcpy);
+#[cfg(target_arch = "x86_64")]
+test_cpy!(avx2cpy_test, CopyVariant::Avx2, avx2_ntcpy);
+#[cfg(target_arch = "x86_64")]
+test_cpy!(avx512cpy_test, CopyVariant::Avx512, avx512_ntcpy);
+#[cfg(target_arch = "x86_64")]
+test_cpy!(sse2cpy_cached_test, CopyVariant::Sse2, sse2_cpy);
+#[cfg(target_arch = "x86_64")]
+test_cpy!(avxcpy_cached_test, CopyVariant::Avx, avx_cpy);
+#[cfg(target_arch = "x86_64")]
+test_cpy!(avx2cpy_cached_test, CopyVariant::Avx2, avx2_cpy);
+#[cfg(target_arch = "x86_64")]
+test_cpy!(avx512cpy_cached_test, CopyVariant::Avx512, avx512_cpy);
+
+#[test]
+fn detect_test() {
+    let variant = CopyVariant::detect();
+    assert!(variant.is_supported());
+    assert_eq!(CopyVariant::parse("avx2"), Some(CopyVariant::Avx2));
+    assert_eq!(CopyVariant::parse("neon"), None);
+}
@@ -1,10 +1,39 @@
-use crate::{shared::*, xfer::request::Request};
+use crate::{communicator::group::CommGroup, shared::*, xfer::request::Request};
+use std::mem::size_of;
 use std::ptr::NonNull;
 
+const NIL: usize = usize::MAX;
+
+/// Default soft limit of outstanding entries in a queue.
+pub const QUEUE_LIMIT: usize = 1 << 16;
+
+/// Slot generations wrap around within these bits.
+pub const GEN_MASK: u32 = (1 << 30) - 1;
+
+#[derive(Clone, Copy)]
+struct Link {
+    prev: usize,
+    next: usize,
+    gen: u32,
+    used: bool,
+}
+
+struct Chunk<T, const N: usize> {
+    items: [T; N],
+    links: [Link; N],
+}
+
+/// Pool of entries that grows by chunks of `N`, so an entry never moves while it
+/// is in use. Entries are kept in insertion order. Chunks are never released,
+/// the generation of a slot has to outlive the handles taken from it.
 pub struct Queue<T, const N: usize> {
-    queue: [T; N],
-    flags: [bool; N],
+    chunks: Vec<Box<Chunk<T, N>>>,
+    /// Start address and number of every chunk, sorted by address.
+    index: Vec<(usize, usize)>,
+    init: T,
+    free: usize,
     size: usize,
+    limit: usize,
     head: usize,
     tail: usize,
 }
@@ -14,83 +43,169 @@ where
     T: Clone + Copy + Default,
 {
     pub fn new() -> Self {
-        Queue::<T, N> {
-            queue: [Default::default(); N],
-            flags: [false; N],
-            size: 0,
-            head: 0,
-            tail: 0,
-        }
+        Self::new_val(Default::default())
     }
 
     pub const fn new_val(val: T) -> Self {
         Queue::<T, N> {
-            queue: [val; N],
-            flags: [false; N],
+            chunks: Vec::new(),
+            index: Vec::new(),
+            init: val,
+            free: NIL,
             size: 0,
-            head: 0,
-            tail: 0,
+            limit: QUEUE_LIMIT,
+            head: NIL,
+            tail: NIL,
         }
     }
 
+    /// Sets the number of entries after which `push` fails.
+    pub fn set_limit(&mut self, limit: usize) {
+        self.limit = limit;
+    }
+
+    #[inline(always)]
+    fn item(&self, i: usize) -> &T {
+        &self.chunks[i / N].items[i % N]
+    }
+
+    #[inline(always)]
+    fn item_mut(&mut self, i: usize) -> &mut T {
+        &mut self.chunks[i / N].items[i % N]
+    }
+
+    #[inline(always)]
+    fn link(&mut self, i: usize) -> &mut Link {
+        &mut self.chunks[i / N].links[i % N]
+    }
+
+    #[inline(always)]
+    fn next(&self, i: usize) -> usize {
+        self.chunks[i / N].links[i % N].next
+    }
+
+    fn grow(&mut self) {
+        let base = self.chunks.len() * N;
+        let mut chunk = Box::new(Chunk {
+            items: [self.init; N],
+            links: [Link {
+                prev: NIL,
+                next: NIL,
+                gen: 0,
+                used: false,
+            }; N],
+        });
+        for (i, link) in chunk.links.iter_mut().enumerate() {
+            link.next = if i + 1 < N { base + i + 1 } else { self.free };
+        }
+        let start = chunk.items.as_ptr() as usize;
+        let pos = self.index.partition_point(|(addr, _)| *addr < start);
+        self.index.insert(pos, (start, self.chunks.len()));
+        self.chunks.push(chunk);
+        self.free = base;
+    }
+
     pub fn push(&mut self) -> Option<&mut T> {
-        if self.size == 0 {
-            self.head = 0;
-            self.tail = 1;
-            self.size += 1;
-            self.flags[0] = true;
-            return Some(&mut self.queue[0]);
-        } else if self.size < N {
-            let idx = self.tail;
-            self.tail = (self.tail + 1) % N;
-            self.flags[idx] = true;
-            self.size += 1;
-            return Some(&mut self.queue[idx]);
+        if self.size >= self.limit {
+            return None;
+        }
+        if self.free == NIL {
+            self.grow();
+        }
+
+        let idx = self.free;
+        self.free = self.next(idx);
+        let gen = self.link(idx).gen;
+        *self.link(idx) = Link {
+            prev: self.tail,
+            next: NIL,
+            gen,
+            used: true,
+        };
+        if self.tail == NIL {
+            self.head = idx;
+        } else {
+            let tail = self.tail;
+            self.link(tail).next = idx;
         }
+        self.tail = idx;
+        self.size += 1;
 
-        None
+        let init = self.init;
+        let item = self.item_mut(idx);
+        *item = init;
+        Some(item)
     }
 
-    pub fn erase(&mut self, mut i: usize) {
-        if self.size == 0 {
+    /// Erasing a slot that is not in use does nothing.
+    pub fn erase(&mut self, i: usize) {
+        if i >= self.chunks.len() * N || !self.link(i).used {
             return;
         }
 
-        if self.flags[i] {
-            self.flags[i] = false;
-
-            if i == self.head {
-                loop {
-                    i = (i + 1) % N;
-                    if self.flags[i] {
-                        self.head = i;
-                        break;
-                    }
-
-                    if i == self.tail {
-                        break;
-                    }
-                }
-            } else if i == self.tail {
-                loop {
-                    i = (N + i - 1) % N;
-                    if self.flags[i] {
-                        self.tail = i;
-                        break;
-                    }
-                    if i == self.head {
-                        self.tail = self.head;
-                        break;
-                    }
-                }
-            }
+        let Link {
+            prev, next, gen, ..
+        } = *self.link(i);
+        if prev == NIL {
+            self.head = next;
+        } else {
+            self.link(prev).next = next;
         }
+        if next == NIL {
+            self.tail = prev;
+        } else {
+            self.link(next).prev = prev;
+        }
+
+        *self.link(i) = Link {
+            prev: NIL,
+            next: self.free,
+            gen: (gen + 1) & GEN_MASK,
+            used: false,
+        };
+        self.free = i;
         self.size -= 1;
     }
 
+    /// Slot of `ptr`, which must point to the start of an entry.
+    fn index_of(&self, ptr: *const T) -> Option<usize> {
+        let addr = ptr as usize;
+        let pos = self.index.partition_point(|(start, _)| *start <= addr);
+        let (start, n) = self.index[pos.checked_sub(1)?];
+        let offset = addr - start;
+        if offset >= N * size_of::<T>() || !offset.is_multiple_of(size_of::<T>()) {
+            return None;
+        }
+        Some(n * N + offset / size_of::<T>())
+    }
+
+    /// Whether `ptr` points to an entry in use.
+    pub fn contains(&self, ptr: *const T) -> bool {
+        self.index_of(ptr)
+            .is_some_and(|i| self.chunks[i / N].links[i % N].used)
+    }
+
+    /// Slot index and generation of the entry at `ptr`, the generation changes
+    /// every time the slot is erased.
+    pub fn key(&self, ptr: *const T) -> Option<(usize, u32)> {
+        let i = self.index_of(ptr)?;
+        Some((i, self.chunks[i / N].links[i % N].gen))
+    }
+
+    /// The entry in slot `i` if it is in use and was not erased since `gen`
+    /// was taken.
+    pub fn get(&mut self, i: usize, gen: u32) -> Option<&mut T> {
+        if i >= self.chunks.len() * N || !self.link(i).used || self.link(i).gen != gen {
+            return None;
+        }
+        Some(self.item_mut(i))
+    }
+
     #[inline(always)]
     pub fn erase_ptr(&mut self, ptr: *const T) {
-        unsafe { self.erase(ptr.offset_from(self.queue.as_ptr()) as usize) }
+        if let Some(i) = self.index_of(ptr) {
+            self.erase(i);
+        }
     }
 
     #[inline(always)]
@@ -119,121 +234,77 @@ where
 
 pub struct Iter<'a, T, const N: usize> {
     q: &'a Queue<T, N>,
-    item: Option<&'a T>,
+    idx: usize,
 }
 
-impl<'a, T, const N: usize> Iterator for Iter<'a, T, N> {
+impl<'a, T, const N: usize> Iterator for Iter<'a, T, N>
+where
+    T: Copy + Clone + Default,
+{
     type Item = &'a T;
     fn next(&mut self) -> Option<Self::Item> {
-        if self.item.is_none() {
+        if self.idx == NIL {
             return None;
         }
 
-        let res = self.item;
-        if self.q.size > 1 {
-            let mut idx = unsafe {
-                (res.unwrap_unchecked() as *const T).offset_from(self.q.queue.as_ptr()) as usize
-            };
-            loop {
-                idx = (idx + 1) % N;
-                if self.q.tail == idx {
-                    self.item = None;
-                    break;
-                }
-
-                if self.q.flags[idx] {
-                    self.item = Some(&self.q.queue[idx]);
-                    break;
-                }
-            }
-        } else {
-            self.item = None;
-        }
-
-        res
+        let res = self.q.item(self.idx);
+        self.idx = self.q.next(self.idx);
+        Some(res)
     }
 }
 
 pub struct IterMut<'a, T, const N: usize> {
     pq: NonNull<Queue<T, N>>,
-    item: Option<&'a mut T>,
+    idx: usize,
+    _q: std::marker::PhantomData<&'a mut Queue<T, N>>,
 }
 
 impl<'a, T, const N: usize> Iterator for IterMut<'a, T, N>
 where
-    T: Copy,
+    T: Copy + Clone + Default,
 {
     type Item = &'a mut T;
     fn next(&mut self) -> Option<Self::Item> {
-        if self.item.is_none() {
+        if self.idx == NIL {
             return None;
         }
 
-        let res = unsafe { *self.item.as_mut().unwrap_unchecked() as *mut T };
+        // The next index is taken before the item is handed out, so the item
+        // may be erased while iterating.
         let q = unsafe { self.pq.as_mut() };
-        if q.size > 1 {
-            let mut idx = unsafe { res.offset_from(q.queue.as_ptr()) as usize };
-
-            loop {
-                idx = (idx + 1) % N;
-                if q.head == idx {
-                    self.item = None;
-                    break;
-                }
-
-                if q.flags[idx] {
-                    self.item = Some(&mut q.queue[idx]);
-                    break;
-                }
-            }
-        } else {
-            self.item = None;
-        }
+        let res = q.item_mut(self.idx) as *mut T;
+        self.idx = q.next(self.idx);
 
-        return unsafe { Some(&mut *res) };
+        unsafe { Some(&mut *res) }
     }
 }
 
 impl<'a, T, const N: usize> IterMut<'a, T, N> {
     fn new(pq: *mut Queue<T, N>) -> Self {
-        let first;
-        let q = unsafe { &mut *pq };
-
-        if q.size > 0 {
-            first = Some(&mut q.queue[q.head]);
-        } else {
-            first = None;
-        }
-
         IterMut {
             pq: unsafe { NonNull::new_unchecked(pq) },
-            item: first,
+            idx: unsafe { (*pq).head },
+            _q: std::marker::PhantomData,
         }
     }
 }
 
 impl<'a, T, const N: usize> Iter<'a, T, N> {
     fn new(q: &'a Queue<T, N>) -> Self {
-        let first;
-        if q.size > 0 {
-            first = Some(&q.queue[q.head]);
-        } else {
-            first = None;
-        }
-        Iter { q, item: first }
+        Iter { q, idx: q.head }
     }
 }
 
 pub type RequestQueue = Queue<Request, 16>;
 
 impl RequestQueue {
+    /// Messages that are still being received are skipped.
     pub fn find_by_tag(&mut self, rank: i32, tag: i32) -> Option<&mut Request> {
-        self.iter_mut().find(|x| x.rank == rank && x.tag == tag)
-    }
-
-    #[inline(always)]
-    pub fn contains(&self, req: MPI_Request) -> bool {
-        return self.iter().find(|&x| x as *const Request == req).is_some();
+        self.iter_mut().find(|x| {
+            x.flag != 0
+                && (rank == MPI_ANY_SOURCE || x.rank == rank)
+                && CommGroup::tag_match(tag, x.tag)
+        })
     }
 
     pub const fn new_c() -> Self {
@@ -259,7 +330,8 @@ fn test_queue() {
 
 #[test]
 fn test_queue_fill() {
-    let mut q = Queue::<u8, 5>::new();
+    let mut q = Queue::<u8, 2>::new();
+    q.set_limit(5);
     for val in [10, 16, 100, 11, 12] {
         *q.push().unwrap() = val;
     }
@@ -271,3 +343,89 @@ fn test_queue_fill() {
     }
     assert!(iter.next().is_none());
 }
+
+#[test]
+fn test_queue_grow() {
+    let mut q = Queue::<u32, 4>::new();
+    let ptrs: Vec<*const u32> = (0..40)
+        .map(|val| {
+            let item = q.push().unwrap();
+            *item = val;
+            item as *const u32
+        })
+        .collect();
+    assert_eq!(q.len(), 40);
+
+    for &ptr in ptrs.iter().step_by(3) {
+        q.erase_ptr(ptr);
+    }
+    assert!(q.iter().copied().eq((0..40).filter(|val| val % 3 != 0)));
+    assert!(ptrs
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| i % 3 != 0)
+        .all(|(i, &ptr)| unsafe { *ptr } == i as u32));
+
+    *q.push().unwrap() = 100;
+    assert_eq!(q.iter().last(), Some(&100));
+}
+
+#[test]
+fn test_queue_key() {
+    let mut q = Queue::<u32, 4>::new();
+    let ptr = q.push().unwrap() as *const u32;
+    let (i, gen) = q.key(ptr).unwrap();
+    assert!(q.get(i, gen).is_some());
+
+    q.erase_ptr(ptr);
+    assert!(q.get(i, gen).is_none());
+    assert_eq!(q.push().unwrap() as *const u32, ptr);
+    assert!(q.get(i, gen).is_none());
+    assert!(q.get(i, gen + 1).is_some());
+    assert!(q.get(100, 0).is_none());
+    // Slots of the chunk that were never pushed are not entries yet.
+    assert!(q.get(i + 1, 0).is_none());
+}
+
+#[test]
+fn test_queue_double_erase() {
+    let mut q = Queue::<u32, 4>::new();
+    let ptrs: Vec<*const u32> = (0..3)
+        .map(|val| {
+            let item = q.push().unwrap();
+            *item = val;
+            item as *const u32
+        })
+        .collect();
+
+    q.erase_ptr(ptrs[1]);
+    q.erase_ptr(ptrs[1]);
+    q.erase(3);
+    assert_eq!(q.len(), 2);
+    assert!(q.iter().copied().eq([0, 2]));
+
+    // The slot is handed out once, not twice from a corrupted free list.
+    let a = q.push().unwrap() as *const u32;
+    let b = q.push().unwrap() as *const u32;
+    assert_ne!(a, b);
+    assert_eq!(q.len(), 4);
+    assert!(q.contains(a) && q.contains(b));
+}
+
+#[test]
+fn test_queue_contains() {
+    let mut q = Queue::<u32, 4>::new();
+    let ptrs: Vec<*const u32> = (0..10).map(|_| q.push().unwrap() as *const u32).collect();
+    q.erase_ptr(ptrs[0]);
+    q.erase_ptr(ptrs[5]);
+    q.erase_ptr(ptrs[9]);
+    for (i, &ptr) in ptrs.iter().enumerate() {
+        assert_eq!(q.contains(ptr), ![0, 5, 9].contains(&i));
+    }
+    let other = 0u32;
+    assert!(!q.contains(&other));
+    let inside = (ptrs[1] as *const u8).wrapping_add(1) as *const u32;
+    assert!(!q.contains(inside));
+    q.erase_ptr(inside);
+    assert_eq!(q.len(), 7);
+}
@@ -0,0 +1,250 @@
+use crate::{debug_bkd, shared::*};
+use std::{
+    ffi::CString,
+    mem::MaybeUninit,
+    ptr::null_mut,
+    sync::atomic::{AtomicI32, AtomicPtr, Ordering},
+    time::Duration,
+};
+
+macro_rules! debug_seg {
+    ($fmt:literal) => {
+        debug_bkd!("segment", $fmt);
+    };
+    ($($args:tt)*) => {
+        debug_bkd!("segment", $($args)*);
+    }
+}
+
+/// Header at the start of every segment, the shared state follows it.
+#[repr(C, align(64))]
+pub struct Segment {
+    owner: AtomicI32, // pid of rank 0 once it created the segment
+    attached: AtomicI32,
+    size: i32,
+}
+
+impl Segment {
+    /// The memory right after the header.
+    #[inline(always)]
+    pub fn data(seg: *mut Segment) -> *mut u8 {
+        unsafe { seg.add(1) as *mut u8 }
+    }
+}
+
+/// Pause between attempts of a rank waiting for rank 0 to create the segment.
+const ATTACH_POLL: Duration = Duration::from_millis(1);
+
+const SIGNALS: [i32; 7] = [
+    libc::SIGHUP,
+    libc::SIGINT,
+    libc::SIGQUIT,
+    libc::SIGABRT,
+    libc::SIGBUS,
+    libc::SIGSEGV,
+    libc::SIGTERM,
+];
+
+/// The named segment until every rank attached to it, the signal handlers need
+/// it without going through the context.
+static NAMED: AtomicPtr<Segment> = AtomicPtr::new(null_mut());
+static mut NAME: [u8; 64] = [0; 64];
+static mut OLD_ACTIONS: [MaybeUninit<libc::sigaction>; SIGNALS.len()] =
+    [MaybeUninit::uninit(); SIGNALS.len()];
+
+/// Maps `len` bytes shared with the ranks forked later.
+pub fn create(len: usize) -> *mut Segment {
+    map(-1, len)
+}
+
+/// Maps the segment of job `key`. Rank 0 creates it, the others wait until it
+/// exists and its creator is alive. The name goes away once all ranks attached,
+/// so a job that dies later leaves nothing behind. Steps of a job may run at
+/// the same time, each of them gets a segment of its own.
+pub fn open(key: i32, len: usize) -> *mut Segment {
+    let step = std::env::var("SLURM_STEP_ID").unwrap_or(String::from("-1"));
+    let uid = unsafe { libc::getuid() };
+    let name = CString::new(format!("/mympi-{uid}-{key}.{step}")).unwrap();
+    debug_seg!("Attach to {name:?}, {len} bytes");
+
+    let seg = if Context::rank() == 0 {
+        create_named(&name, len)
+    } else {
+        loop {
+            let fd = unsafe { libc::shm_open(name.as_ptr(), libc::O_RDWR, 0) };
+            if fd == -1 {
+                if std::io::Error::last_os_error().raw_os_error() != Some(libc::ENOENT) {
+                    return null_mut();
+                }
+                std::thread::sleep(ATTACH_POLL);
+                continue;
+            }
+            let seg = join(fd, len);
+            unsafe { libc::close(fd) };
+            if !seg.is_null() {
+                break seg;
+            }
+            std::thread::sleep(ATTACH_POLL);
+        }
+    };
+    if seg.is_null() {
+        return null_mut();
+    }
+
+    unsafe {
+        let bytes = name.as_bytes_with_nul();
+        NAME[..bytes.len()].copy_from_slice(bytes);
+    }
+    NAMED.store(seg, Ordering::SeqCst);
+    install_handlers();
+
+    let seg_ref = unsafe { &*seg };
+    if seg_ref.attached.fetch_add(1, Ordering::SeqCst) + 1 == seg_ref.size {
+        debug_seg!("All ranks attached, unlink {name:?}");
+        NAMED.store(null_mut(), Ordering::SeqCst);
+        unsafe { libc::shm_unlink(name.as_ptr()) };
+    }
+    seg
+}
+
+/// Unmaps a segment, its name is removed if some rank never attached.
+pub fn release(seg: *mut Segment, len: usize) {
+    if NAMED.load(Ordering::SeqCst) == seg {
+        unlink();
+    }
+    restore_handlers();
+    unsafe { libc::munmap(seg as *mut c_void, len) };
+}
+
+/// Puts back the signal handlers of the application once the name is gone,
+/// that is after all ranks went through a barrier.
+pub fn restore_handlers() {
+    unsafe {
+        if NAME[0] == 0 {
+            return;
+        }
+        for (sig, old) in SIGNALS.iter().zip(OLD_ACTIONS.iter()) {
+            libc::sigaction(*sig, old.as_ptr(), null_mut());
+        }
+        NAME[0] = 0;
+    }
+    NAMED.store(null_mut(), Ordering::SeqCst);
+}
+
+fn map(fd: i32, len: usize) -> *mut Segment {
+    let flags = if fd == -1 {
+        libc::MAP_ANONYMOUS | libc::MAP_SHARED
+    } else {
+        libc::MAP_SHARED
+    };
+    let seg = unsafe {
+        libc::mmap(
+            null_mut(),
+            len,
+            libc::PROT_READ | libc::PROT_WRITE,
+            flags,
+            fd,
+            0,
+        )
+    };
+    if seg == libc::MAP_FAILED {
+        return null_mut();
+    }
+    seg as *mut Segment
+}
+
+fn create_named(name: &CString, len: usize) -> *mut Segment {
+    unsafe {
+        // Left behind by a job with the same key that died while attaching.
+        if libc::shm_unlink(name.as_ptr()) == 0 {
+            debug_seg!("Removed stale segment {name:?}");
+        }
+        let fd = libc::shm_open(
+            name.as_ptr(),
+            libc::O_CREAT | libc::O_EXCL | libc::O_RDWR,
+            0o600,
+        );
+        if fd == -1 {
+            return null_mut();
+        }
+        let seg = if libc::ftruncate(fd, len as libc::off_t) == 0 {
+            map(fd, len)
+        } else {
+            null_mut()
+        };
+        libc::close(fd);
+        if seg.is_null() {
+            libc::shm_unlink(name.as_ptr());
+            return null_mut();
+        }
+        (*seg).size = Context::size();
+        (*seg).owner.store(libc::getpid(), Ordering::SeqCst);
+        seg
+    }
+}
+
+/// Maps the segment behind `fd` if it is the one of the running job. A segment
+/// of a dead job either belongs to a process that is gone or was unlinked by
+/// rank 0 before it created the new one.
+fn join(fd: i32, len: usize) -> *mut Segment {
+    let mut st = MaybeUninit::<libc::stat>::uninit();
+    let stat = |st: &mut MaybeUninit<libc::stat>| unsafe {
+        libc::fstat(fd, st.as_mut_ptr());
+        st.assume_init()
+    };
+    if stat(&mut st).st_size as usize != len {
+        return null_mut();
+    }
+
+    let seg = map(fd, len);
+    if seg.is_null() {
+        return null_mut();
+    }
+    let owner = loop {
+        match unsafe { (*seg).owner.load(Ordering::SeqCst) } {
+            0 if stat(&mut st).st_nlink == 0 => break 0,
+            0 => std::thread::sleep(ATTACH_POLL),
+            pid => break pid,
+        }
+    };
+    let alive = owner != 0
+        && (unsafe { libc::kill(owner, 0) } == 0
+            || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM));
+    if !alive {
+        debug_seg!("Skip stale segment of {owner}");
+        unsafe { libc::munmap(seg as *mut c_void, len) };
+        return null_mut();
+    }
+    seg
+}
+
+/// Removes the name of the segment, only async-signal-safe calls in here.
+fn unlink() {
+    let seg = NAMED.swap(null_mut(), Ordering::SeqCst);
+    if seg.is_null() {
+        return;
+    }
+    let seg = unsafe { &*seg };
+    if seg.attached.load(Ordering::SeqCst) < seg.size {
+        unsafe { libc::shm_unlink(NAME.as_ptr() as *const i8) };
+    }
+}
+
+extern "C" fn cleanup_handler(sig: i32) {
+    unlink();
+    // The handler was reset to the default action, which takes over now.
+    unsafe { libc::raise(sig) };
+}
+
+fn install_handlers() {
+    unsafe {
+        let mut act: libc::sigaction = std::mem::zeroed();
+        act.sa_sigaction = cleanup_handler as extern "C" fn(i32) as libc::sighandler_t;
+        act.sa_flags = libc::SA_RESETHAND;
+        libc::sigemptyset(&mut act.sa_mask);
+        for (sig, old) in SIGNALS.iter().zip(OLD_ACTIONS.iter_mut()) {
+            libc::sigaction(*sig, &act, old.as_mut_ptr());
+        }
+    }
+    debug_seg!("Installed cleanup handlers");
+}
@@ -2,9 +2,22 @@
 
 use libc::SYS_request_key;
 
-use super::memory::memcpy;
-use crate::{debug_bkd, debug_xfer, shared::*, xfer::request::Request};
-use std::{mem::size_of, ptr::null_mut, sync::atomic::AtomicI8};
+use super::memory::memcpy_part;
+use super::reqqueue::GEN_MASK;
+use super::segment::{self, Segment};
+use super::wait::{futex_wait, futex_wake, Backoff};
+use crate::{
+    communicator::group::CommGroup,
+    debug_bkd, debug_xfer,
+    metatypes::engine,
+    shared::*,
+    xfer::request::{Cursor, Request, SendMode},
+};
+use std::{
+    mem::size_of,
+    ptr::null_mut,
+    sync::atomic::{AtomicI32, AtomicI8, AtomicU32, Ordering},
+};
 
 macro_rules! debug_shm {
     ($fmt:literal) => {
@@ -15,25 +28,31 @@ macro_rules! debug_shm {
     }
 }
 
+// Layout of a request handle: the slot index plus one in the low bits, the
+// queue of the slot and the slot generation above it. Generations fit in
+// `GEN_MASK`, so a handle never equals `MPI_MESSAGE_NO_PROC`.
+const HANDLE_IDX_MASK: usize = (1 << 32) - 1;
+const HANDLE_SEND: usize = 1 << 32;
+const HANDLE_GEN_SHIFT: u32 = 33;
+
 #[repr(C)]
 struct Cell {
-    pub len: i32,                               // 4
-    pub tag: i32,                               // 8
-    pub m_flag: std::sync::atomic::AtomicUsize, // 16
-    pub coll_flag: AtomicI8,                    // 17
-    pub pad: [i8; 15],                          // 32
-    pub buff: [i8; 8160],                       // 8192
+    pub len: i32,            // 4
+    pub tag: i32,            // 8
+    pub m_flag: AtomicU32,   // 12
+    pub next: AtomicU32,     // 16
+    pub coll_flag: AtomicI8, // 17
+    pub rndv: i8,            // 18
+    pub pad0: [i8; 2],       // 20
+    pub sync: u32,           // 24
+    pub addr: usize,         // 32
 }
 
 impl Cell {
-    pub const fn buf_len() -> usize {
-        let m = null_mut() as *const Self;
-        let p = unsafe { core::ptr::addr_of!((*m).buff) };
-
-        const fn size_of_raw<T>(_: *const T) -> usize {
-            core::mem::size_of::<T>()
-        }
-        size_of_raw(p)
+    /// The payload follows the header, its length is the same for all cells.
+    #[inline(always)]
+    pub fn buff(&mut self) -> *mut c_void {
+        unsafe { (self as *mut Self).add(1) as *mut c_void }
     }
 
     pub fn set_coll_flag(&self, val: i8) {
@@ -42,42 +61,204 @@ impl Cell {
     }
 
     #[inline(always)]
-    pub fn flag(&self) -> usize {
-        self.m_flag.load(std::sync::atomic::Ordering::SeqCst)
+    pub fn set_flag(&mut self, val: u32) {
+        self.m_flag.store(val, std::sync::atomic::Ordering::SeqCst);
+    }
+}
+
+const SYNC_SLOTS: usize = 16;
+
+/// Cells a pair can have in flight, the cells themselves come from the pool of
+/// the sender.
+const RING_SLOTS: usize = 16;
+
+/// Cells in the pool of every rank unless configured otherwise, at most
+/// `CELL_COUNT_MAX`.
+pub const CELL_COUNT: usize = 64;
+pub const CELL_COUNT_MAX: usize = 1 << 20;
+/// Payload bytes of a cell unless configured otherwise, a cell is 8 KiB with
+/// its header. Payloads are rounded to `CELL_ALIGN` so every cell stays aligned.
+pub const CELL_LEN: usize = 8160;
+const CELL_ALIGN: usize = 32;
+
+/// Messages from this size on are pulled by the receiver with cross memory
+/// attach instead of being copied through the cells.
+pub const CMA_THRESHOLD: usize = 1 << 16;
+
+/// Value at the address every rank publishes, a peer that can read it back is
+/// allowed to pull messages from this rank.
+static CMA_PROBE: u64 = 0x4d50_495f_434d_4121;
+
+/// Reads `len` bytes at `addr` in process `pid` into `buf`.
+fn cma_read(pid: i32, addr: usize, buf: *mut c_void, len: usize) -> bool {
+    let mut done = 0;
+    while done < len {
+        let local = libc::iovec {
+            iov_base: unsafe { buf.add(done) },
+            iov_len: len - done,
+        };
+        let remote = libc::iovec {
+            iov_base: (addr + done) as *mut c_void,
+            iov_len: len - done,
+        };
+        let res = unsafe { libc::process_vm_readv(pid, &local, 1, &remote, 1, 0) };
+        if res <= 0 {
+            return false;
+        }
+        done += res as usize;
+    }
+    true
+}
+
+/// Free list of the cells a rank sends from. Only the owner takes cells off the
+/// list, so popping can't suffer from ABA; receivers push back what they consumed.
+/// Peers also ring the doorbell of the owner here when they have news for it.
+#[repr(C, align(64))]
+struct Pool {
+    head: AtomicU32, // index plus one of the first free cell, 0 when empty
+    free: AtomicU32,
+    bell: AtomicU32,
+    sleeping: AtomicU32,
+}
+
+/// The cells of all ranks, `ncells` per rank laid out one pool after another.
+#[derive(Clone, Copy)]
+struct Cells {
+    pools: *mut Pool,
+    base: *mut u8,
+    ncells: usize,
+    cell_len: usize,
+}
+
+impl Cells {
+    const fn new() -> Cells {
+        Cells {
+            pools: null_mut(),
+            base: null_mut(),
+            ncells: 0,
+            cell_len: 0,
+        }
+    }
+
+    #[inline(always)]
+    fn stride(cell_len: usize) -> usize {
+        size_of::<Cell>() + cell_len
     }
 
     #[inline(always)]
-    pub fn set_flag(&mut self, val: usize) {
-        self.m_flag.store(val, std::sync::atomic::Ordering::SeqCst);
+    #[allow(clippy::mut_from_ref)]
+    fn cell(&self, idx: u32) -> &mut Cell {
+        let stride = Self::stride(self.cell_len);
+        unsafe { &mut *(self.base.add(idx as usize * stride) as *mut Cell) }
+    }
+
+    #[inline(always)]
+    fn pool(&self, rank: i32) -> &Pool {
+        unsafe { &*self.pools.add(rank as usize) }
+    }
+
+    /// Puts all cells of `rank` on its free list.
+    fn init(&self, rank: i32) {
+        let first = (rank as usize * self.ncells) as u32;
+        for idx in first..first + self.ncells as u32 {
+            let next = if idx + 1 == first + self.ncells as u32 {
+                0
+            } else {
+                idx + 2
+            };
+            self.cell(idx).next.store(next, Ordering::SeqCst);
+        }
+        let pool = self.pool(rank);
+        pool.head.store(first + 1, Ordering::SeqCst);
+        pool.free.store(self.ncells as u32, Ordering::SeqCst);
+    }
+
+    /// Takes a free cell of `rank`, which must be the calling rank.
+    #[inline(always)]
+    fn alloc(&self, rank: i32) -> Option<u32> {
+        let pool = self.pool(rank);
+        let mut head = pool.head.load(Ordering::SeqCst);
+        loop {
+            if head == 0 {
+                return None;
+            }
+            let next = self.cell(head - 1).next.load(Ordering::SeqCst);
+            match pool
+                .head
+                .compare_exchange_weak(head, next, Ordering::SeqCst, Ordering::SeqCst)
+            {
+                Ok(_) => break,
+                Err(cur) => head = cur,
+            }
+        }
+        pool.free.fetch_sub(1, Ordering::SeqCst);
+        Some(head - 1)
     }
 
+    /// Returns a consumed cell to the pool it came from.
     #[inline(always)]
-    pub fn dec_flag(&mut self) {
-        self.m_flag
-            .fetch_sub(1, std::sync::atomic::Ordering::SeqCst);
+    fn free(&self, idx: u32) {
+        let pool = self.pool((idx as usize / self.ncells) as i32);
+        let mut head = pool.head.load(Ordering::SeqCst);
+        loop {
+            self.cell(idx).next.store(head, Ordering::SeqCst);
+            match pool
+                .head
+                .compare_exchange_weak(head, idx + 1, Ordering::SeqCst, Ordering::SeqCst)
+            {
+                Ok(_) => break,
+                Err(cur) => head = cur,
+            }
+        }
+        pool.free.fetch_add(1, Ordering::SeqCst);
+        // The owner may wait for a cell or for a message to be consumed.
+        self.ring((idx as usize / self.ncells) as i32);
     }
 
+    /// Tells `rank` to poll again, waking it if it sleeps.
     #[inline(always)]
-    pub fn wait_eq(&self, target: usize) {
-        while self.flag() != target {
-            continue;
+    fn ring(&self, rank: i32) {
+        let pool = self.pool(rank);
+        pool.bell.fetch_add(1, Ordering::SeqCst);
+        if pool.sleeping.load(Ordering::SeqCst) != 0 {
+            futex_wake(&pool.bell);
         }
     }
 
     #[inline(always)]
-    pub fn wait_ne(&self, target: usize) {
-        while self.flag() == target {
-            continue;
+    fn bell(&self, rank: i32) -> u32 {
+        self.pool(rank).bell.load(Ordering::SeqCst)
+    }
+
+    /// Sleeps unless the doorbell of `rank` changed since it read `seen`.
+    fn sleep(&self, rank: i32, seen: u32) {
+        let pool = self.pool(rank);
+        pool.sleeping.store(1, Ordering::SeqCst);
+        if pool.bell.load(Ordering::SeqCst) == seen {
+            futex_wait(&pool.bell, seen);
         }
+        pool.sleeping.store(0, Ordering::SeqCst);
+    }
+
+    /// Whether `rank` is about to run out of cells. Its messages are then taken
+    /// out of the cells even if nobody waits for them yet.
+    #[inline(always)]
+    fn is_low(&self, rank: i32) -> bool {
+        self.pool(rank).free.load(Ordering::SeqCst) as usize * 4 < self.ncells
     }
 }
 
 #[repr(C)]
 struct MpiShm {
-    nsend: AtomicI8, // 1
-    nrecv: AtomicI8, // 2
-    pad: [i8; 30],   // 32
-    cells: [Cell; 16],
+    nsend: AtomicI8,                // 1
+    nrecv: AtomicI8,                // 2
+    pad0: [i8; 2],                  // 4
+    nsync: AtomicU32,               // 8
+    acks: [AtomicU32; SYNC_SLOTS],  // 72
+    pid: i32,                       // 76
+    cma: AtomicI32,                 // 80
+    probe: usize,                   // 88
+    slots: [AtomicU32; RING_SLOTS], // 152
 }
 
 impl MpiShm {
@@ -85,7 +266,7 @@ impl MpiShm {
     pub fn swapSend(&mut self) {
         let idx = self.nsend.load(std::sync::atomic::Ordering::SeqCst);
         self.nsend.store(
-            (idx + 1) % self.cells.len() as i8,
+            (idx + 1) % RING_SLOTS as i8,
             std::sync::atomic::Ordering::SeqCst,
         );
     }
@@ -94,86 +275,239 @@ impl MpiShm {
     pub fn swapRecv(&mut self) {
         let idx = self.nrecv.load(std::sync::atomic::Ordering::SeqCst);
         self.nrecv.store(
-            (idx + 1) % self.cells.len() as i8,
+            (idx + 1) % RING_SLOTS as i8,
             std::sync::atomic::Ordering::SeqCst,
         );
     }
 
+    /// The cell published in slot `pos` of the ring, if any.
+    #[inline(always)]
+    pub fn cell<'a>(&self, cells: &'a Cells, pos: usize) -> Option<&'a mut Cell> {
+        match self.slots[pos].load(Ordering::SeqCst) {
+            0 => None,
+            idx => Some(cells.cell(idx - 1)),
+        }
+    }
+
+    /// Hands the cell `idx` over to the receiver.
+    #[inline(always)]
+    pub fn publish(&mut self, idx: u32) {
+        let pos = self.nsend.load(Ordering::SeqCst) as usize;
+        self.slots[pos].store(idx + 1, Ordering::SeqCst);
+        self.swapSend();
+    }
+
+    /// Gives the head cell back to the sender and moves on to the next one.
     #[inline(always)]
-    pub fn coll_wait_and_swap(&mut self) {
+    pub fn release(&mut self, cells: &Cells) {
+        let pos = self.nrecv.load(Ordering::SeqCst) as usize;
+        let idx = self.slots[pos].load(Ordering::SeqCst) - 1;
+        // Freeing rings the sender, which must see the slot empty by then.
+        self.slots[pos].store(0, Ordering::SeqCst);
+        cells.free(idx);
+        self.swapRecv();
+    }
+
+    /// Waits until every reader of the collective cell in slot `pos` copied it,
+    /// the last one frees the cell. Readers ring each other as they go, so they
+    /// can wait according to the wait policy.
+    #[inline(always)]
+    pub fn coll_wait_and_swap(&mut self, cells: &Cells, pos: usize, req: &Request) {
         debug_shm!("Coll dec flag");
-        let cell = self.recv_cell() as *mut Cell;
-        let flag = unsafe { &mut (*cell).m_flag };
-        let coll_flag = unsafe { &mut (*cell).coll_flag };
+        let idx = self.slots[pos].load(Ordering::SeqCst) - 1;
+        let cell = cells.cell(idx);
+        let flag = &cell.m_flag;
+        let coll_flag = &cell.coll_flag;
         debug_shm!(
-            "Flag: {}, value: {}, coll_flag: {}",
-            flag as *mut _ as usize,
+            "Cell: {idx}, value: {}, coll_flag: {}",
             flag.load(std::sync::atomic::Ordering::SeqCst),
             coll_flag.load(std::sync::atomic::Ordering::SeqCst)
         );
+        let mut backoff = Backoff::new();
         if coll_flag.fetch_sub(1, std::sync::atomic::Ordering::SeqCst) == 1 {
             self.swapRecv();
             coll_flag.store(-1, std::sync::atomic::Ordering::SeqCst);
+            ShmData::notify(cells, req);
             while flag.load(std::sync::atomic::Ordering::SeqCst) != 1 {
-                continue;
+                backoff.snooze();
             }
             flag.store(0, std::sync::atomic::Ordering::SeqCst);
+            // All other readers are done with the cell.
+            self.slots[pos].store(0, Ordering::SeqCst);
+            cells.free(idx);
         } else {
             while coll_flag.load(std::sync::atomic::Ordering::SeqCst) != -1 {
-                continue;
+                backoff.snooze();
             }
             flag.fetch_sub(1, std::sync::atomic::Ordering::SeqCst);
+            ShmData::notify(cells, req);
         }
-        debug_shm!(
-            "End coll_wait_and_swap, value: {}, coll_flag: {}",
-            flag.load(std::sync::atomic::Ordering::SeqCst),
-            coll_flag.load(std::sync::atomic::Ordering::SeqCst)
-        );
+        debug_shm!("End coll_wait_and_swap");
+    }
+
+    /// Reserves the sequence number of the next synchronous message, fails while
+    /// the acknowledgement slot is still held by an unmatched older message.
+    #[inline(always)]
+    pub fn next_sync(&mut self) -> Option<u32> {
+        let seq = self.nsync.load(Ordering::SeqCst) + 1;
+        let prev = seq.saturating_sub(SYNC_SLOTS as u32);
+        if self.acks[seq as usize % SYNC_SLOTS].load(Ordering::SeqCst) != prev {
+            return None;
+        }
+        self.nsync.store(seq, Ordering::SeqCst);
+        Some(seq)
+    }
+
+    #[inline(always)]
+    pub fn ack(&self, seq: u32) {
+        self.acks[seq as usize % SYNC_SLOTS].store(seq, Ordering::SeqCst);
     }
 
     #[inline(always)]
-    pub fn recv_cell(&mut self) -> &mut Cell {
-        &mut self.cells[self.nrecv.load(std::sync::atomic::Ordering::SeqCst) as usize]
+    pub fn is_acked(&self, seq: u32) -> bool {
+        self.acks[seq as usize % SYNC_SLOTS].load(Ordering::SeqCst) == seq
     }
 
     #[inline(always)]
-    pub fn send_cell(&mut self) -> &mut Cell {
-        &mut self.cells[self.nsend.load(std::sync::atomic::Ordering::SeqCst) as usize]
+    pub fn recv_cell<'a>(&self, cells: &'a Cells) -> Option<&'a mut Cell> {
+        self.cell(
+            cells,
+            self.nrecv.load(std::sync::atomic::Ordering::SeqCst) as usize,
+        )
+    }
+
+    /// Whether the ring has room for another cell.
+    #[inline(always)]
+    pub fn can_send(&self) -> bool {
+        let pos = self.nsend.load(std::sync::atomic::Ordering::SeqCst) as usize;
+        self.slots[pos].load(Ordering::SeqCst) == 0
     }
 }
 
 pub struct ShmData {
     d: *mut MpiShm,
-    shm_key: i32,
+    seg: *mut Segment,
     recv_queue: RequestQueue,
     send_queue: RequestQueue,
     unexp_queue: RequestQueue,
+    msg_queue: RequestQueue,
+    nloop: u32,
+    /// Requests freed by the user that are still pending.
+    nfreed: usize,
+    /// Ranks a send is still being written to, one entry per rank.
+    busy: Vec<bool>,
+    /// Completed requests the progress engine releases, kept to reuse its space.
+    done: Vec<*const Request>,
+    cma_threshold: usize,
+    ncells: usize,
+    cell_len: usize,
+    cells: Cells,
 }
 
 impl ShmData {
-    fn find_queue(&mut self, req: MPI_Request) -> &mut RequestQueue {
+    fn find_queue(&mut self, req: *const Request) -> Result<&mut RequestQueue, MpiError> {
         if self.recv_queue.contains(req) {
-            return &mut self.recv_queue;
+            return Ok(&mut self.recv_queue);
         }
         if self.send_queue.contains(req) {
-            return &mut self.send_queue;
+            return Ok(&mut self.send_queue);
         }
         if self.unexp_queue.contains(req) {
-            return &mut self.unexp_queue;
+            return Ok(&mut self.unexp_queue);
+        }
+        if self.msg_queue.contains(req) {
+            return Ok(&mut self.msg_queue);
         }
-        unreachable!();
+        Err(MPI_ERR_REQUEST)
     }
 
     pub const fn new() -> ShmData {
         ShmData {
             d: null_mut(),
-            shm_key: -1,
+            seg: null_mut(),
             recv_queue: RequestQueue::new_c(),
             send_queue: RequestQueue::new_c(),
             unexp_queue: RequestQueue::new_c(),
+            msg_queue: RequestQueue::new_c(),
+            nloop: 0,
+            nfreed: 0,
+            busy: Vec::new(),
+            done: Vec::new(),
+            cma_threshold: CMA_THRESHOLD,
+            ncells: CELL_COUNT,
+            cell_len: CELL_LEN,
+            cells: Cells::new(),
+        }
+    }
+
+    /// Messages of at least `threshold` bytes use the rendezvous protocol, zero
+    /// disables it.
+    pub fn set_cma_threshold(&mut self, threshold: usize) {
+        self.cma_threshold = threshold;
+    }
+
+    /// Publishes the pid of this rank and the address of the probe value in
+    /// every pair it sends through.
+    pub fn publish_cma(&mut self) {
+        let pid = unsafe { libc::getpid() };
+        for dst in 0..Context::size() {
+            let pshm = self.pair(Context::rank(), dst);
+            pshm.pid = pid;
+            pshm.probe = &CMA_PROBE as *const u64 as usize;
+            pshm.cma.store(0, Ordering::SeqCst);
         }
     }
 
+    /// Checks which peers this rank can read from once all of them published
+    /// their pid. Senders fall back to the cells when the check failed, for
+    /// example when ptrace restrictions forbid the access.
+    pub fn probe_cma(&mut self) {
+        if self.cma_threshold == 0 {
+            return;
+        }
+
+        for src in 0..Context::size() {
+            if src == Context::rank() {
+                continue;
+            }
+            let pshm = self.pair(src, Context::rank());
+            let mut val = 0u64;
+            let ok = cma_read(
+                pshm.pid,
+                pshm.probe,
+                &mut val as *mut u64 as *mut c_void,
+                size_of::<u64>(),
+            ) && val == CMA_PROBE;
+            debug_shm!("Cross memory attach from {src}: {ok}");
+            pshm.cma.store(if ok { 1 } else { -1 }, Ordering::SeqCst);
+        }
+    }
+
+    /// Sets the number of cells in the pool of each rank and their payload size,
+    /// takes effect when the segment is allocated.
+    pub fn set_geometry(&mut self, ncells: usize, cell_len: usize) {
+        self.ncells = ncells.clamp(2, CELL_COUNT_MAX);
+        self.cell_len = cell_len.max(1).next_multiple_of(CELL_ALIGN);
+    }
+
+    /// Limits the number of outstanding requests of each kind.
+    pub fn set_limit(&mut self, limit: usize) {
+        self.recv_queue.set_limit(limit);
+        self.send_queue.set_limit(limit);
+        self.unexp_queue.set_limit(limit);
+        self.msg_queue.set_limit(limit);
+    }
+
+    /// The doorbell of this rank, it changes whenever a peer has news for it.
+    pub fn bell(&self) -> u32 {
+        self.cells.bell(Context::rank())
+    }
+
+    /// Sleeps until a peer rings the doorbell, unless it rang since `seen`.
+    pub fn sleep(&self, seen: u32) {
+        self.cells.sleep(Context::rank(), seen);
+    }
+
     #[inline(always)]
     pub fn get_send(&mut self) -> Option<&mut Request> {
         self.send_queue.push()
@@ -198,77 +532,231 @@ impl ShmData {
         self.unexp_queue.find_by_tag(rank, tag)
     }
 
-    pub fn free_req(&mut self, req: MPI_Request) {
-        self.find_queue(req).erase_ptr(req);
+    #[inline(always)]
+    fn unexp_layout(len: i32) -> std::alloc::Layout {
+        std::alloc::Layout::from_size_align(len.max(1) as usize, 32).unwrap()
     }
 
-    pub fn allocate(&mut self) -> i32 {
-        unsafe {
-            self.d = libc::mmap(
-                null_mut(),
-                size_of::<MpiShm>() * (Context::size() * Context::size()) as usize,
-                libc::PROT_READ | libc::PROT_WRITE,
-                libc::MAP_ANONYMOUS | libc::MAP_SHARED,
-                -1,
-                0,
-            ) as *mut MpiShm;
-            if self.d as *mut c_void == libc::MAP_FAILED {
-                return !MPI_SUCCESS;
-            }
-        }
-        MPI_SUCCESS
+    pub fn alloc_unexp(len: i32) -> *mut c_void {
+        unsafe { std::alloc::alloc(Self::unexp_layout(len)) as *mut c_void }
     }
 
-    pub fn allocate_by_key(&mut self, key: i32) -> i32 {
-        unsafe {
-            let len = size_of::<MpiShm>() * (Context::size() * Context::size()) as usize;
-            let mut id;
-            if Context::rank() == 0 {
-                id = libc::shmget(key, len, 0o666 | libc::IPC_CREAT);
-                assert!(id != -1);
-            } else {
-                let errno = &*libc::__errno_location();
-                loop {
-                    id = libc::shmget(key, len, 0o666);
-                    if id != -1 || *errno != libc::ENOENT {
-                        break;
-                    }
+    pub fn free_unexp(buf: *mut c_void, len: i32) {
+        unsafe { std::alloc::dealloc(buf as *mut u8, Self::unexp_layout(len)) }
+    }
+
+    /// Removes a buffered message from the unexpected or matched queue, a
+    /// synchronous sender is notified that its message is matched.
+    pub fn take(&mut self, msg: *mut Request) -> Request {
+        let req = unsafe { *msg };
+        self.unexp_queue.erase_ptr(msg);
+        self.msg_queue.erase_ptr(msg);
+
+        if req.seq != 0 {
+            if req.rank == Context::rank() {
+                let sreq = self
+                    .send_queue
+                    .iter_mut()
+                    .find(|r| r.rank == req.rank && r.seq == req.seq);
+                if let Some(sreq) = sreq {
+                    sreq.seq = 0;
+                    sreq.flag = 1;
                 }
-                assert!(id != -1);
+            } else {
+                self.pair(req.rank, Context::rank()).ack(req.seq);
+                self.cells.ring(req.rank);
             }
-            self.d = libc::shmat(id, null(), 0) as *mut MpiShm;
-            if self.d as i64 == -1 {
-                return !MPI_SUCCESS;
+        }
+        req
+    }
+
+    /// Completes `req` from a fully buffered message and releases the message.
+    /// A message longer than the receive is cut to its length and the status
+    /// reports `MPI_ERR_TRUNCATE`.
+    pub fn complete_from(&mut self, req: &mut Request, msg: *mut Request) {
+        let m = self.take(msg);
+        let cnt = m.cnt.min(req.cnt);
+        engine::unpack(req.dtype, req.buf, 0, m.buf, cnt as usize);
+        Self::free_unexp(m.buf, m.cnt);
+
+        req.stat = MPI_Status {
+            MPI_SOURCE: m.rank,
+            MPI_TAG: m.tag,
+            MPI_ERROR: Self::truncation(m.cnt, req.cnt),
+            cnt,
+            cancelled: 0,
+        };
+        req.rank = m.rank;
+        req.tag = m.tag;
+        req.cnt = cnt;
+        req.flag = 1;
+    }
+
+    /// Error of a receive of `cnt` bytes for a message of `len` bytes.
+    fn truncation(len: i32, cnt: i32) -> i32 {
+        if len > cnt {
+            debug_shm!("Truncate error for recv {cnt} != {len}");
+            MPI_ERR_TRUNCATE as i32
+        } else {
+            MPI_SUCCESS
+        }
+    }
+
+    #[inline(always)]
+    pub fn is_recv(&self, req: *const Request) -> bool {
+        self.recv_queue.contains(req)
+    }
+
+    #[inline(always)]
+    pub fn is_send(&self, req: *const Request) -> bool {
+        self.send_queue.contains(req)
+    }
+
+    /// Whether a request walks its buffer with the derived datatype `dtype`.
+    pub fn uses_type(&self, dtype: MPI_Datatype) -> bool {
+        self.send_queue
+            .iter()
+            .chain(self.recv_queue.iter())
+            .any(|r| r.dtype == dtype)
+    }
+
+    /// Claims the first message matching `rank` and `tag`, the message is buffered
+    /// and can only be received through the returned handle.
+    pub fn mprobe(&mut self, rank: i32, tag: i32) -> Result<Option<*mut Request>, MpiError> {
+        let Some(stat) = self.probe(rank, tag)? else {
+            return Ok(None);
+        };
+
+        if self.find_unexp(rank, tag).is_none() {
+            self.accept(stat.MPI_SOURCE)?;
+        }
+
+        let Some(unexp) = self.find_unexp(rank, tag) else {
+            debug_shm!("Message from {} is not received yet", stat.MPI_SOURCE);
+            return Ok(None);
+        };
+        let unexp = unexp as *mut Request;
+        let msg = self.msg_queue.push().ok_or(MPI_ERR_NO_MEM)?;
+        *msg = unsafe { *unexp };
+        self.unexp_queue.erase_ptr(unexp);
+
+        Ok(Some(msg))
+    }
+
+    pub fn free_req(&mut self, req: *const Request) -> MpiResult {
+        self.find_queue(req)?.erase_ptr(req);
+        Ok(())
+    }
+
+    /// Counts a pending request the user freed, see `release_freed`.
+    pub fn defer_free(&mut self) {
+        self.nfreed += 1;
+    }
+
+    /// Releases the requests the user freed once they completed.
+    fn release_freed(&mut self) {
+        for queue in [&mut self.send_queue, &mut self.recv_queue] {
+            self.done.clear();
+            let done = queue.iter().filter(|r| r.freed && r.flag != 0);
+            self.done.extend(done.map(|r| r as *const Request));
+            for &req in &self.done {
+                queue.erase_ptr(req);
+                self.nfreed -= 1;
             }
-            self.shm_key = id;
         }
+    }
+
+    /// User handle of a send or receive request, it becomes invalid once the
+    /// request slot is released. Any other request has no handle.
+    pub fn handle(&self, req: *const Request) -> Result<MPI_Request, MpiError> {
+        let (queue, send) = if self.is_send(req) {
+            (&self.send_queue, HANDLE_SEND)
+        } else if self.is_recv(req) {
+            (&self.recv_queue, 0)
+        } else {
+            return Err(MPI_ERR_INTERN);
+        };
+        let (idx, gen) = queue.key(req).ok_or(MPI_ERR_INTERN)?;
+        Ok(((gen as usize) << HANDLE_GEN_SHIFT | send | (idx + 1)) as MPI_Request)
+    }
+
+    /// The request behind `req` if the handle is not stale.
+    pub fn resolve(&mut self, req: MPI_Request) -> Option<*mut Request> {
+        let raw = req as usize;
+        let idx = (raw & HANDLE_IDX_MASK).checked_sub(1)?;
+        let gen = (raw >> HANDLE_GEN_SHIFT) as u32 & GEN_MASK;
+        let queue = if raw & HANDLE_SEND != 0 {
+            &mut self.send_queue
+        } else {
+            &mut self.recv_queue
+        };
+        queue.get(idx, gen).map(|r| r as *mut Request)
+    }
+
+    /// User handle of a matched message, laid out like a receive handle but
+    /// for the matched message queue. It becomes invalid once it is received.
+    pub fn message(&self, msg: *const Request) -> Result<MPI_Message, MpiError> {
+        if !self.msg_queue.contains(msg) {
+            return Err(MPI_ERR_INTERN);
+        }
+        let (idx, gen) = self.msg_queue.key(msg).ok_or(MPI_ERR_INTERN)?;
+        Ok(((gen as usize) << HANDLE_GEN_SHIFT | (idx + 1)) as MPI_Message)
+    }
+
+    /// The matched message behind `msg` if the handle is not stale.
+    pub fn resolve_message(&mut self, msg: MPI_Message) -> Option<*mut Request> {
+        let raw = msg as usize;
+        let idx = (raw & HANDLE_IDX_MASK).checked_sub(1)?;
+        if raw & HANDLE_SEND != 0 {
+            return None;
+        }
+        let gen = (raw >> HANDLE_GEN_SHIFT) as u32 & GEN_MASK;
+        self.msg_queue.get(idx, gen).map(|r| r as *mut Request)
+    }
+
+    pub fn allocate(&mut self, key: i32) -> i32 {
+        let len = self.segment_len();
+        self.seg = if key == -1 {
+            segment::create(len)
+        } else {
+            segment::open(key, len)
+        };
+        if self.seg.is_null() {
+            return !MPI_SUCCESS;
+        }
+        self.d = Segment::data(self.seg) as *mut MpiShm;
         MPI_SUCCESS
     }
 
     pub fn deallocate(&mut self) -> MpiResult {
-        unsafe {
-            if self.shm_key == -1 {
-                libc::munmap(
-                    self.d as *mut c_void,
-                    size_of::<MpiShm>() * (Context::size() * Context::size()) as usize,
-                );
-            } else {
-                libc::shmdt(self.d as *mut c_void);
-            }
-        }
+        segment::release(self.seg, self.segment_len());
         Ok(())
     }
 
     pub fn init(&mut self, _: *mut i32, _: *mut *mut *mut i8, key: i32) -> MpiResult {
         debug_assert!(!Context::is_init());
+        if self.allocate(key) != MPI_SUCCESS {
+            return Err(MPI_ERR_INTERN);
+        }
+
+        self.busy = vec![false; Context::size() as usize];
+        let pools = unsafe { (self.d as *mut u8).add(self.pairs_len()) as *mut Pool };
+        self.cells = Cells {
+            pools,
+            base: unsafe { pools.add(Context::size() as usize) as *mut u8 },
+            ncells: self.ncells,
+            cell_len: self.cell_len,
+        };
+
+        // Nobody takes cells from a pool before its owner is initialized, so a
+        // rank started on its own only sets up its pool.
+        debug_shm!("{} cells of {} bytes per rank", self.ncells, self.cell_len);
         if key == -1 {
-            if self.allocate() != MPI_SUCCESS {
-                return Err(MPI_ERR_INTERN);
+            for rank in 0..Context::size() {
+                self.cells.init(rank);
             }
         } else {
-            if self.allocate_by_key(key) != MPI_SUCCESS {
-                return Err(MPI_ERR_INTERN);
-            }
+            self.cells.init(Context::rank());
         }
         Ok(())
     }
@@ -283,124 +771,402 @@ impl ShmData {
         let d = unsafe { &mut *(self as *mut Self) };
 
         for req in d.recv_queue.iter_mut() {
-            Self::recv_progress(self as *mut Self, req)?;
+            if req.isColl {
+                Self::recv_progress(self as *mut Self, req)?;
+            }
+        }
+
+        for src in 0..Context::size() {
+            if src != Context::rank() {
+                self.match_progress(src)?;
+            }
         }
 
+        // Messages to the same rank go out one after another, a send that is
+        // not fully written holds back the later ones.
+        self.busy.fill(false);
+        self.done.clear();
         for req in d.send_queue.iter_mut() {
+            if req.isColl {
+                Self::send_progress(self as *mut Self, req)?;
+                continue;
+            }
+            if req.rank != MPI_PROC_NULL && self.busy[req.rank as usize] {
+                continue;
+            }
             Self::send_progress(self as *mut Self, req)?;
+            if req.flag == 0 && req.is_active() && (req.seq == 0 || !req.cursor.is_idle()) {
+                self.busy[req.rank as usize] = true;
+            }
+            if req.flag != 0 && req.mode == SendMode::Buffered {
+                self.done.push(req as *const Request);
+            }
+        }
+
+        for &req in &d.done {
+            Context::bsend().release(unsafe { (*req).buf as *mut u8 });
+            self.send_queue.erase_ptr(req);
         }
 
+        if self.nfreed != 0 {
+            self.release_freed();
+        }
         Ok(())
     }
 
     #[inline(always)]
-    fn recv_progress(this: *mut Self, mut req: &mut Request) -> MpiResult {
-        if req.flag != 0 {
-            return Ok(());
+    fn pair_at(&mut self, idx: usize) -> &mut MpiShm {
+        unsafe { &mut *self.d.add(idx) }
+    }
+
+    #[inline(always)]
+    fn pair(&mut self, src: i32, dest: i32) -> &mut MpiShm {
+        self.pair_at((src * Context::size() + dest) as usize)
+    }
+
+    /// Bytes of the pair headers, the pools start right after them.
+    fn pairs_len(&self) -> usize {
+        let len = size_of::<MpiShm>() * (Context::size() * Context::size()) as usize;
+        len.next_multiple_of(size_of::<Pool>())
+    }
+
+    /// Bytes of the whole segment, only the small pair headers grow with the
+    /// square of the ranks.
+    fn segment_len(&self) -> usize {
+        let size = Context::size() as usize;
+        let cells = self.ncells * Cells::stride(self.cell_len);
+        size_of::<Segment>() + self.pairs_len() + size * (size_of::<Pool>() + cells)
+    }
+
+    #[inline(always)]
+    fn is_awaited(&self, src: i32) -> bool {
+        self.recv_queue
+            .iter()
+            .any(|r| r.is_posted() && (r.rank == src || r.rank == MPI_ANY_SOURCE))
+    }
+
+    /// The request a partially received message from `src` is copied into.
+    fn incoming(&mut self, src: i32) -> Option<&mut Request> {
+        let recv = self as *mut Self;
+        let busy = |r: &&mut Request| !r.isColl && !r.cursor.is_idle() && r.stat.MPI_SOURCE == src;
+        unsafe { (*recv).recv_queue.iter_mut().find(busy) }
+            .or_else(|| self.unexp_queue.iter_mut().find(busy))
+    }
+
+    fn match_progress(&mut self, src: i32) -> MpiResult {
+        if let Some(req) = self.incoming(src) {
+            let req = req as *mut Request;
+            if !self.receive(src, unsafe { &mut *req })? {
+                return Ok(());
+            }
         }
 
-        debug_shm!("Enter recover progress");
+        // Nobody may wait for the messages of a sender that runs out of cells,
+        // they are buffered so it can go on sending to others.
+        let cells = self.cells;
+        while (self.is_awaited(src) || cells.is_low(src))
+            && self.pair(src, Context::rank()).recv_cell(&cells).is_some()
+        {
+            if !self.accept(src)? {
+                break;
+            }
+        }
 
-        let rank = Context::comm_rank(req.comm);
-        let size = Context::comm_size(req.comm);
+        Ok(())
+    }
 
-        let d = unsafe { &mut *this };
-        let pshm = unsafe {
-            if req.isColl {
-                let rootRank = Context::comm_prank(req.comm, req.collRoot);
-                d.d.add((rootRank * size + rootRank) as usize)
-                    .as_mut()
-                    .unwrap_unchecked()
-            } else {
-                d.d.add((req.rank * size + rank) as usize)
-                    .as_mut()
-                    .unwrap_unchecked()
+    /// Starts receiving the head message from `src`, either into the first posted
+    /// receive that matches it or into the unexpected queue. Returns whether the
+    /// whole message is received.
+    fn accept(&mut self, src: i32) -> Result<bool, MpiError> {
+        let pshm = self.pair(src, Context::rank()) as *mut MpiShm;
+        let pshm = unsafe { &mut *pshm };
+
+        let cells = self.cells;
+        let Some(cell) = pshm.recv_cell(&cells) else {
+            return Ok(false);
+        };
+        let (tag, len, seq) = (cell.tag, cell.len, cell.sync);
+
+        // A message longer than the receive it matches is buffered first, the
+        // receive is completed from it with the part that fits.
+        let preq = self
+            .recv_queue
+            .iter_mut()
+            .find(|r| r.is_posted() && r.matches(src, tag))
+            .filter(|r| r.cnt >= len);
+
+        let req = if let Some(req) = preq {
+            req.cnt = len;
+            req.seq = 0;
+            if seq != 0 {
+                pshm.ack(seq);
+                self.cells.ring(src);
             }
+            req
+        } else {
+            debug_shm!("Find unexpect message from rank: {src}, tag: {tag}");
+            self.push_unexp(src, tag, len, seq)?
+        };
+
+        req.stat.MPI_SOURCE = src;
+        req.stat.MPI_TAG = tag;
+        req.stat.cnt = len;
+        req.cursor = Cursor {
+            cell: pshm.nrecv.load(Ordering::SeqCst) as i32,
+            ..Cursor::new()
         };
 
-        pshm.recv_cell().wait_ne(0);
+        let req = req as *mut Request;
+        self.receive(src, unsafe { &mut *req })
+    }
 
-        debug_shm!("Wait cell");
+    /// Copies the cells of `src` that are already written into `req`, a
+    /// completed unexpected message goes to a receive posted in the meantime.
+    fn receive(&mut self, src: i32, req: &mut Request) -> Result<bool, MpiError> {
+        let cells = self.cells;
+        let pshm = self.pair(src, Context::rank()) as *mut MpiShm;
+        if !Self::recv_cells(unsafe { &mut *pshm }, &cells, req)? {
+            return Ok(false);
+        }
 
-        let mut unexp = false;
-        if req.tag != pshm.recv_cell().tag as i32 {
-            debug_shm!(
-                "Find unexpect message from rank: {}, {} != {}",
-                req.rank,
-                req.tag,
-                pshm.recv_cell().tag
-            );
-            let preqx = d.unexp_queue.push();
-            if let Some(reqx) = preqx {
-                reqx.rank = req.rank;
-                reqx.tag = pshm.recv_cell().tag as i32;
-                reqx.cnt = pshm.recv_cell().len;
+        req.flag = 1;
+        debug_shm!("Success recover from {src} with tag {}", req.stat.MPI_TAG);
 
-                req = reqx;
+        if self.unexp_queue.contains(req) {
+            let (rank, tag) = (req.rank, req.tag);
+            let preq = self
+                .recv_queue
+                .iter_mut()
+                .find(|r| r.is_posted() && r.matches(rank, tag));
+            if let Some(preq) = preq {
+                let preq = preq as *mut Request;
+                self.complete_from(unsafe { &mut *preq }, req);
+            }
+        }
+
+        Ok(true)
+    }
+
+    fn push_unexp(
+        &mut self,
+        src: i32,
+        tag: i32,
+        len: i32,
+        seq: u32,
+    ) -> Result<&mut Request, MpiError> {
+        let reqx = self.unexp_queue.push().ok_or(MPI_ERR_NO_MEM)?;
+        reqx.rank = src;
+        reqx.tag = tag;
+        reqx.cnt = len;
+        reqx.dtype = MPI_BYTE;
+        reqx.flag = 0;
+        reqx.isColl = false;
+        reqx.seq = seq;
+        reqx.cursor = Cursor::new();
+
+        debug_shm!("Allocate unexpected buffer");
+        let buf = Self::alloc_unexp(len);
+        if buf.is_null() {
+            debug_shm!("Error allocate unexpected buffer");
+            return Err(MPI_ERR_OTHER);
+        }
+        reqx.buf = buf;
+        Ok(reqx)
+    }
+
+    /// Delivers a message to the own rank without going through the shared
+    /// memory, either straight into a posted receive or into the unexpected
+    /// queue. A synchronous send stays pending until its message is taken.
+    fn loopback(&mut self, req: &mut Request) -> MpiResult {
+        if req.seq != 0 {
+            return Ok(());
+        }
+
+        let rank = Context::rank();
+        let preq = self
+            .recv_queue
+            .iter_mut()
+            .find(|r| r.is_posted() && r.matches(rank, req.tag));
 
-                unexp = true;
+        let dst = if let Some(r) = preq {
+            r.stat.MPI_ERROR = Self::truncation(req.cnt, r.cnt);
+            r.cnt = r.cnt.min(req.cnt);
+            r
+        } else {
+            debug_shm!("Buffer message to self with tag: {}", req.tag);
+            let seq = if req.mode == SendMode::Synchronous {
+                self.nloop += 1;
+                self.nloop
             } else {
-                return Err(MPI_ERR_OTHER);
+                0
+            };
+            req.seq = seq;
+            self.push_unexp(rank, req.tag, req.cnt, seq)?
+        };
+
+        engine::transfer(dst.buf, dst.dtype, req.buf, req.dtype, dst.cnt as usize);
+        dst.flag = 1;
+        dst.stat.MPI_SOURCE = rank;
+        dst.stat.MPI_TAG = req.tag;
+        dst.stat.cnt = dst.cnt;
+
+        req.stat.MPI_SOURCE = rank;
+        req.stat.MPI_TAG = req.tag;
+        req.stat.cnt = req.cnt;
+        if req.seq == 0 {
+            req.flag = 1;
+        }
+
+        Ok(())
+    }
+
+    /// Looks for a message matching `rank` and `tag` without receiving it. Head
+    /// messages that don't match are moved aside so the ones behind them are visible.
+    pub fn probe(&mut self, rank: i32, tag: i32) -> Result<Option<MPI_Status>, MpiError> {
+        for src in 0..Context::size() {
+            if let Some(req) = self.incoming(src) {
+                let req = req as *mut Request;
+                self.receive(src, unsafe { &mut *req })?;
             }
         }
 
-        if unexp {
-            debug_shm!("Allocate unexpected buffer");
-            let layout = std::alloc::Layout::from_size_align(req.cnt as usize, 32).unwrap();
-            let buf = unsafe { std::alloc::alloc(layout) };
-            if buf.is_null() {
-                debug_shm!("Error allocate unexpected buffer");
-                return Err(MPI_ERR_OTHER);
+        if let Some(r) = self.find_unexp(rank, tag) {
+            let mut stat = r.stat;
+            stat.MPI_ERROR = MPI_SUCCESS;
+            return Ok(Some(stat));
+        }
+
+        for src in 0..Context::size() {
+            if src == Context::rank() || (rank != MPI_ANY_SOURCE && rank != src) {
+                continue;
+            }
+
+            while self.incoming(src).is_none() {
+                let cells = self.cells;
+                let Some(cell) = self.pair(src, Context::rank()).recv_cell(&cells) else {
+                    break;
+                };
+                if CommGroup::tag_match(tag, cell.tag) {
+                    return Ok(Some(MPI_Status {
+                        MPI_SOURCE: src,
+                        MPI_TAG: cell.tag,
+                        MPI_ERROR: MPI_SUCCESS,
+                        cnt: cell.len,
+                        cancelled: 0,
+                    }));
+                }
+                self.accept(src)?;
             }
-            debug_assert!(
-                buf as usize % 32 == 0,
-                "Unexpected buff alignment: {}",
-                buf as usize % 32
-            );
-            req.buf = buf as *mut c_void;
-        } else if req.cnt < pshm.recv_cell().len {
-            debug_shm!(
-                "Truncate error for recv {} != {}",
-                req.cnt,
-                pshm.recv_cell().len
-            );
-            return Err(MPI_ERR_TRUNCATE);
-        } else {
-            req.cnt = pshm.recv_cell().len;
         }
 
-        let mut length = req.cnt as usize;
-        let mut buf = req.buf;
+        Ok(None)
+    }
+
+    /// Copies cells until the message is complete or the next cell is not
+    /// written yet, returns whether the message is complete. A rendezvous
+    /// message is read from the sender at once.
+    #[inline(always)]
+    fn recv_cells(pshm: &mut MpiShm, cells: &Cells, req: &mut Request) -> Result<bool, MpiError> {
+        debug_shm!("Recv length: {}, done: {}", req.cnt, req.cursor.done);
+        loop {
+            let pos = req.cursor.cell as usize;
+            let Some(cell) = pshm.cell(cells, pos) else {
+                return Ok(false);
+            };
 
-        debug_shm!("Recv length: {length}");
-        while length > Cell::buf_len() {
-            memcpy(
-                buf,
-                pshm.recv_cell().buff.as_ptr() as *const c_void,
-                Cell::buf_len(),
-            );
+            if req.cursor.done == 0 && cell.rndv != 0 && !req.cursor.rndv {
+                let len = req.cnt as usize;
+                let mut stage = Vec::new();
+                let dst = if req.dtype == MPI_BYTE {
+                    req.buf
+                } else {
+                    stage.resize(len, 0u8);
+                    stage.as_mut_ptr() as *mut c_void
+                };
+                if !cma_read(pshm.pid, cell.addr, dst, len) {
+                    // The sender sees the mark once the header is released and
+                    // sends the data through the cells, now and from then on.
+                    debug_shm!("Error reading {} bytes from pid {}", req.cnt, pshm.pid);
+                    pshm.cma.store(-1, Ordering::SeqCst);
+                    pshm.release(cells);
+                    req.cursor.cell = pshm.nrecv.load(Ordering::SeqCst) as i32;
+                    req.cursor.rndv = true;
+                    continue;
+                }
+                if req.dtype != MPI_BYTE {
+                    engine::unpack(req.dtype, req.buf, 0, dst, len);
+                }
+                pshm.release(cells);
+                req.cursor = Cursor::new();
+                return Ok(true);
+            }
+
+            let length = cells.cell_len.min((req.cnt - req.cursor.done) as usize);
+            debug_assert!(cell.buff() as usize % CELL_ALIGN == 0);
+            if req.dtype == MPI_BYTE {
+                memcpy_part(
+                    unsafe { req.buf.add(req.cursor.done as usize) },
+                    cell.buff(),
+                    length,
+                    req.cnt as usize,
+                );
+            } else {
+                let pos = req.cursor.done as usize;
+                engine::unpack(req.dtype, req.buf, pos, cell.buff(), length);
+            }
             if req.isColl {
-                pshm.coll_wait_and_swap();
+                pshm.coll_wait_and_swap(cells, pos, req);
             } else {
-                pshm.recv_cell().dec_flag();
-                pshm.swapRecv();
+                pshm.release(cells);
             }
-            pshm.recv_cell().wait_ne(0);
 
-            buf = unsafe { buf.add(Cell::buf_len()) };
-            length -= Cell::buf_len();
+            req.cursor.done += length as i32;
+            req.cursor.cell = pshm.nrecv.load(Ordering::SeqCst) as i32;
+            if req.cursor.done == req.cnt {
+                req.cursor = Cursor::new();
+                return Ok(true);
+            }
         }
+    }
 
-        debug_assert!(pshm.recv_cell().buff.as_ptr() as *const c_void as usize % 32 == 0);
-        memcpy(buf, pshm.recv_cell().buff.as_ptr() as *const c_void, length);
-        if req.isColl {
-            pshm.coll_wait_and_swap();
-        } else {
-            pshm.recv_cell().dec_flag();
-            pshm.swapRecv();
+    #[inline(always)]
+    fn recv_progress(this: *mut Self, req: &mut Request) -> MpiResult {
+        if req.flag != 0 {
+            return Ok(());
         }
 
-        req.stat.MPI_SOURCE = req.rank;
+        debug_shm!("Enter recover progress");
+
+        let d = unsafe { &mut *this };
+        let rootRank = Context::comm_prank(req.comm, req.collRoot);
+        let cells = d.cells;
+        let pshm = d.pair(rootRank, rootRank) as *mut MpiShm;
+        let pshm = unsafe { &mut *pshm };
+
+        if req.cursor.is_idle() {
+            let Some(cell) = pshm.recv_cell(&cells) else {
+                return Ok(());
+            };
+
+            debug_shm!("Wait cell");
+
+            if req.cnt < cell.len {
+                debug_shm!("Truncate error for recv {} != {}", req.cnt, cell.len);
+                return Err(MPI_ERR_TRUNCATE);
+            }
+            req.cnt = cell.len;
+            req.cursor = Cursor {
+                cell: pshm.nrecv.load(Ordering::SeqCst) as i32,
+                ..Cursor::new()
+            };
+        }
+
+        if !Self::recv_cells(pshm, &cells, req)? {
+            return Ok(());
+        }
+
+        req.stat.MPI_SOURCE = rootRank;
         req.stat.MPI_TAG = req.tag;
         req.stat.cnt = req.cnt;
         req.flag = 1;
@@ -410,71 +1176,160 @@ impl ShmData {
         Ok(())
     }
 
+    /// Rings the ranks that read the cells `req` publishes.
+    #[inline(always)]
+    fn notify(cells: &Cells, req: &Request) {
+        if !req.isColl {
+            cells.ring(req.rank);
+            return;
+        }
+        for rank in 0..Context::comm_size(req.comm) {
+            if rank != req.collRoot {
+                cells.ring(Context::comm_prank(req.comm, rank));
+            }
+        }
+    }
+
     #[inline(always)]
     fn send_progress(this: *mut Self, req: &mut Request) -> MpiResult {
-        if req.flag != 0 {
+        if req.flag != 0 || !req.is_active() {
             return Ok(());
         }
+        if !req.isColl && req.rank == Context::rank() {
+            return unsafe { (*this).loopback(req) };
+        }
         let rank = Context::comm_rank(req.comm);
         let size = Context::comm_size(req.comm);
-        let flagValue: usize;
+        let flagValue: u32;
 
         let d = unsafe { &mut *this };
-        let pshm = unsafe {
-            if req.isColl {
-                flagValue = size as usize - 1;
-                let rootRank = Context::comm_prank(req.comm, req.collRoot);
-                d.d.add((rootRank * size + rootRank) as usize)
-                    .as_mut()
-                    .unwrap_unchecked()
-            } else {
-                flagValue = 1;
-                d.d.add((rank * size + req.rank) as usize)
-                    .as_mut()
-                    .unwrap_unchecked()
-            }
+        let threshold = d.cma_threshold;
+        let cells = d.cells;
+        let pshm = if req.isColl {
+            flagValue = size as u32 - 1;
+            let rootRank = Context::comm_prank(req.comm, req.collRoot);
+            d.pair_at((rootRank * size + rootRank) as usize)
+        } else {
+            flagValue = 1;
+            d.pair_at((rank * size + req.rank) as usize)
         };
 
-        pshm.send_cell().wait_eq(0);
-
-        let mut length = req.cnt as usize;
-        let mut buf = req.buf;
-        pshm.send_cell().len = req.cnt;
-        pshm.send_cell().tag = req.tag;
-        debug_shm!("Send length: {length}");
+        // The first cell of a message is taken before its sequence number, so
+        // a send without a cell doesn't leave a gap in the acknowledgements.
+        let mut head = None;
+        if req.cursor.is_idle() {
+            if req.seq != 0 {
+                if pshm.is_acked(req.seq) {
+                    debug_shm!("Synchronous send to {} matched", req.rank);
+                    req.flag = 1;
+                }
+                return Ok(());
+            }
 
-        while length > Cell::buf_len() {
-            memcpy(
-                pshm.send_cell().buff.as_mut_ptr() as *mut c_void,
-                buf,
-                Cell::buf_len(),
-            );
-            if req.isColl {
-                pshm.send_cell().set_coll_flag(flagValue as i8);
+            if !pshm.can_send() {
+                return Ok(());
+            }
+            let Some(idx) = cells.alloc(Context::rank()) else {
+                return Ok(());
+            };
+            if req.mode == SendMode::Synchronous && !req.isColl {
+                let Some(seq) = pshm.next_sync() else {
+                    cells.free(idx);
+                    return Ok(());
+                };
+                req.seq = seq;
+            }
+            // Only contiguous data can be pulled by the receiver as it is.
+            let rndv = !req.isColl
+                && req.dtype == MPI_BYTE
+                && threshold != 0
+                && req.cnt as usize >= threshold
+                && pshm.cma.load(Ordering::SeqCst) == 1;
+            let cell = cells.cell(idx);
+            cell.len = req.cnt;
+            cell.tag = req.tag;
+            cell.sync = req.seq;
+            cell.rndv = rndv as i8;
+            cell.addr = req.buf as usize;
+            req.cursor = Cursor {
+                done: 0,
+                cell: pshm.nsend.load(Ordering::SeqCst) as i32,
+                rndv,
+            };
+            if rndv {
+                debug_shm!("Rendezvous send of {} bytes to {}", req.cnt, req.rank);
+                cell.set_flag(flagValue);
+                pshm.publish(idx);
+                Self::notify(&cells, req);
+                return Ok(());
             }
-            pshm.send_cell().set_flag(flagValue);
-            pshm.swapSend();
-            pshm.send_cell().wait_eq(0);
+            head = Some(idx);
+        }
+        debug_shm!("Send length: {}, done: {}", req.cnt, req.cursor.done);
 
-            buf = unsafe { buf.add(Cell::buf_len()) };
-            length -= Cell::buf_len();
+        if req.cursor.rndv {
+            // The receiver releases the header cell once it has read the data,
+            // or once it failed to and waits for the data in cells.
+            if pshm.cell(&cells, req.cursor.cell as usize).is_some() {
+                return Ok(());
+            }
+            req.cursor = if pshm.cma.load(Ordering::SeqCst) == 1 {
+                Cursor::new()
+            } else {
+                debug_shm!(
+                    "Rendezvous to {} failed, sending through the cells",
+                    req.rank
+                );
+                Cursor {
+                    cell: pshm.nsend.load(Ordering::SeqCst) as i32,
+                    ..Cursor::new()
+                }
+            };
         }
 
-        memcpy(
-            pshm.send_cell().buff.as_mut_ptr() as *mut c_void,
-            buf,
-            length,
-        );
-        if req.isColl {
-            pshm.send_cell().set_coll_flag(flagValue as i8);
+        while !req.cursor.is_idle() {
+            let idx = match head.take() {
+                Some(idx) => idx,
+                None if pshm.can_send() => match cells.alloc(Context::rank()) {
+                    Some(idx) => idx,
+                    None => return Ok(()),
+                },
+                None => return Ok(()),
+            };
+            let cell = cells.cell(idx);
+
+            let length = cells.cell_len.min((req.cnt - req.cursor.done) as usize);
+            if req.dtype == MPI_BYTE {
+                memcpy_part(
+                    cell.buff(),
+                    unsafe { req.buf.add(req.cursor.done as usize) },
+                    length,
+                    req.cnt as usize,
+                );
+            } else {
+                let pos = req.cursor.done as usize;
+                engine::pack(req.dtype, req.buf, pos, cell.buff(), length);
+            }
+            if req.isColl {
+                cell.set_coll_flag(flagValue as i8);
+            }
+            cell.set_flag(flagValue);
+            pshm.publish(idx);
+            Self::notify(&cells, req);
+
+            req.cursor.done += length as i32;
+            req.cursor.cell = pshm.nsend.load(Ordering::SeqCst) as i32;
+            if req.cursor.done == req.cnt {
+                req.cursor = Cursor::new();
+            }
         }
-        pshm.send_cell().set_flag(flagValue);
-        pshm.swapSend();
 
         req.stat.MPI_SOURCE = req.rank;
         req.stat.MPI_TAG = req.tag;
         req.stat.cnt = req.cnt;
-        req.flag = 1;
+        if req.seq == 0 || pshm.is_acked(req.seq) {
+            req.flag = 1;
+        }
 
         debug_shm!("Success send to {}", req.tag);
 
@@ -0,0 +1,102 @@
+use crate::shared::*;
+use std::sync::atomic::AtomicU32;
+
+/// What a blocked rank does once polling found nothing to do for a while.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum WaitPolicy {
+    /// Keeps polling, the lowest latency when every rank has a core of its own.
+    Spin,
+    /// Gives the core to other ranks between polls.
+    Yield,
+    /// Yields for a while, then sleeps until a peer rings the doorbell.
+    Futex,
+}
+
+impl WaitPolicy {
+    pub fn parse(val: &str) -> Option<WaitPolicy> {
+        match val {
+            "spin" => Some(WaitPolicy::Spin),
+            "yield" => Some(WaitPolicy::Yield),
+            "futex" => Some(WaitPolicy::Futex),
+            _ => None,
+        }
+    }
+
+    /// Spins while each of the `size` ranks can have a core of its own, ranks
+    /// that share cores would only spin away each other's time slices.
+    pub fn default_for(size: i32) -> WaitPolicy {
+        let cores = std::thread::available_parallelism().map_or(1, |n| n.get());
+        if size as usize <= cores {
+            WaitPolicy::Spin
+        } else {
+            WaitPolicy::Yield
+        }
+    }
+}
+
+/// Polls before a waiting rank starts to yield unless configured otherwise.
+pub const SPIN_COUNT: u32 = 1000;
+/// Yields before a waiting rank goes to sleep.
+const YIELD_COUNT: u32 = 100;
+/// A sleeping rank polls again after this long even if nobody rang.
+const SLEEP_NS: i64 = 10_000_000;
+
+/// Paces the polls of one blocking call according to the wait policy.
+pub struct Backoff {
+    polls: u32,
+    seen: u32,
+    sleep: bool,
+}
+
+impl Backoff {
+    pub fn new() -> Backoff {
+        let sleep = Context::wait_policy() == WaitPolicy::Futex;
+        Backoff {
+            polls: 0,
+            seen: if sleep { Context::shm().bell() } else { 0 },
+            sleep,
+        }
+    }
+
+    /// Called after every poll that found nothing to do. The doorbell is read
+    /// before the next poll, so news arriving during that poll is not missed.
+    #[inline(always)]
+    pub fn snooze(&mut self) {
+        let policy = Context::wait_policy();
+        let spins = Context::spin_count();
+        if policy == WaitPolicy::Spin || self.polls < spins {
+            std::hint::spin_loop();
+        } else if self.sleep && self.polls >= spins.saturating_add(YIELD_COUNT) {
+            Context::shm().sleep(self.seen);
+        } else {
+            unsafe { libc::sched_yield() };
+        }
+        self.polls = self.polls.saturating_add(1);
+        if self.sleep {
+            self.seen = Context::shm().bell();
+        }
+    }
+}
+
+/// Sleeps while `word` holds `val`, at most for `SLEEP_NS`. The word may live in
+/// memory shared with other processes.
+pub fn futex_wait(word: &AtomicU32, val: u32) {
+    let timeout = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: SLEEP_NS,
+    };
+    unsafe {
+        libc::syscall(
+            libc::SYS_futex,
+            word.as_ptr(),
+            libc::FUTEX_WAIT,
+            val,
+            &timeout as *const libc::timespec,
+        )
+    };
+}
+
+/// Wakes every process sleeping on `word`.
+pub fn futex_wake(word: &AtomicU32) {
+    unsafe { libc::syscall(libc::SYS_futex, word.as_ptr(), libc::FUTEX_WAKE, i32::MAX) };
+}
@@ -1,5 +1,5 @@
 use crate::context::Context;
-use crate::{shared::*, types::*, MPI_CHECK, MPI_CHECK_COMM};
+use crate::{shared::*, types::*, MPI_CHECK_COMM, MPI_TRY};
 
 pub(crate) fn p_mpi_abort(_: MPI_Comm, _: i32) {
     Context::deinit().unwrap();
@@ -9,7 +9,7 @@ pub(crate) fn p_mpi_abort(_: MPI_Comm, _: i32) {
 
 #[no_mangle]
 pub extern "C" fn MPI_Init(pargc: *mut i32, pargv: *mut *mut *mut i8) -> i32 {
-    MPI_CHECK!(!Context::is_init(), MPI_COMM_WORLD, MPI_ERR_OTHER);
+    MPI_TRY!(!Context::is_init(), MPI_COMM_WORLD, MPI_ERR_OTHER);
 
     if let Err(code) = Context::init(pargc, pargv) {
         return code as i32;
@@ -19,7 +19,7 @@ pub extern "C" fn MPI_Init(pargc: *mut i32, pargv: *mut *mut *mut i8) -> i32 {
 
 #[no_mangle]
 pub extern "C" fn MPI_Finalize() -> i32 {
-    MPI_CHECK!(Context::is_init(), MPI_COMM_WORLD, MPI_ERR_OTHER);
+    MPI_TRY!(Context::is_init(), MPI_COMM_WORLD, MPI_ERR_OTHER);
 
     if let Err(code) = Context::deinit() {
         Context::call_error(MPI_COMM_WORLD, code);
@@ -31,7 +31,7 @@ pub extern "C" fn MPI_Finalize() -> i32 {
 
 #[no_mangle]
 pub extern "C" fn MPI_Abort(comm: MPI_Comm, code: i32) -> i32 {
-    MPI_CHECK!(Context::is_init(), MPI_COMM_WORLD, MPI_ERR_OTHER);
+    MPI_TRY!(Context::is_init(), MPI_COMM_WORLD, MPI_ERR_OTHER);
     MPI_CHECK_COMM!(comm);
 
     p_mpi_abort(comm, code);
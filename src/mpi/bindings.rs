@@ -1,13 +1,24 @@
+// The C API takes raw pointers, callers are responsible for them.
+#![allow(clippy::not_unsafe_ptr_arg_deref)]
+
 use crate::{shared::*, metatypes};
-use crate::xfer::ppp::recv::{irecv, recv};
-use crate::xfer::ppp::send::{isend, send};
-use crate::xfer::ppp::sendrecv;
+use crate::xfer::ppp::probe::{improbe, iprobe, mprobe, probe};
+use crate::xfer::ppp::recv::{imrecv, irecv, mrecv, recv, recv_init};
+use crate::xfer::ppp::send::{
+    buffer_attach, buffer_detach, bsend, ibsend, irsend, isend, issend, rsend, send, send_init,
+    ssend,
+};
+use crate::xfer::ppp::userbuf::TypedBuf;
+use crate::xfer::ppp::{sendrecv, sendrecv_replace};
+use crate::errhandler::handler::HandlerContext;
 use crate::xfer::request::Request;
-use crate::MPI_CHECK;
+use crate::MPI_TRY;
 use crate::{MPI_Comm, MPI_Datatype, MPI_Request};
 use libc::c_void;
+use std::ffi::{c_char, CStr};
 use std::slice::{from_raw_parts, from_raw_parts_mut};
-use crate::metatypes::type_size;
+use crate::metatypes::{contiguous_len, external, pack};
+use crate::metatypes::typemap::{self, Typemap};
 
 #[no_mangle]
 pub extern "C" fn MPI_Isend(
@@ -19,18 +30,12 @@ pub extern "C" fn MPI_Isend(
     comm: MPI_Comm,
     preq: *mut MPI_Request,
 ) -> i32 {
-    let dataLen = type_size(dtype).unwrap() * cnt;
-    MPI_CHECK!(!preq.is_null(), comm, MPI_ERR_ARG);
+    MPI_TRY!(!preq.is_null(), comm, MPI_ERR_ARG);
     unsafe {
-        return match isend(
-            from_raw_parts(buf as *const u8, dataLen as usize),
-            dest,
-            tag,
-            comm,
-        ) {
+        return match isend(&TypedBuf::new(buf, cnt, dtype), dest, tag, comm).and_then(|req| Request::handle(req)) {
             Err(code) => code as i32,
-            Ok(req) => {
-                *preq = req;
+            Ok(handle) => {
+                *preq = handle;
                 MPI_SUCCESS
             }
         };
@@ -47,18 +52,56 @@ pub extern "C" fn MPI_Irecv(
     comm: MPI_Comm,
     preq: *mut MPI_Request,
 ) -> i32 {
-    let dataLen = type_size(dtype).unwrap() * cnt;
-    MPI_CHECK!(!preq.is_null(), comm, MPI_ERR_ARG);
+    MPI_TRY!(!preq.is_null(), comm, MPI_ERR_ARG);
     unsafe {
-        return match irecv(
-            from_raw_parts_mut(buf as *mut u8, dataLen as usize),
-            src,
-            tag,
-            comm,
-        ) {
+        return match irecv(&mut TypedBuf::new(buf, cnt, dtype), src, tag, comm).and_then(|req| Request::handle(req)) {
+            Err(code) => code as i32,
+            Ok(handle) => {
+                *preq = handle;
+                MPI_SUCCESS
+            }
+        };
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn MPI_Send_init(
+    buf: *const c_void,
+    cnt: i32,
+    dtype: MPI_Datatype,
+    dest: i32,
+    tag: i32,
+    comm: MPI_Comm,
+    preq: *mut MPI_Request,
+) -> i32 {
+    MPI_TRY!(!preq.is_null(), comm, MPI_ERR_ARG);
+    unsafe {
+        return match send_init(&TypedBuf::new(buf, cnt, dtype), dest, tag, comm).and_then(|req| Request::handle(req)) {
             Err(code) => code as i32,
-            Ok(req) => {
-                *preq = req;
+            Ok(handle) => {
+                *preq = handle;
+                MPI_SUCCESS
+            }
+        };
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn MPI_Recv_init(
+    buf: *mut c_void,
+    cnt: i32,
+    dtype: MPI_Datatype,
+    src: i32,
+    tag: i32,
+    comm: MPI_Comm,
+    preq: *mut MPI_Request,
+) -> i32 {
+    MPI_TRY!(!preq.is_null(), comm, MPI_ERR_ARG);
+    unsafe {
+        return match recv_init(&mut TypedBuf::new(buf, cnt, dtype), src, tag, comm).and_then(|req| Request::handle(req)) {
+            Err(code) => code as i32,
+            Ok(handle) => {
+                *preq = handle;
                 MPI_SUCCESS
             }
         };
@@ -74,98 +117,563 @@ pub extern "C" fn MPI_Send(
     tag: i32,
     comm: MPI_Comm,
 ) -> i32 {
-    let dataLen = type_size(dtype).unwrap() * cnt;
-    MPI_CHECK!(!buf.is_null(), comm, MPI_ERR_ARG);
-    let result;
+    MPI_TRY!(cnt <= 0 || !buf.is_null(), comm, MPI_ERR_ARG);
+    let result = send(&TypedBuf::new(buf, cnt, dtype), dest, tag, comm);
+    if let Err(code) = result {
+        return code as i32;
+    }
+
+    MPI_SUCCESS
+}
+
+#[no_mangle]
+pub extern "C" fn MPI_Ssend(
+    buf: *const c_void,
+    cnt: i32,
+    dtype: MPI_Datatype,
+    dest: i32,
+    tag: i32,
+    comm: MPI_Comm,
+) -> i32 {
+    MPI_TRY!(cnt <= 0 || !buf.is_null(), comm, MPI_ERR_ARG);
+    let result = ssend(&TypedBuf::new(buf, cnt, dtype), dest, tag, comm);
+    if let Err(code) = result {
+        return code as i32;
+    }
+
+    MPI_SUCCESS
+}
+
+#[no_mangle]
+pub extern "C" fn MPI_Rsend(
+    buf: *const c_void,
+    cnt: i32,
+    dtype: MPI_Datatype,
+    dest: i32,
+    tag: i32,
+    comm: MPI_Comm,
+) -> i32 {
+    MPI_TRY!(cnt <= 0 || !buf.is_null(), comm, MPI_ERR_ARG);
+    let result = rsend(&TypedBuf::new(buf, cnt, dtype), dest, tag, comm);
+    if let Err(code) = result {
+        return code as i32;
+    }
+
+    MPI_SUCCESS
+}
+
+#[no_mangle]
+pub extern "C" fn MPI_Bsend(
+    buf: *const c_void,
+    cnt: i32,
+    dtype: MPI_Datatype,
+    dest: i32,
+    tag: i32,
+    comm: MPI_Comm,
+) -> i32 {
+    MPI_TRY!(cnt <= 0 || !buf.is_null(), comm, MPI_ERR_ARG);
+    let result = bsend(&TypedBuf::new(buf, cnt, dtype), dest, tag, comm);
+    if let Err(code) = result {
+        return code as i32;
+    }
+
+    MPI_SUCCESS
+}
+
+#[no_mangle]
+pub extern "C" fn MPI_Issend(
+    buf: *const c_void,
+    cnt: i32,
+    dtype: MPI_Datatype,
+    dest: i32,
+    tag: i32,
+    comm: MPI_Comm,
+    preq: *mut MPI_Request,
+) -> i32 {
+    MPI_TRY!(!preq.is_null(), comm, MPI_ERR_ARG);
     unsafe {
-        result = send(
-            from_raw_parts(buf as *const u8, dataLen as usize),
-            dest,
+        return match issend(&TypedBuf::new(buf, cnt, dtype), dest, tag, comm).and_then(|req| Request::handle(req)) {
+            Err(code) => code as i32,
+            Ok(handle) => {
+                *preq = handle;
+                MPI_SUCCESS
+            }
+        };
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn MPI_Irsend(
+    buf: *const c_void,
+    cnt: i32,
+    dtype: MPI_Datatype,
+    dest: i32,
+    tag: i32,
+    comm: MPI_Comm,
+    preq: *mut MPI_Request,
+) -> i32 {
+    MPI_TRY!(!preq.is_null(), comm, MPI_ERR_ARG);
+    unsafe {
+        return match irsend(&TypedBuf::new(buf, cnt, dtype), dest, tag, comm).and_then(|req| Request::handle(req)) {
+            Err(code) => code as i32,
+            Ok(handle) => {
+                *preq = handle;
+                MPI_SUCCESS
+            }
+        };
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn MPI_Ibsend(
+    buf: *const c_void,
+    cnt: i32,
+    dtype: MPI_Datatype,
+    dest: i32,
+    tag: i32,
+    comm: MPI_Comm,
+    preq: *mut MPI_Request,
+) -> i32 {
+    MPI_TRY!(!preq.is_null(), comm, MPI_ERR_ARG);
+    unsafe {
+        return match ibsend(&TypedBuf::new(buf, cnt, dtype), dest, tag, comm).and_then(|req| Request::handle(req)) {
+            Err(code) => code as i32,
+            Ok(handle) => {
+                *preq = handle;
+                MPI_SUCCESS
+            }
+        };
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn MPI_Buffer_attach(buf: *mut c_void, size: i32) -> i32 {
+    if let Err(code) = buffer_attach(buf, size) {
+        return code as i32;
+    }
+
+    MPI_SUCCESS
+}
+
+#[no_mangle]
+pub extern "C" fn MPI_Buffer_detach(pbuf: *mut c_void, psize: *mut i32) -> i32 {
+    MPI_TRY!(!pbuf.is_null() && !psize.is_null(), MPI_COMM_WORLD, MPI_ERR_ARG);
+    match buffer_detach() {
+        Err(code) => code as i32,
+        Ok((buf, size)) => {
+            unsafe {
+                *(pbuf as *mut *mut c_void) = buf;
+                *psize = size;
+            }
+            MPI_SUCCESS
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn MPI_Recv(
+    buf: *mut c_void,
+    cnt: i32,
+    dtype: MPI_Datatype,
+    src: i32,
+    tag: i32,
+    comm: MPI_Comm,
+    pstat: *mut MPI_Status,
+) -> i32 {
+    let result = unsafe {
+        recv(
+            &mut TypedBuf::new(buf, cnt, dtype),
+            src,
             tag,
             comm,
-        );
+            pstat.as_mut(),
+        )
+    };
+    if let Err(code) = result {
+        return code as i32;
     }
+
+    MPI_SUCCESS
+}
+
+#[no_mangle]
+pub extern "C" fn MPI_Sendrecv(
+    sbuf: *const c_void,
+    scnt: i32,
+    sdtype: MPI_Datatype,
+    dest: i32,
+    stag: i32,
+    rbuf: *mut c_void,
+    rcnt: i32,
+    rdtype: MPI_Datatype,
+    src: i32,
+    rtag: i32,
+    comm: MPI_Comm,
+    pstat: *mut MPI_Status,
+) -> i32 {
+    MPI_TRY!(Context::is_init(), comm, MPI_ERR_OTHER);
+
+    unsafe {
+        return match sendrecv(
+            &TypedBuf::new(sbuf, scnt, sdtype),
+            dest,
+            stag,
+            &mut TypedBuf::new(rbuf, rcnt, rdtype),
+            src,
+            rtag,
+            comm,
+        ) {
+            Ok(stat) => {
+                if !pstat.is_null() {
+                    pstat.write(stat);
+                }
+                MPI_SUCCESS
+            }
+            Err(code) => code as i32,
+        };
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn MPI_Sendrecv_replace(
+    buf: *mut c_void,
+    cnt: i32,
+    dtype: MPI_Datatype,
+    dest: i32,
+    stag: i32,
+    src: i32,
+    rtag: i32,
+    comm: MPI_Comm,
+    pstat: *mut MPI_Status,
+) -> i32 {
+    MPI_TRY!(Context::is_init(), comm, MPI_ERR_OTHER);
+
+    unsafe {
+        return match sendrecv_replace(
+            &mut TypedBuf::new(buf, cnt, dtype),
+            dest,
+            stag,
+            src,
+            rtag,
+            comm,
+        ) {
+            Ok(stat) => {
+                if !pstat.is_null() {
+                    pstat.write(stat);
+                }
+                MPI_SUCCESS
+            }
+            Err(code) => code as i32,
+        };
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn MPI_Iprobe(
+    src: i32,
+    tag: i32,
+    comm: MPI_Comm,
+    pflag: *mut i32,
+    pstat: *mut MPI_Status,
+) -> i32 {
+    MPI_TRY!(Context::is_init(), comm, MPI_ERR_OTHER);
+    MPI_TRY!(!pflag.is_null(), comm, MPI_ERR_ARG);
+
+    unsafe {
+        return match iprobe(src, tag, comm) {
+            Ok(Some(stat)) => {
+                pflag.write(1);
+                if !pstat.is_null() {
+                    pstat.write(stat);
+                }
+                MPI_SUCCESS
+            }
+            Ok(None) => {
+                pflag.write(0);
+                MPI_SUCCESS
+            }
+            Err(code) => code as i32,
+        };
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn MPI_Probe(src: i32, tag: i32, comm: MPI_Comm, pstat: *mut MPI_Status) -> i32 {
+    MPI_TRY!(Context::is_init(), comm, MPI_ERR_OTHER);
+
+    unsafe {
+        return match probe(src, tag, comm) {
+            Ok(stat) => {
+                if !pstat.is_null() {
+                    pstat.write(stat);
+                }
+                MPI_SUCCESS
+            }
+            Err(code) => code as i32,
+        };
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn MPI_Improbe(
+    src: i32,
+    tag: i32,
+    comm: MPI_Comm,
+    pflag: *mut i32,
+    pmsg: *mut MPI_Message,
+    pstat: *mut MPI_Status,
+) -> i32 {
+    MPI_TRY!(Context::is_init(), comm, MPI_ERR_OTHER);
+    MPI_TRY!(!pflag.is_null(), comm, MPI_ERR_ARG);
+    MPI_TRY!(!pmsg.is_null(), comm, MPI_ERR_ARG);
+
+    unsafe {
+        return match improbe(src, tag, comm) {
+            Ok(Some((msg, stat))) => {
+                pflag.write(1);
+                pmsg.write(msg);
+                if !pstat.is_null() {
+                    pstat.write(stat);
+                }
+                MPI_SUCCESS
+            }
+            Ok(None) => {
+                pflag.write(0);
+                MPI_SUCCESS
+            }
+            Err(code) => code as i32,
+        };
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn MPI_Mprobe(
+    src: i32,
+    tag: i32,
+    comm: MPI_Comm,
+    pmsg: *mut MPI_Message,
+    pstat: *mut MPI_Status,
+) -> i32 {
+    MPI_TRY!(Context::is_init(), comm, MPI_ERR_OTHER);
+    MPI_TRY!(!pmsg.is_null(), comm, MPI_ERR_ARG);
+
+    unsafe {
+        return match mprobe(src, tag, comm) {
+            Ok((msg, stat)) => {
+                pmsg.write(msg);
+                if !pstat.is_null() {
+                    pstat.write(stat);
+                }
+                MPI_SUCCESS
+            }
+            Err(code) => code as i32,
+        };
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn MPI_Imrecv(
+    buf: *mut c_void,
+    cnt: i32,
+    dtype: MPI_Datatype,
+    pmsg: *mut MPI_Message,
+    preq: *mut MPI_Request,
+) -> i32 {
+    MPI_TRY!(Context::is_init(), MPI_COMM_WORLD, MPI_ERR_OTHER);
+    MPI_TRY!(!pmsg.is_null(), MPI_COMM_WORLD, MPI_ERR_ARG);
+    MPI_TRY!(!preq.is_null(), MPI_COMM_WORLD, MPI_ERR_ARG);
+
+    unsafe {
+        return match imrecv(&mut TypedBuf::new(buf, cnt, dtype), *pmsg).and_then(|req| Request::handle(req)) {
+            Err(code) => code as i32,
+            Ok(handle) => {
+                *preq = handle;
+                *pmsg = MPI_MESSAGE_NULL;
+                MPI_SUCCESS
+            }
+        };
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn MPI_Mrecv(
+    buf: *mut c_void,
+    cnt: i32,
+    dtype: MPI_Datatype,
+    pmsg: *mut MPI_Message,
+    pstat: *mut MPI_Status,
+) -> i32 {
+    MPI_TRY!(Context::is_init(), MPI_COMM_WORLD, MPI_ERR_OTHER);
+    MPI_TRY!(!pmsg.is_null(), MPI_COMM_WORLD, MPI_ERR_ARG);
+
+    let result = unsafe { mrecv(&mut TypedBuf::new(buf, cnt, dtype), *pmsg, pstat.as_mut()) };
     if let Err(code) = result {
         return code as i32;
     }
-
-    MPI_SUCCESS
+    unsafe { *pmsg = MPI_MESSAGE_NULL };
+
+    MPI_SUCCESS
+}
+
+/// `MPI_STATUSES_IGNORE` is passed as a null array.
+fn statuses<'a>(pstat: *mut MPI_Status, cnt: i32) -> Option<&'a mut [MPI_Status]> {
+    if pstat.is_null() {
+        None
+    } else {
+        Some(unsafe { from_raw_parts_mut(pstat, cnt as usize) })
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn MPI_Test(preq: *mut MPI_Request, pflag: *mut i32, pstat: *mut MPI_Status) -> i32 {
+    MPI_TRY!(!preq.is_null(), MPI_COMM_WORLD, MPI_ERR_ARG);
+    MPI_TRY!(!pflag.is_null(), MPI_COMM_WORLD, MPI_ERR_ARG);
+    if let Err(code) = unsafe { Request::test_handle(&mut *preq, &mut *pflag, pstat.as_mut()) } {
+        return code as i32;
+    } else {
+        return MPI_SUCCESS;
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn MPI_Wait(preq: *mut MPI_Request, pstat: *mut MPI_Status) -> i32 {
+    MPI_TRY!(!preq.is_null(), MPI_COMM_WORLD, MPI_ERR_ARG);
+    if let Err(code) = unsafe { Request::wait_handle(&mut *preq, pstat.as_mut()) } {
+        return code as i32;
+    } else {
+        return MPI_SUCCESS;
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn MPI_Waitall(cnt: i32, preq: *mut MPI_Request, pstat: *mut MPI_Status) -> i32 {
+    MPI_TRY!(!preq.is_null(), MPI_COMM_WORLD, MPI_ERR_ARG);
+    MPI_TRY!(cnt >= 0, MPI_COMM_WORLD, MPI_ERR_COUNT);
+    if cnt == 0 {
+        return MPI_SUCCESS;
+    }
+    if let Err(code) = Request::wait_all(
+        unsafe { from_raw_parts_mut(preq, cnt as usize) },
+        statuses(pstat, cnt),
+    ) {
+        return code as i32;
+    } else {
+        return MPI_SUCCESS;
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn MPI_Testall(
+    cnt: i32,
+    preq: *mut MPI_Request,
+    pflag: *mut i32,
+    pstat: *mut MPI_Status,
+) -> i32 {
+    MPI_TRY!(!preq.is_null() && !pflag.is_null(), MPI_COMM_WORLD, MPI_ERR_ARG);
+    MPI_TRY!(cnt >= 0, MPI_COMM_WORLD, MPI_ERR_COUNT);
+    if let Err(code) = Request::test_all(
+        unsafe { from_raw_parts_mut(preq, cnt as usize) },
+        unsafe { &mut *pflag },
+        statuses(pstat, cnt),
+    ) {
+        return code as i32;
+    } else {
+        return MPI_SUCCESS;
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn MPI_Waitany(
+    cnt: i32,
+    preq: *mut MPI_Request,
+    pidx: *mut i32,
+    pstat: *mut MPI_Status,
+) -> i32 {
+    MPI_TRY!(!preq.is_null() && !pidx.is_null(), MPI_COMM_WORLD, MPI_ERR_ARG);
+    MPI_TRY!(cnt >= 0, MPI_COMM_WORLD, MPI_ERR_COUNT);
+    if let Err(code) = Request::wait_any(
+        unsafe { from_raw_parts_mut(preq, cnt as usize) },
+        unsafe { &mut *pidx },
+        unsafe { pstat.as_mut() },
+    ) {
+        return code as i32;
+    } else {
+        return MPI_SUCCESS;
+    }
 }
 
 #[no_mangle]
-pub extern "C" fn MPI_Recv(
-    buf: *mut c_void,
+pub extern "C" fn MPI_Testany(
     cnt: i32,
-    dtype: MPI_Datatype,
-    src: i32,
-    tag: i32,
-    comm: MPI_Comm,
+    preq: *mut MPI_Request,
+    pidx: *mut i32,
+    pflag: *mut i32,
     pstat: *mut MPI_Status,
 ) -> i32 {
-    let dataLen = type_size(dtype).unwrap() * cnt;
-    let result = unsafe {
-        recv(
-            from_raw_parts_mut(buf as *mut u8, dataLen as usize),
-            src,
-            tag,
-            comm,
-            pstat.as_mut(),
-        )
-    };
-    if let Err(code) = result {
+    MPI_TRY!(
+        !preq.is_null() && !pidx.is_null() && !pflag.is_null(),
+        MPI_COMM_WORLD,
+        MPI_ERR_ARG
+    );
+    MPI_TRY!(cnt >= 0, MPI_COMM_WORLD, MPI_ERR_COUNT);
+    if let Err(code) = Request::test_any(
+        unsafe { from_raw_parts_mut(preq, cnt as usize) },
+        unsafe { &mut *pidx },
+        unsafe { &mut *pflag },
+        unsafe { pstat.as_mut() },
+    ) {
         return code as i32;
+    } else {
+        return MPI_SUCCESS;
     }
-
-    MPI_SUCCESS
 }
 
 #[no_mangle]
-pub extern "C" fn MPI_Sendrecv(
-    sbuf: *const c_void,
-    scnt: i32,
-    sdtype: MPI_Datatype,
-    dest: i32,
-    stag: i32,
-    rbuf: *mut c_void,
-    rcnt: i32,
-    rdtype: MPI_Datatype,
-    src: i32,
-    rtag: i32,
-    comm: MPI_Comm,
+pub extern "C" fn MPI_Waitsome(
+    cnt: i32,
+    preq: *mut MPI_Request,
+    pout: *mut i32,
+    pidx: *mut i32,
     pstat: *mut MPI_Status,
 ) -> i32 {
-    MPI_CHECK!(Context::is_init(), comm, MPI_ERR_OTHER);
-    MPI_CHECK!(!pstat.is_null(), MPI_COMM_WORLD, MPI_ERR_ARG);
-
-    let send_len = (type_size(sdtype).unwrap() * scnt) as usize;
-    let recv_len = (type_size(rdtype).unwrap() * rcnt) as usize;
-
-    unsafe {
-        return match sendrecv(
-            from_raw_parts(sbuf as *const u8, send_len),
-            dest,
-            stag,
-            from_raw_parts_mut(rbuf as *mut u8, recv_len),
-            src,
-            rtag,
-            comm,
-        ) {
-            Ok(stat) => {
-                if !pstat.is_null() {
-                    pstat.write(stat);
-                }
-                MPI_SUCCESS
-            }
-            Err(code) => code as i32,
-        };
+    MPI_TRY!(
+        !preq.is_null() && !pout.is_null() && !pidx.is_null(),
+        MPI_COMM_WORLD,
+        MPI_ERR_ARG
+    );
+    MPI_TRY!(cnt >= 0, MPI_COMM_WORLD, MPI_ERR_COUNT);
+    if let Err(code) = Request::wait_some(
+        unsafe { from_raw_parts_mut(preq, cnt as usize) },
+        unsafe { &mut *pout },
+        unsafe { from_raw_parts_mut(pidx, cnt as usize) },
+        statuses(pstat, cnt),
+    ) {
+        return code as i32;
+    } else {
+        return MPI_SUCCESS;
     }
 }
 
 #[no_mangle]
-pub extern "C" fn MPI_Test(preq: *mut MPI_Request, pflag: *mut i32, pstat: *mut MPI_Status) -> i32 {
-    MPI_CHECK!(!preq.is_null(), MPI_COMM_WORLD, MPI_ERR_ARG);
-    MPI_CHECK!(!pflag.is_null(), MPI_COMM_WORLD, MPI_ERR_ARG);
-    if let Err(code) = unsafe { Request::test(*preq, &mut *pflag, pstat.as_mut()) } {
+pub extern "C" fn MPI_Testsome(
+    cnt: i32,
+    preq: *mut MPI_Request,
+    pout: *mut i32,
+    pidx: *mut i32,
+    pstat: *mut MPI_Status,
+) -> i32 {
+    MPI_TRY!(
+        !preq.is_null() && !pout.is_null() && !pidx.is_null(),
+        MPI_COMM_WORLD,
+        MPI_ERR_ARG
+    );
+    MPI_TRY!(cnt >= 0, MPI_COMM_WORLD, MPI_ERR_COUNT);
+    if let Err(code) = Request::test_some(
+        unsafe { from_raw_parts_mut(preq, cnt as usize) },
+        unsafe { &mut *pout },
+        unsafe { from_raw_parts_mut(pidx, cnt as usize) },
+        statuses(pstat, cnt),
+    ) {
         return code as i32;
     } else {
         return MPI_SUCCESS;
@@ -173,9 +681,9 @@ pub extern "C" fn MPI_Test(preq: *mut MPI_Request, pflag: *mut i32, pstat: *mut
 }
 
 #[no_mangle]
-pub extern "C" fn MPI_Wait(preq: *mut MPI_Request, pstat: *mut MPI_Status) -> i32 {
-    MPI_CHECK!(!preq.is_null(), MPI_COMM_WORLD, MPI_ERR_ARG);
-    if let Err(code) = unsafe { (**preq).wait(pstat.as_mut()) } {
+pub extern "C" fn MPI_Cancel(preq: *mut MPI_Request) -> i32 {
+    MPI_TRY!(!preq.is_null(), MPI_COMM_WORLD, MPI_ERR_ARG);
+    if let Err(code) = Request::cancel(unsafe { *preq }) {
         return code as i32;
     } else {
         return MPI_SUCCESS;
@@ -183,26 +691,44 @@ pub extern "C" fn MPI_Wait(preq: *mut MPI_Request, pstat: *mut MPI_Status) -> i3
 }
 
 #[no_mangle]
-pub extern "C" fn MPI_Waitall(cnt: i32, preq: *mut MPI_Request, pstat: *mut MPI_Status) -> i32 {
-    MPI_CHECK!(
-        !preq.is_null() && !pstat.is_null(),
-        MPI_COMM_WORLD,
-        MPI_ERR_ARG
-    );
-    MPI_CHECK!(cnt >= 0, MPI_COMM_WORLD, MPI_ERR_COUNT);
-    if cnt == 0 {
-        return MPI_SUCCESS;
-    }
-    if let Err(code) = Request::wait_all(
-        unsafe { std::slice::from_raw_parts_mut(preq, cnt as usize) },
-        unsafe { std::slice::from_raw_parts_mut(pstat, cnt as usize) },
-    ) {
+pub extern "C" fn MPI_Test_cancelled(pstat: *const MPI_Status, pflag: *mut i32) -> i32 {
+    MPI_TRY!(!pstat.is_null() && !pflag.is_null(), MPI_COMM_WORLD, MPI_ERR_ARG);
+    unsafe { *pflag = ((*pstat).cancelled != 0) as i32 };
+    MPI_SUCCESS
+}
+
+#[no_mangle]
+pub extern "C" fn MPI_Start(preq: *mut MPI_Request) -> i32 {
+    MPI_TRY!(!preq.is_null(), MPI_COMM_WORLD, MPI_ERR_ARG);
+    if let Err(code) = Request::start(unsafe { *preq }) {
         return code as i32;
     } else {
         return MPI_SUCCESS;
     }
 }
 
+#[no_mangle]
+pub extern "C" fn MPI_Startall(cnt: i32, preq: *mut MPI_Request) -> i32 {
+    MPI_TRY!(!preq.is_null(), MPI_COMM_WORLD, MPI_ERR_ARG);
+    MPI_TRY!(cnt >= 0, MPI_COMM_WORLD, MPI_ERR_COUNT);
+    for i in 0..cnt as usize {
+        if let Err(code) = Request::start(unsafe { *preq.add(i) }) {
+            return code as i32;
+        }
+    }
+    MPI_SUCCESS
+}
+
+#[no_mangle]
+pub extern "C" fn MPI_Request_free(preq: *mut MPI_Request) -> i32 {
+    MPI_TRY!(!preq.is_null(), MPI_COMM_WORLD, MPI_ERR_ARG);
+    if let Err(code) = Request::free(unsafe { *preq }) {
+        return code as i32;
+    }
+    unsafe { *preq = MPI_REQUEST_NULL };
+    MPI_SUCCESS
+}
+
 #[no_mangle]
 pub extern "C" fn MPI_Barrier(comm: MPI_Comm) -> i32 {
     if let Err(code) = Context::barrier()(comm) {
@@ -219,9 +745,12 @@ pub extern "C" fn MPI_Bcast(
     root: i32,
     comm: MPI_Comm,
 ) -> i32 {
-    let dataLen = type_size(dtype).unwrap() * cnt;
+    let data_len = match contiguous_len(dtype, cnt, comm) {
+        Ok(len) => len,
+        Err(code) => return code as i32,
+    };
     if let Err(code) = Context::bcast()(
-        unsafe { from_raw_parts_mut(buf as *mut u8, dataLen as usize) },
+        unsafe { from_raw_parts_mut(buf as *mut u8, data_len) },
         root,
         comm,
     ) {
@@ -240,7 +769,10 @@ pub extern "C" fn MPI_Reduce(
     root: i32,
     comm: MPI_Comm,
 ) -> i32 {
-    let data_len = (type_size(dtype).unwrap() * cnt) as usize;
+    let data_len = match contiguous_len(dtype, cnt, comm) {
+        Ok(len) => len,
+        Err(code) => return code as i32,
+    };
 
     unsafe {
         if let Err(code) = Context::reduce()(
@@ -266,7 +798,10 @@ pub extern "C" fn MPI_Allreduce(
     op: MPI_Op,
     comm: MPI_Comm,
 ) -> i32 {
-    let data_len = (type_size(dtype).unwrap() * cnt) as usize;
+    let data_len = match contiguous_len(dtype, cnt, comm) {
+        Ok(len) => len,
+        Err(code) => return code as i32,
+    };
 
     unsafe {
         if let Err(code) = Context::allreduce()(
@@ -293,8 +828,13 @@ pub extern "C" fn MPI_Gather(
     root: i32,
     comm: MPI_Comm,
 ) -> i32 {
-    let send_len = (type_size(sdtype).unwrap() * scnt) as usize;
-    let recv_len = (type_size(rdtype).unwrap() * rcnt * Context::comm_size(comm)) as usize;
+    let (send_len, recv_len) = match (
+        contiguous_len(sdtype, scnt, comm),
+        contiguous_len(rdtype, rcnt, comm),
+    ) {
+        (Ok(send_len), Ok(recv_len)) => (send_len, recv_len * Context::comm_size(comm) as usize),
+        (Err(code), _) | (_, Err(code)) => return code as i32,
+    };
 
     unsafe {
         if let Err(code) = Context::gather()(
@@ -319,8 +859,13 @@ pub extern "C" fn MPI_Allgather(
     rdtype: MPI_Datatype,
     comm: MPI_Comm,
 ) -> i32 {
-    let send_len = (type_size(sdtype).unwrap() * scnt) as usize;
-    let recv_len = (type_size(rdtype).unwrap() * rcnt * Context::comm_size(comm)) as usize;
+    let (send_len, recv_len) = match (
+        contiguous_len(sdtype, scnt, comm),
+        contiguous_len(rdtype, rcnt, comm),
+    ) {
+        (Ok(send_len), Ok(recv_len)) => (send_len, recv_len * Context::comm_size(comm) as usize),
+        (Err(code), _) | (_, Err(code)) => return code as i32,
+    };
 
     unsafe {
         if let Err(code) = Context::allgather()(
@@ -336,9 +881,9 @@ pub extern "C" fn MPI_Allgather(
 
 #[no_mangle]
 pub extern "C" fn MPI_Comm_size(comm: MPI_Comm, psize: *mut i32) -> i32 {
-    MPI_CHECK!(Context::is_init(), MPI_COMM_WORLD, MPI_ERR_OTHER);
+    MPI_TRY!(Context::is_init(), MPI_COMM_WORLD, MPI_ERR_OTHER);
     crate::MPI_CHECK_COMM!(comm);
-    MPI_CHECK!(!psize.is_null(), comm, MPI_ERR_ARG);
+    MPI_TRY!(!psize.is_null(), comm, MPI_ERR_ARG);
 
     unsafe {
         psize.write(Context::comm_size(comm));
@@ -349,9 +894,9 @@ pub extern "C" fn MPI_Comm_size(comm: MPI_Comm, psize: *mut i32) -> i32 {
 
 #[no_mangle]
 pub extern "C" fn MPI_Comm_rank(comm: MPI_Comm, prank: *mut i32) -> i32 {
-    MPI_CHECK!(Context::is_init(), MPI_COMM_WORLD, MPI_ERR_OTHER);
+    MPI_TRY!(Context::is_init(), MPI_COMM_WORLD, MPI_ERR_OTHER);
     crate::MPI_CHECK_COMM!(comm);
-    MPI_CHECK!(!prank.is_null(), comm, MPI_ERR_ARG);
+    MPI_TRY!(!prank.is_null(), comm, MPI_ERR_ARG);
 
     unsafe {
         prank.write(Context::comm_rank(comm));
@@ -362,9 +907,9 @@ pub extern "C" fn MPI_Comm_rank(comm: MPI_Comm, prank: *mut i32) -> i32 {
 
 #[no_mangle]
 pub extern "C" fn MPI_Comm_dup(comm: MPI_Comm, pcomm: *mut MPI_Comm) -> i32 {
-    MPI_CHECK!(Context::is_init(), MPI_COMM_WORLD, MPI_ERR_OTHER);
+    MPI_TRY!(Context::is_init(), MPI_COMM_WORLD, MPI_ERR_OTHER);
     crate::MPI_CHECK_COMM!(comm);
-    MPI_CHECK!(!pcomm.is_null(), comm, MPI_ERR_ARG);
+    MPI_TRY!(!pcomm.is_null(), comm, MPI_ERR_ARG);
 
     let code = Context::comm().comm_dup(comm, pcomm);
     if code != MPI_SUCCESS {
@@ -376,10 +921,10 @@ pub extern "C" fn MPI_Comm_dup(comm: MPI_Comm, pcomm: *mut MPI_Comm) -> i32 {
 
 #[no_mangle]
 pub extern "C" fn MPI_Comm_split(comm: MPI_Comm, col: i32, key: i32, pcomm: *mut MPI_Comm) -> i32 {
-    MPI_CHECK!(Context::is_init(), MPI_COMM_WORLD, MPI_ERR_OTHER);
+    MPI_TRY!(Context::is_init(), MPI_COMM_WORLD, MPI_ERR_OTHER);
     crate::MPI_CHECK_COMM!(comm);
-    MPI_CHECK!(col >= 0 || col == MPI_UNDEFINED, comm, MPI_ERR_ARG);
-    MPI_CHECK!(!pcomm.is_null(), comm, MPI_ERR_ARG);
+    MPI_TRY!(col >= 0 || col == MPI_UNDEFINED, comm, MPI_ERR_ARG);
+    MPI_TRY!(!pcomm.is_null(), comm, MPI_ERR_ARG);
 
     let code = Context::comm().comm_split(comm, col, key, pcomm);
     if let Err(code) = code {
@@ -392,9 +937,9 @@ pub extern "C" fn MPI_Comm_split(comm: MPI_Comm, col: i32, key: i32, pcomm: *mut
 
 #[no_mangle]
 pub extern "C" fn MPI_Comm_get_errhandler(comm: MPI_Comm, perrh: *mut MPI_Errhandler) -> i32 {
-    MPI_CHECK!(Context::is_init(), MPI_COMM_WORLD, MPI_ERR_OTHER);
+    MPI_TRY!(Context::is_init(), MPI_COMM_WORLD, MPI_ERR_OTHER);
     crate::MPI_CHECK_COMM!(comm);
-    MPI_CHECK!(!perrh.is_null(), comm, MPI_ERR_ARG);
+    MPI_TRY!(!perrh.is_null(), comm, MPI_ERR_ARG);
 
     unsafe { perrh.write(Context::comm().err_handler(comm)) }
 
@@ -403,9 +948,11 @@ pub extern "C" fn MPI_Comm_get_errhandler(comm: MPI_Comm, perrh: *mut MPI_Errhan
 
 #[no_mangle]
 pub extern "C" fn MPI_Comm_set_errhandler(comm: MPI_Comm, errh: MPI_Errhandler) -> i32 {
-    MPI_CHECK!(Context::is_init(), MPI_COMM_WORLD, MPI_ERR_OTHER);
+    MPI_TRY!(Context::is_init(), MPI_COMM_WORLD, MPI_ERR_OTHER);
     crate::MPI_CHECK_COMM!(comm);
-    //  MPI_CHECK_ERRH!(comm, errh);
+    if let Err(code) = HandlerContext::check(comm, errh) {
+        return code as i32;
+    }
 
     Context::comm().set_err_handler(comm, errh);
 
@@ -420,8 +967,8 @@ pub extern "C" fn MPI_Comm_call_errhandler(comm: MPI_Comm, code: crate::types::M
 
 #[no_mangle]
 pub extern "C" fn MPI_Type_size(dtype: MPI_Datatype, psize: *mut i32) -> i32 {
-    MPI_CHECK!(Context::is_init(), MPI_COMM_WORLD, MPI_ERR_OTHER);
-    MPI_CHECK!(!psize.is_null(), MPI_COMM_WORLD, MPI_ERR_ARG);
+    MPI_TRY!(Context::is_init(), MPI_COMM_WORLD, MPI_ERR_OTHER);
+    MPI_TRY!(!psize.is_null(), MPI_COMM_WORLD, MPI_ERR_ARG);
 
     unsafe {
         return match metatypes::type_size(dtype) {
@@ -434,21 +981,337 @@ pub extern "C" fn MPI_Type_size(dtype: MPI_Datatype, psize: *mut i32) -> i32 {
     }
 }
 
+#[no_mangle]
+pub extern "C" fn MPI_Type_get_extent(
+    dtype: MPI_Datatype,
+    plb: *mut MPI_Aint,
+    pextent: *mut MPI_Aint,
+) -> i32 {
+    MPI_TRY!(Context::is_init(), MPI_COMM_WORLD, MPI_ERR_OTHER);
+    MPI_TRY!(!plb.is_null(), MPI_COMM_WORLD, MPI_ERR_ARG);
+    MPI_TRY!(!pextent.is_null(), MPI_COMM_WORLD, MPI_ERR_ARG);
+
+    match metatypes::type_extent(dtype) {
+        Ok((lb, extent)) => unsafe {
+            plb.write(lb);
+            pextent.write(extent);
+            MPI_SUCCESS
+        },
+        Err(code) => code as i32,
+    }
+}
+
+/// An array of `cnt` elements passed from C, which may be null if empty.
+fn array<'a, T>(ptr: *const T, cnt: i32) -> &'a [T] {
+    if cnt <= 0 {
+        &[]
+    } else {
+        unsafe { from_raw_parts(ptr, cnt as usize) }
+    }
+}
+
+/// Registers a new derived datatype and stores its handle in `pnew`.
+fn new_type(map: Result<Typemap, MpiError>, pnew: *mut MPI_Datatype) -> i32 {
+    match map {
+        Ok(map) => {
+            unsafe { pnew.write(Context::types().insert(map)) };
+            MPI_SUCCESS
+        }
+        Err(code) => code as i32,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn MPI_Type_contiguous(cnt: i32, old: MPI_Datatype, pnew: *mut MPI_Datatype) -> i32 {
+    MPI_TRY!(Context::is_init(), MPI_COMM_WORLD, MPI_ERR_OTHER);
+    MPI_TRY!(!pnew.is_null(), MPI_COMM_WORLD, MPI_ERR_ARG);
+
+    new_type(typemap::contiguous(cnt, old), pnew)
+}
+
+#[no_mangle]
+pub extern "C" fn MPI_Type_vector(
+    cnt: i32,
+    blocklen: i32,
+    stride: i32,
+    old: MPI_Datatype,
+    pnew: *mut MPI_Datatype,
+) -> i32 {
+    MPI_TRY!(Context::is_init(), MPI_COMM_WORLD, MPI_ERR_OTHER);
+    MPI_TRY!(!pnew.is_null(), MPI_COMM_WORLD, MPI_ERR_ARG);
+
+    new_type(typemap::vector(cnt, blocklen, stride, old), pnew)
+}
+
+#[no_mangle]
+pub extern "C" fn MPI_Type_create_hvector(
+    cnt: i32,
+    blocklen: i32,
+    stride: MPI_Aint,
+    old: MPI_Datatype,
+    pnew: *mut MPI_Datatype,
+) -> i32 {
+    MPI_TRY!(Context::is_init(), MPI_COMM_WORLD, MPI_ERR_OTHER);
+    MPI_TRY!(!pnew.is_null(), MPI_COMM_WORLD, MPI_ERR_ARG);
+
+    new_type(typemap::hvector(cnt, blocklen, stride, old), pnew)
+}
+
+#[no_mangle]
+pub extern "C" fn MPI_Type_indexed(
+    cnt: i32,
+    pblocklens: *const i32,
+    pdisps: *const i32,
+    old: MPI_Datatype,
+    pnew: *mut MPI_Datatype,
+) -> i32 {
+    MPI_TRY!(Context::is_init(), MPI_COMM_WORLD, MPI_ERR_OTHER);
+    MPI_TRY!(cnt >= 0, MPI_COMM_WORLD, MPI_ERR_COUNT);
+    MPI_TRY!(!pnew.is_null(), MPI_COMM_WORLD, MPI_ERR_ARG);
+
+    let map = typemap::indexed(array(pblocklens, cnt), array(pdisps, cnt), old);
+    new_type(map, pnew)
+}
+
+#[no_mangle]
+pub extern "C" fn MPI_Type_create_struct(
+    cnt: i32,
+    pblocklens: *const i32,
+    pdisps: *const MPI_Aint,
+    ptypes: *const MPI_Datatype,
+    pnew: *mut MPI_Datatype,
+) -> i32 {
+    MPI_TRY!(Context::is_init(), MPI_COMM_WORLD, MPI_ERR_OTHER);
+    MPI_TRY!(cnt >= 0, MPI_COMM_WORLD, MPI_ERR_COUNT);
+    MPI_TRY!(!pnew.is_null(), MPI_COMM_WORLD, MPI_ERR_ARG);
+
+    let map = typemap::create_struct(
+        array(pblocklens, cnt),
+        array(pdisps, cnt),
+        array(ptypes, cnt),
+    );
+    new_type(map, pnew)
+}
+
+#[no_mangle]
+pub extern "C" fn MPI_Type_create_subarray(
+    ndims: i32,
+    psizes: *const i32,
+    psubsizes: *const i32,
+    pstarts: *const i32,
+    order: i32,
+    old: MPI_Datatype,
+    pnew: *mut MPI_Datatype,
+) -> i32 {
+    MPI_TRY!(Context::is_init(), MPI_COMM_WORLD, MPI_ERR_OTHER);
+    MPI_TRY!(ndims > 0, MPI_COMM_WORLD, MPI_ERR_ARG);
+    MPI_TRY!(!pnew.is_null(), MPI_COMM_WORLD, MPI_ERR_ARG);
+
+    let map = typemap::subarray(
+        array(psizes, ndims),
+        array(psubsizes, ndims),
+        array(pstarts, ndims),
+        order,
+        old,
+    );
+    new_type(map, pnew)
+}
+
+#[no_mangle]
+pub extern "C" fn MPI_Type_commit(pdtype: *mut MPI_Datatype) -> i32 {
+    MPI_TRY!(Context::is_init(), MPI_COMM_WORLD, MPI_ERR_OTHER);
+    MPI_TRY!(!pdtype.is_null(), MPI_COMM_WORLD, MPI_ERR_ARG);
+
+    if let Err(code) = metatypes::type_commit(unsafe { *pdtype }) {
+        return code as i32;
+    }
+    MPI_SUCCESS
+}
+
+#[no_mangle]
+pub extern "C" fn MPI_Type_free(pdtype: *mut MPI_Datatype) -> i32 {
+    MPI_TRY!(Context::is_init(), MPI_COMM_WORLD, MPI_ERR_OTHER);
+    MPI_TRY!(!pdtype.is_null(), MPI_COMM_WORLD, MPI_ERR_ARG);
+
+    if let Err(code) = metatypes::type_free(unsafe { *pdtype }) {
+        return code as i32;
+    }
+    unsafe { *pdtype = MPI_DATATYPE_NULL };
+    MPI_SUCCESS
+}
+
+#[no_mangle]
+pub extern "C" fn MPI_Pack(
+    inbuf: *const c_void,
+    incnt: i32,
+    dtype: MPI_Datatype,
+    outbuf: *mut c_void,
+    outsize: i32,
+    ppos: *mut i32,
+    comm: MPI_Comm,
+) -> i32 {
+    MPI_TRY!(Context::is_init(), comm, MPI_ERR_OTHER);
+    MPI_TRY!(!outbuf.is_null() && outsize >= 0, comm, MPI_ERR_ARG);
+    MPI_TRY!(!ppos.is_null(), comm, MPI_ERR_ARG);
+
+    let out = unsafe { from_raw_parts_mut(outbuf as *mut u8, outsize as usize) };
+    match pack::pack(inbuf, incnt, dtype, out, unsafe { &mut *ppos }, comm) {
+        Ok(()) => MPI_SUCCESS,
+        Err(code) => code as i32,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn MPI_Unpack(
+    inbuf: *const c_void,
+    insize: i32,
+    ppos: *mut i32,
+    outbuf: *mut c_void,
+    outcnt: i32,
+    dtype: MPI_Datatype,
+    comm: MPI_Comm,
+) -> i32 {
+    MPI_TRY!(Context::is_init(), comm, MPI_ERR_OTHER);
+    MPI_TRY!(!inbuf.is_null() && insize >= 0, comm, MPI_ERR_ARG);
+    MPI_TRY!(!ppos.is_null(), comm, MPI_ERR_ARG);
+
+    let input = unsafe { from_raw_parts(inbuf as *const u8, insize as usize) };
+    match pack::unpack(input, unsafe { &mut *ppos }, outbuf, outcnt, dtype, comm) {
+        Ok(()) => MPI_SUCCESS,
+        Err(code) => code as i32,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn MPI_Pack_size(
+    incnt: i32,
+    dtype: MPI_Datatype,
+    comm: MPI_Comm,
+    psize: *mut i32,
+) -> i32 {
+    MPI_TRY!(Context::is_init(), comm, MPI_ERR_OTHER);
+    MPI_TRY!(!psize.is_null(), comm, MPI_ERR_ARG);
+
+    match pack::pack_size(incnt, dtype, comm) {
+        Ok(size) => {
+            unsafe { psize.write(size) };
+            MPI_SUCCESS
+        }
+        Err(code) => code as i32,
+    }
+}
+
+/// The name of a data representation, empty for a null pointer.
+fn datarep<'a>(pdatarep: *const c_char) -> &'a [u8] {
+    if pdatarep.is_null() {
+        return &[];
+    }
+    unsafe { CStr::from_ptr(pdatarep) }.to_bytes()
+}
+
+#[no_mangle]
+pub extern "C" fn MPI_Pack_external(
+    pdatarep: *const c_char,
+    inbuf: *const c_void,
+    incnt: i32,
+    dtype: MPI_Datatype,
+    outbuf: *mut c_void,
+    outsize: MPI_Aint,
+    ppos: *mut MPI_Aint,
+) -> i32 {
+    MPI_TRY!(Context::is_init(), MPI_COMM_WORLD, MPI_ERR_OTHER);
+    if let Err(code) = external::check_datarep(datarep(pdatarep), MPI_COMM_WORLD) {
+        return code as i32;
+    }
+    MPI_TRY!(
+        !outbuf.is_null() && outsize >= 0,
+        MPI_COMM_WORLD,
+        MPI_ERR_ARG
+    );
+    MPI_TRY!(!ppos.is_null(), MPI_COMM_WORLD, MPI_ERR_ARG);
+
+    let out = unsafe { from_raw_parts_mut(outbuf as *mut u8, outsize as usize) };
+    let pos = unsafe { &mut *ppos };
+    match external::pack_external(inbuf, incnt, dtype, out, pos, MPI_COMM_WORLD) {
+        Ok(()) => MPI_SUCCESS,
+        Err(code) => code as i32,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn MPI_Unpack_external(
+    pdatarep: *const c_char,
+    inbuf: *const c_void,
+    insize: MPI_Aint,
+    ppos: *mut MPI_Aint,
+    outbuf: *mut c_void,
+    outcnt: i32,
+    dtype: MPI_Datatype,
+) -> i32 {
+    MPI_TRY!(Context::is_init(), MPI_COMM_WORLD, MPI_ERR_OTHER);
+    if let Err(code) = external::check_datarep(datarep(pdatarep), MPI_COMM_WORLD) {
+        return code as i32;
+    }
+    MPI_TRY!(
+        !inbuf.is_null() && insize >= 0,
+        MPI_COMM_WORLD,
+        MPI_ERR_ARG
+    );
+    MPI_TRY!(!ppos.is_null(), MPI_COMM_WORLD, MPI_ERR_ARG);
+
+    let input = unsafe { from_raw_parts(inbuf as *const u8, insize as usize) };
+    let pos = unsafe { &mut *ppos };
+    match external::unpack_external(input, pos, outbuf, outcnt, dtype, MPI_COMM_WORLD) {
+        Ok(()) => MPI_SUCCESS,
+        Err(code) => code as i32,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn MPI_Pack_external_size(
+    pdatarep: *const c_char,
+    incnt: i32,
+    dtype: MPI_Datatype,
+    psize: *mut MPI_Aint,
+) -> i32 {
+    MPI_TRY!(Context::is_init(), MPI_COMM_WORLD, MPI_ERR_OTHER);
+    if let Err(code) = external::check_datarep(datarep(pdatarep), MPI_COMM_WORLD) {
+        return code as i32;
+    }
+    MPI_TRY!(!psize.is_null(), MPI_COMM_WORLD, MPI_ERR_ARG);
+
+    match external::pack_external_size(incnt, dtype, MPI_COMM_WORLD) {
+        Ok(size) => {
+            unsafe { psize.write(size) };
+            MPI_SUCCESS
+        }
+        Err(code) => code as i32,
+    }
+}
+
 #[no_mangle]
 pub extern "C" fn MPI_Get_count(
     pstat: *const MPI_Status,
     dtype: MPI_Datatype,
     pcnt: *mut i32,
 ) -> i32 {
-    MPI_CHECK!(Context::is_init(), MPI_COMM_WORLD, MPI_ERR_OTHER);
-    MPI_CHECK!(!pstat.is_null(), MPI_COMM_WORLD, MPI_ERR_ARG);
-    MPI_CHECK!(!pcnt.is_null(), MPI_COMM_WORLD, MPI_ERR_ARG);
+    MPI_TRY!(Context::is_init(), MPI_COMM_WORLD, MPI_ERR_OTHER);
+    MPI_TRY!(!pstat.is_null(), MPI_COMM_WORLD, MPI_ERR_ARG);
+    MPI_TRY!(!pcnt.is_null(), MPI_COMM_WORLD, MPI_ERR_ARG);
 
     return match metatypes::type_size(dtype) {
         Ok(size) => unsafe {
-            pcnt.write((*pstat).cnt / size);
+            // Bytes that don't make up whole elements have no count.
+            let bytes = (*pstat).cnt;
+            let cnt = match size {
+                0 if bytes == 0 => 0,
+                0 => MPI_UNDEFINED,
+                _ if bytes % size != 0 => MPI_UNDEFINED,
+                _ => bytes / size,
+            };
+            pcnt.write(cnt);
             MPI_SUCCESS
         },
         Err(code) => code as i32,
     };
-}
\ No newline at end of file
+}
@@ -1,3 +1,4 @@
+use crate::types::MPI_BSEND_OVERHEAD;
 use std::{alloc::Layout, ptr::null_mut, slice::from_raw_parts_mut};
 
 pub struct DynBuffer {
@@ -29,7 +30,108 @@ impl DynBuffer {
         }
     }
 
-    pub fn to_slice<'a>(&'a self) -> &'a mut [u8] {
+    pub fn to_slice(&mut self) -> &mut [u8] {
         unsafe { from_raw_parts_mut(self.data, self.layout.size()) }
     }
 }
+
+/// User buffer attached with `MPI_Buffer_attach`, buffered sends keep a copy of
+/// their data here until it is pushed to the receiver.
+pub struct AttachedBuffer {
+    data: *mut u8,
+    size: usize,
+    used: Vec<(usize, usize)>,
+}
+
+impl AttachedBuffer {
+    pub const fn new() -> Self {
+        Self {
+            data: null_mut(),
+            size: 0,
+            used: Vec::new(),
+        }
+    }
+
+    pub fn is_attached(&self) -> bool {
+        !self.data.is_null()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.used.is_empty()
+    }
+
+    pub fn attach(&mut self, data: *mut u8, size: usize) {
+        debug_assert!(!self.is_attached());
+        self.data = data;
+        self.size = size;
+    }
+
+    pub fn detach(&mut self) -> (*mut u8, usize) {
+        debug_assert!(self.is_empty());
+        let res = (self.data, self.size);
+        self.data = null_mut();
+        self.size = 0;
+        res
+    }
+
+    /// First fit allocation, every block is charged with `MPI_BSEND_OVERHEAD`
+    /// bytes which are used to align the returned pointer.
+    pub fn alloc(&mut self, len: usize) -> Option<*mut u8> {
+        let need = len + MPI_BSEND_OVERHEAD as usize;
+        let mut pos = 0;
+        let mut idx = 0;
+        for (i, &(off, size)) in self.used.iter().enumerate() {
+            if off - pos >= need {
+                break;
+            }
+            pos = off + size;
+            idx = i + 1;
+        }
+        if self.size - pos < need {
+            return None;
+        }
+        self.used.insert(idx, (pos, need));
+
+        let ptr = unsafe { self.data.add(pos) };
+        Some(unsafe { ptr.add(ptr.align_offset(MPI_BSEND_OVERHEAD as usize)) })
+    }
+
+    pub fn release(&mut self, ptr: *mut u8) {
+        let off = ptr as usize - self.data as usize;
+        let idx = self
+            .used
+            .iter()
+            .position(|&(start, size)| start <= off && off < start + size);
+        debug_assert!(idx.is_some());
+        if let Some(idx) = idx {
+            self.used.remove(idx);
+        }
+    }
+}
+
+#[test]
+fn test_attached_buffer() {
+    let overhead = MPI_BSEND_OVERHEAD as usize;
+    let mut buf = DynBuffer::new(3 * (100 + overhead));
+    let mut pool = AttachedBuffer::new();
+    pool.attach(buf.to_slice().as_mut_ptr(), buf.to_slice().len());
+
+    let a = pool.alloc(100).unwrap();
+    let b = pool.alloc(100).unwrap();
+    let c = pool.alloc(100).unwrap();
+    assert!(pool.alloc(1).is_none());
+    assert!([a, b, c].iter().all(|p| *p as usize % overhead == 0));
+
+    pool.release(b);
+    assert!(pool.alloc(101).is_none());
+    assert_eq!(pool.alloc(100), Some(b));
+
+    for p in [a, b, c] {
+        pool.release(p);
+    }
+    assert!(pool.is_empty());
+    assert_eq!(
+        pool.alloc(3 * 100 + 2 * overhead).unwrap() as usize % overhead,
+        0
+    );
+}
@@ -8,6 +8,7 @@ use crate::types::MpiError::*;
 use crate::{debug_core, types::*};
 
 const KEY_INC: i32 = 2;
+const ANY_TAG_FLAG: i32 = 0x8000;
 
 pub struct CommGroup {
     comms: Vec<Comm>,
@@ -92,9 +93,13 @@ impl CommGroup {
         self.comms[idx as usize].rank
     }
 
+    /// Errors on a communicator that doesn't exist go to the handler of
+    /// `MPI_COMM_WORLD`.
     pub fn err_handler(&self, i: MPI_Comm) -> MPI_Errhandler {
-        debug_assert!((i as usize) < self.size() && i >= 0);
-        self.comms[i as usize].errh
+        match usize::try_from(i).ok().and_then(|i| self.comms.get(i)) {
+            Some(comm) => comm.errh,
+            None => self.comms[MPI_COMM_WORLD as usize].errh,
+        }
     }
 
     pub fn set_err_handler(&mut self, comm: MPI_Comm, errh: MPI_Errhandler) {
@@ -292,11 +297,10 @@ impl CommGroup {
     }
 
     pub fn check(&self, comm: MPI_Comm) -> MpiResult {
-        crate::MPI_CHECK!(
-            comm >= 0 && comm < self.comms.len() as i32,
-            MPI_COMM_WORLD,
-            MPI_ERR_COMM
-        )
+        if comm < 0 || comm >= self.comms.len() as i32 {
+            return Err(Context::err_handler().call(MPI_COMM_WORLD, MPI_ERR_COMM));
+        }
+        Ok(())
     }
 
     pub fn check_rank(&self, rank: i32, comm: MPI_Comm) -> MpiResult {
@@ -306,22 +310,21 @@ impl CommGroup {
             "Check rank: {rank} for comm: {comm}, {}",
             self.comms[comm as usize].prank.len()
         );
-        crate::MPI_CHECK!(
-            rank >= 0 && rank < self.comms[comm as usize].prank.len() as i32,
-            comm,
-            MPI_ERR_RANK
-        )
+        if rank < 0 || rank >= self.comms[comm as usize].prank.len() as i32 {
+            return Err(Context::err_handler().call(comm, MPI_ERR_RANK));
+        }
+        Ok(())
     }
 
-    pub fn rank_map(&self, comm: MPI_Comm, rank: i32) -> i32 {
+    pub fn rank_map(&self, comm: MPI_Comm, rank: i32) -> Result<i32, MpiError> {
         debug_assert!(Context::is_init());
-        self.check_rank(rank, comm);
-        self.comms[comm as usize].prank[rank as usize]
+        self.check_rank(rank, comm)?;
+        Ok(self.comms[comm as usize].prank[rank as usize])
     }
 
     pub fn rank_unmap(&self, comm: MPI_Comm, rank: i32) -> i32 {
         debug_assert!(Context::is_init());
-        self.check_rank(rank, comm);
+        debug_assert!(self.check_rank(rank, comm).is_ok());
         for (i, r) in self.comms[comm as usize].prank.iter().enumerate() {
             if *r == rank {
                 return i as i32;
@@ -330,31 +333,45 @@ impl CommGroup {
         unreachable!()
     }
 
-    pub fn tag_map(&self, comm: MPI_Comm, tag: i32) -> i32 {
+    pub fn tag_map(&self, comm: MPI_Comm, tag: i32) -> Result<i32, MpiError> {
         debug_assert!(Context::is_init());
-        self.check(comm);
-        debug_assert!(tag >= 0 && tag <= 32767);
+        self.check(comm)?;
+        debug_assert!(tag == MPI_ANY_TAG || (tag >= 0 && tag <= 32767));
 
-        (self.comms[comm as usize].key << 16) | (tag & 0x7FFF)
+        if tag == MPI_ANY_TAG {
+            return Ok((self.comms[comm as usize].key << 16) | ANY_TAG_FLAG);
+        }
+
+        Ok((self.comms[comm as usize].key << 16) | (tag & 0x7FFF))
+    }
+
+    /// Checks a mapped tag of an incoming message against the mapped tag of a receive,
+    /// a wildcard tag only matches messages of the same communicator key.
+    #[inline(always)]
+    pub fn tag_match(pattern: i32, tag: i32) -> bool {
+        if pattern & ANY_TAG_FLAG != 0 {
+            return (pattern >> 16) == (tag >> 16);
+        }
+        pattern == tag
     }
 
     pub fn tag_unmap(&self, comm: MPI_Comm, tag: i32) -> i32 {
         debug_assert!(Context::is_init());
-        self.check(comm);
+        debug_assert!(self.check(comm).is_ok());
 
         tag & 0x7FFF
     }
 
     pub fn inc_key(&mut self, comm: MPI_Comm) {
         debug_assert!(Context::is_init());
-        self.check(comm);
+        debug_assert!(self.check(comm).is_ok());
 
         self.comms[comm as usize].key += 1
     }
 
     pub fn dec_key(&mut self, comm: MPI_Comm) {
         debug_assert!(Context::is_init());
-        self.check(comm);
+        debug_assert!(self.check(comm).is_ok());
 
         self.comms[comm as usize].key -= 1
     }
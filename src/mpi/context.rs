@@ -1,7 +1,13 @@
-use crate::backend::shm::ShmData;
+use crate::backend::memory::{nt_threshold, portable_cpy, CopyFn, CopyVariant};
+use crate::backend::reqqueue::QUEUE_LIMIT;
+use crate::backend::segment;
+use crate::backend::shm::{ShmData, CELL_COUNT, CELL_LEN, CMA_THRESHOLD};
+use crate::backend::wait::{WaitPolicy, SPIN_COUNT};
+use crate::buffer::AttachedBuffer;
 use crate::communicator::group::CommGroup;
 use crate::debug_core;
 use crate::errhandler::handler::HandlerContext;
+use crate::metatypes::typemap::TypeTable;
 pub use crate::shared::*;
 pub use crate::types::*;
 use crate::xfer::collectives::allgather::allgather_simple;
@@ -26,12 +32,19 @@ macro_rules! debug_init {
 
 pub struct Context {
     shm: ShmData,
+    bsend: AttachedBuffer,
     err_handler: HandlerContext,
     comm_group: CommGroup,
+    types: TypeTable,
     mpi_size: i32,
     mpi_rank: i32,
     mpi_init: bool,
-    use_nt: bool,
+    forked: bool,
+    nt_threshold: usize,
+    wait_policy: WaitPolicy,
+    spin_count: u32,
+    cpy_impl: CopyFn,
+    ntcpy_impl: CopyFn,
     barrier_impl: BarrierFn,
     bcast_impl: BCastFn,
     reduce_impl: ReduceFn,
@@ -42,12 +55,19 @@ pub struct Context {
 
 static mut CONTEXT: Context = Context {
     shm: ShmData::new(),
+    bsend: AttachedBuffer::new(),
     mpi_size: 1,
     mpi_rank: 0,
     mpi_init: false,
+    forked: false,
     err_handler: HandlerContext::new(),
     comm_group: CommGroup::new(),
-    use_nt: false,
+    types: TypeTable::new(),
+    nt_threshold: usize::MAX,
+    wait_policy: WaitPolicy::Spin,
+    spin_count: SPIN_COUNT,
+    cpy_impl: portable_cpy,
+    ntcpy_impl: portable_cpy,
     barrier_impl: barrier::barrier_simple,
     bcast_impl: bcast::bcast_shm,
     reduce_impl: reduce::reduce_ring,
@@ -72,14 +92,47 @@ unsafe extern "C" fn child_handler(_: i32) {
 }
 
 impl Context {
-    fn get_use_nt() -> bool {
+    fn get_use_nt() -> Option<bool> {
         if cfg!(feature = "ntcpy") {
-            return true;
+            return Some(true);
         }
-        if let Ok(val) = std::env::var("MPI_USE_NT") {
-            return val == "1";
+        match std::env::var("MPI_USE_NT").ok()?.as_str() {
+            "1" => Some(true),
+            "0" => Some(false),
+            _ => None,
         }
-        false
+    }
+
+    fn get_nt_threshold() -> Option<usize> {
+        std::env::var("MPI_NT_THRESHOLD").ok()?.parse().ok()
+    }
+
+    fn get_copy_variant() -> Option<CopyVariant> {
+        CopyVariant::parse(&std::env::var("MPI_COPY").ok()?)
+    }
+
+    fn get_max_requests() -> Option<usize> {
+        std::env::var("MPI_MAX_REQUESTS").ok()?.parse().ok()
+    }
+
+    fn get_cell_count() -> Option<usize> {
+        std::env::var("MPI_CELL_COUNT").ok()?.parse().ok()
+    }
+
+    fn get_cell_size() -> Option<usize> {
+        std::env::var("MPI_CELL_SIZE").ok()?.parse().ok()
+    }
+
+    fn get_wait_policy() -> Option<WaitPolicy> {
+        WaitPolicy::parse(&std::env::var("MPI_WAIT_POLICY").ok()?)
+    }
+
+    fn get_spin_count() -> Option<u32> {
+        std::env::var("MPI_SPIN_COUNT").ok()?.parse().ok()
+    }
+
+    fn get_cma_threshold() -> Option<usize> {
+        std::env::var("MPI_CMA_THRESHOLD").ok()?.parse().ok()
     }
 
     fn get_mpi() -> Option<i32> {
@@ -124,12 +177,33 @@ impl Context {
 
     fn get_env() -> i32 {
         unsafe {
-            CONTEXT.use_nt = Self::get_use_nt();
-            if CONTEXT.use_nt {
-                debug_init!("Enable non-temporal copy");
-            } else {
-                debug_init!("Disable non-temporal copy");
-            }
+            CONTEXT.nt_threshold = match Self::get_use_nt() {
+                Some(true) => 0,
+                Some(false) => usize::MAX,
+                None => Self::get_nt_threshold().unwrap_or_else(nt_threshold),
+            };
+            debug_init!("Non-temporal copy from {} bytes", CONTEXT.nt_threshold);
+            let variant = match Self::get_copy_variant() {
+                Some(variant) if variant.is_supported() => variant,
+                Some(variant) => {
+                    debug_init!("CPU lacks {variant:?}, detect copy variant");
+                    CopyVariant::detect()
+                }
+                None => CopyVariant::detect(),
+            };
+            debug_init!("Copy with {variant:?}");
+            (CONTEXT.cpy_impl, CONTEXT.ntcpy_impl) = variant.routines();
+            CONTEXT.spin_count = Self::get_spin_count().unwrap_or(SPIN_COUNT);
+            let limit = Self::get_max_requests().unwrap_or(QUEUE_LIMIT);
+            debug_init!("Limit outstanding requests to {limit}");
+            CONTEXT.shm.set_limit(limit);
+            let threshold = Self::get_cma_threshold().unwrap_or(CMA_THRESHOLD);
+            debug_init!("Rendezvous threshold {threshold}");
+            CONTEXT.shm.set_cma_threshold(threshold);
+            CONTEXT.shm.set_geometry(
+                Self::get_cell_count().unwrap_or(CELL_COUNT),
+                Self::get_cell_size().unwrap_or(CELL_LEN),
+            );
             if let Some(size) = Self::get_mpi() {
                 CONTEXT.mpi_size = size;
                 CONTEXT.mpi_rank = -1;
@@ -147,8 +221,27 @@ impl Context {
         }
     }
 
-    pub fn use_nt() -> bool {
-        unsafe { CONTEXT.use_nt }
+    #[inline(always)]
+    pub fn nt_threshold() -> usize {
+        unsafe { CONTEXT.nt_threshold }
+    }
+
+    #[inline(always)]
+    pub fn cpy() -> CopyFn {
+        unsafe { CONTEXT.cpy_impl }
+    }
+
+    #[inline(always)]
+    pub fn ntcpy() -> CopyFn {
+        unsafe { CONTEXT.ntcpy_impl }
+    }
+
+    pub fn wait_policy() -> WaitPolicy {
+        unsafe { CONTEXT.wait_policy }
+    }
+
+    pub fn spin_count() -> u32 {
+        unsafe { CONTEXT.spin_count }
     }
 
     pub fn comm() -> &'static mut CommGroup {
@@ -163,6 +256,14 @@ impl Context {
         unsafe { &mut CONTEXT.shm }
     }
 
+    pub fn bsend() -> &'static mut AttachedBuffer {
+        unsafe { &mut CONTEXT.bsend }
+    }
+
+    pub fn types() -> &'static mut TypeTable {
+        unsafe { &mut CONTEXT.types }
+    }
+
     pub fn progress() -> MpiResult {
         debug_core!("Progress", "Enter");
         let ret = unsafe { CONTEXT.shm.progress() };
@@ -251,6 +352,13 @@ impl Context {
             debug_assert!(!CONTEXT.mpi_init);
 
             let key = Self::get_env();
+            CONTEXT.wait_policy = Self::get_wait_policy()
+                .unwrap_or_else(|| WaitPolicy::default_for(CONTEXT.mpi_size));
+            debug_init!(
+                "Wait policy {:?} after {} polls",
+                CONTEXT.wait_policy,
+                CONTEXT.spin_count
+            );
 
             let mut code = CONTEXT.shm.init(pargc, pargv, key);
             if let Err(code) = code {
@@ -265,6 +373,7 @@ impl Context {
                     return Err(CONTEXT.err_handler.call(MPI_COMM_WORLD, code));
                 }
                 libc::signal(libc::SIGCHLD, child_handler as usize);
+                CONTEXT.forked = true;
             }
 
             code = CONTEXT.comm_group.init(pargc, pargv);
@@ -274,6 +383,17 @@ impl Context {
             }
 
             CONTEXT.mpi_init = true;
+
+            CONTEXT.shm.publish_cma();
+            code = (CONTEXT.barrier_impl)(MPI_COMM_WORLD);
+            if let Err(code) = code {
+                debug_init!("Error publish process ids");
+                return Err(CONTEXT.err_handler.call(MPI_COMM_WORLD, code));
+            }
+            CONTEXT.shm.probe_cma();
+            // Every rank attached before the barrier, the segment has no name left
+            // to clean up.
+            segment::restore_handlers();
         }
 
         debug_init!("Success");
@@ -288,9 +408,13 @@ impl Context {
             (CONTEXT.barrier_impl)(MPI_COMM_WORLD)?;
             CONTEXT.shm.deinit()?;
             CONTEXT.comm_group.deinit();
+            CONTEXT.types.clear();
             CONTEXT.mpi_init = false;
             if CONTEXT.mpi_rank == 0 {
-                libc::signal(libc::SIGCHLD, libc::SIG_IGN);
+                // Ranks attached by the launcher are not our children.
+                if CONTEXT.forked {
+                    libc::signal(libc::SIGCHLD, libc::SIG_IGN);
+                }
             } else {
                 std::process::exit(0);
             }
@@ -10,6 +10,6 @@ pub fn error_fatal(_: MPI_Comm, pcode: crate::types::MpiError) {
     unsafe { exit(-1) };
 }
 
-pub fn error_return(_: MPI_Comm, _: crate::types::MpiError) {
-    todo!();
+pub fn error_return(_: MPI_Comm, pcode: crate::types::MpiError) {
+    debug_core!("Error", "Return error, code: {}", pcode as i32);
 }
@@ -12,6 +12,19 @@ macro_rules! MPI_CHECK {
     };
 }
 
+/// Argument check for the C bindings, unlike `MPI_CHECK!` it runs in every
+/// build profile. A failed check returns its error code from the calling
+/// function.
+#[macro_export]
+macro_rules! MPI_TRY {
+    ($exp:expr, $comm:expr, $code:expr) => {
+        if !$exp {
+            $crate::debug_core!("Check", "Check failed");
+            return Context::err_handler().call($comm, $code) as i32;
+        }
+    };
+}
+
 #[allow(dead_code)]
 pub fn check_handler(comm: MPI_Comm, handler: MpiError) {
     todo!()
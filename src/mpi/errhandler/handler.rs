@@ -1,7 +1,7 @@
 use std::ffi::CStr;
 
 use crate::context::Context;
-use crate::MPI_CHECK;
+use crate::MPI_TRY;
 use crate::{cstr, shared::*, types::*};
 use zstr::zstr;
 
@@ -13,20 +13,16 @@ pub struct HandlerContext {
     handlers: [callback::Callback; ERRH_MAX],
 }
 
+/// Returns `MPI_ERR_COMM` from the calling binding if `comm` doesn't exist.
 #[macro_export]
-#[cfg(debug_assertions)]
 macro_rules! MPI_CHECK_COMM {
     ($comm:expr) => {
-        Context::comm().check($comm)
+        if let Err(code) = Context::comm().check($comm) {
+            return code as i32;
+        }
     };
 }
 
-#[macro_export]
-#[cfg(not(debug_assertions))]
-macro_rules! MPI_CHECK_COMM {
-    ($comm:expr) => {};
-}
-
 #[macro_export]
 macro_rules! MPI_CHECK_COMM_RET {
     ($comm:expr) => {
@@ -55,6 +51,10 @@ impl HandlerContext {
         cstr!("buffer truncated"),
         cstr!("other error"),
         cstr!("internal error"),
+        cstr!("pending request"),
+        cstr!("error in status"),
+        cstr!("out of resources"),
+        cstr!("value not representable"),
     ];
 
     pub const fn new() -> Self {
@@ -68,7 +68,10 @@ impl HandlerContext {
     }
 
     pub fn check(comm: MPI_Comm, errh: MPI_Errhandler) -> MpiResult {
-        crate::MPI_CHECK!(errh >= 0 && errh < ERRH_MAX as i32, comm, MPI_ERR_ARG)
+        if errh < 0 || errh >= ERRH_MAX as i32 {
+            return Err(Context::err_handler().call(comm, MPI_ERR_ARG));
+        }
+        Ok(())
     }
 
     pub fn call(&self, comm: MPI_Comm, code: crate::types::MpiError) -> crate::types::MpiError {
@@ -82,13 +85,13 @@ impl HandlerContext {
 
 #[no_mangle]
 pub extern "C" fn MPI_Error_class(code: i32, pclass: *mut i32) -> i32 {
-    MPI_CHECK!(Context::is_init(), MPI_COMM_WORLD, MPI_ERR_OTHER);
-    MPI_CHECK!(
-        code >= MPI_SUCCESS && code <= MPI_ERR_LASTCODE as i32,
+    MPI_TRY!(Context::is_init(), MPI_COMM_WORLD, MPI_ERR_OTHER);
+    MPI_TRY!(
+        code >= MPI_SUCCESS && code < MPI_ERR_LASTCODE as i32,
         MPI_COMM_WORLD,
         MPI_ERR_ARG
     );
-    MPI_CHECK!(!pclass.is_null(), MPI_COMM_WORLD, MPI_ERR_ARG);
+    MPI_TRY!(!pclass.is_null(), MPI_COMM_WORLD, MPI_ERR_ARG);
 
     unsafe { pclass.write(code) };
 
@@ -97,14 +100,14 @@ pub extern "C" fn MPI_Error_class(code: i32, pclass: *mut i32) -> i32 {
 
 #[no_mangle]
 pub extern "C" fn MPI_Error_string(code: i32, str: *mut i8, plen: *mut i32) -> i32 {
-    MPI_CHECK!(Context::is_init(), MPI_COMM_WORLD, MPI_ERR_OTHER);
-    MPI_CHECK!(
-        code >= MPI_SUCCESS && code <= MPI_ERR_LASTCODE as i32,
+    MPI_TRY!(Context::is_init(), MPI_COMM_WORLD, MPI_ERR_OTHER);
+    MPI_TRY!(
+        code >= MPI_SUCCESS && code < MPI_ERR_LASTCODE as i32,
         MPI_COMM_WORLD,
         MPI_ERR_ARG
     );
-    MPI_CHECK!(!str.is_null(), MPI_COMM_WORLD, MPI_ERR_ARG);
-    MPI_CHECK!(!plen.is_null(), MPI_COMM_WORLD, MPI_ERR_ARG);
+    MPI_TRY!(!str.is_null(), MPI_COMM_WORLD, MPI_ERR_ARG);
+    MPI_TRY!(!plen.is_null(), MPI_COMM_WORLD, MPI_ERR_ARG);
 
     let len = unsafe {
         CStr::from_ptr(HandlerContext::err_to_string(code))
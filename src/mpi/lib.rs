@@ -16,8 +16,14 @@ mod xfer;
 
 pub use base::*;
 pub use bindings::*;
+pub use my_mpi_derive::Typed;
 pub use object::context::MpiObject;
+#[doc(hidden)]
+pub use object::types::derived_type;
 pub use object::types::Data;
+pub use object::types::Packer;
 pub use object::types::Promise;
+pub use object::types::Typed;
+pub use object::types::Unpacker;
 pub use shared::uninit;
 pub use types::*;
@@ -1,16 +1,173 @@
 use crate::{shared::*, types::*, MPI_CHECK};
+use std::ffi::{
+    c_char, c_int, c_long, c_longlong, c_short, c_uint, c_ulong, c_ulonglong, c_ushort,
+};
+use std::mem::size_of;
 
+/// Reductions a datatype supports, one bit per `MPI_Op`.
+const ORDERED: u8 = 1 << MPI_MAX | 1 << MPI_MIN | 1 << MPI_SUM;
+const ADDITIVE: u8 = 1 << MPI_SUM;
+const NONE: u8 = 0;
+
+pub(crate) mod engine;
+pub(crate) mod external;
+pub(crate) mod pack;
+pub(crate) mod typemap;
+
+/// Layout of a datatype and the reductions it has kernels for.
+#[derive(Clone, Copy)]
+pub(crate) struct TypeInfo {
+    pub size: i32,
+    pub lb: isize,
+    pub extent: isize,
+    ops: u8,
+}
+
+impl TypeInfo {
+    const fn of<T>(ops: u8) -> Option<TypeInfo> {
+        Some(TypeInfo {
+            size: size_of::<T>() as i32,
+            lb: 0,
+            extent: size_of::<T>() as isize,
+            ops,
+        })
+    }
+
+    /// Elements follow each other without gaps, starting at the buffer.
+    pub fn is_contiguous(&self) -> bool {
+        self.lb == 0 && self.extent == self.size as isize
+    }
+
+    pub fn can_reduce(&self, op: MPI_Op) -> bool {
+        (0..8).contains(&op) && self.ops & 1 << op != 0
+    }
+}
+
+const TYPE_COUNT: usize = MPI_PACKED as usize + 1;
+/// Handle of the first derived datatype, see `TypeTable`.
+pub(crate) const FIRST_DERIVED: MPI_Datatype = 64;
+
+/// The predefined datatypes indexed by handle.
+static TYPES: [Option<TypeInfo>; TYPE_COUNT] = {
+    let mut types = [None; TYPE_COUNT];
+    types[MPI_BYTE as usize] = TypeInfo::of::<u8>(ORDERED);
+    types[MPI_CHAR as usize] = TypeInfo::of::<c_char>(NONE);
+    types[MPI_SHORT as usize] = TypeInfo::of::<c_short>(ORDERED);
+    types[MPI_INT as usize] = TypeInfo::of::<c_int>(ORDERED);
+    types[MPI_LONG as usize] = TypeInfo::of::<c_long>(ORDERED);
+    types[MPI_LONG_LONG as usize] = TypeInfo::of::<c_longlong>(ORDERED);
+    types[MPI_FLOAT as usize] = TypeInfo::of::<f32>(ORDERED);
+    types[MPI_DOUBLE as usize] = TypeInfo::of::<f64>(ORDERED);
+    types[MPI_UNSIGNED_CHAR as usize] = TypeInfo::of::<u8>(ORDERED);
+    types[MPI_UNSIGNED_SHORT as usize] = TypeInfo::of::<c_ushort>(ORDERED);
+    types[MPI_UNSIGNED as usize] = TypeInfo::of::<c_uint>(ORDERED);
+    types[MPI_UNSIGNED_LONG as usize] = TypeInfo::of::<c_ulong>(ORDERED);
+    types[MPI_UNSIGNED_LONG_LONG as usize] = TypeInfo::of::<c_ulonglong>(ORDERED);
+    types[MPI_INT8_T as usize] = TypeInfo::of::<i8>(ORDERED);
+    types[MPI_INT16_T as usize] = TypeInfo::of::<i16>(ORDERED);
+    types[MPI_INT32_T as usize] = TypeInfo::of::<i32>(ORDERED);
+    types[MPI_INT64_T as usize] = TypeInfo::of::<i64>(ORDERED);
+    types[MPI_UINT8_T as usize] = TypeInfo::of::<u8>(ORDERED);
+    types[MPI_UINT16_T as usize] = TypeInfo::of::<u16>(ORDERED);
+    types[MPI_UINT32_T as usize] = TypeInfo::of::<u32>(ORDERED);
+    types[MPI_UINT64_T as usize] = TypeInfo::of::<u64>(ORDERED);
+    types[MPI_C_BOOL as usize] = TypeInfo::of::<bool>(NONE);
+    types[MPI_C_DOUBLE_COMPLEX as usize] = TypeInfo::of::<[f64; 2]>(ADDITIVE);
+    types[MPI_PACKED as usize] = TypeInfo::of::<u8>(NONE);
+    types
+};
+
+/// Predefined datatypes and derived ones that were not freed yet.
+pub(crate) fn type_info(dtype: MPI_Datatype) -> Option<&'static TypeInfo> {
+    if dtype >= FIRST_DERIVED {
+        return Context::types().get(dtype).map(|map| &map.info);
+    }
+    TYPES.get(usize::try_from(dtype).ok()?)?.as_ref()
+}
+
+/// Only predefined and committed datatypes can be used for communication.
 pub(crate) fn check_type(dtype: MPI_Datatype, comm: MPI_Comm) -> MpiResult {
-    MPI_CHECK!(
-        matches!(dtype, MPI_BYTE | MPI_INT | MPI_DOUBLE),
-        comm,
-        MPI_ERR_TYPE
-    )
+    let valid = if dtype >= FIRST_DERIVED {
+        Context::types().get(dtype).is_some_and(|map| map.committed)
+    } else {
+        type_info(dtype).is_some()
+    };
+    if !valid {
+        return Err(Context::err_handler().call(comm, MPI_ERR_TYPE));
+    }
+    Ok(())
+}
+
+/// How the datatype engine walks a buffer of `dtype`, `MPI_BYTE` stands for
+/// contiguous data that is copied as is.
+pub(crate) fn layout(dtype: MPI_Datatype) -> MPI_Datatype {
+    match Context::types().get(dtype) {
+        Some(map) if !map.is_dense() => dtype,
+        _ => MPI_BYTE,
+    }
+}
+
+/// Fails with `MPI_ERR_OP` if `op` is not defined on `dtype`.
+pub(crate) fn check_reduce(dtype: MPI_Datatype, op: MPI_Op, comm: MPI_Comm) -> MpiResult {
+    if !type_info(dtype).is_some_and(|info| info.can_reduce(op)) {
+        return Err(Context::err_handler().call(comm, MPI_ERR_OP));
+    }
+    Ok(())
 }
 
 pub(crate) fn type_size(dtype: MPI_Datatype) -> Result<i32, MpiError> {
-    MPI_CHECK!(Context::is_init(), MPI_COMM_WORLD, MPI_ERR_TYPE);
-    check_type(dtype, MPI_COMM_WORLD)?;
+    MPI_CHECK!(Context::is_init(), MPI_COMM_WORLD, MPI_ERR_TYPE)?;
+    let Some(info) = type_info(dtype) else {
+        return Err(Context::err_handler().call(MPI_COMM_WORLD, MPI_ERR_TYPE));
+    };
+
+    Ok(info.size)
+}
+
+/// Lower bound and extent of `dtype`.
+pub(crate) fn type_extent(dtype: MPI_Datatype) -> Result<(isize, isize), MpiError> {
+    MPI_CHECK!(Context::is_init(), MPI_COMM_WORLD, MPI_ERR_TYPE)?;
+    let Some(info) = type_info(dtype) else {
+        return Err(Context::err_handler().call(MPI_COMM_WORLD, MPI_ERR_TYPE));
+    };
+
+    Ok((info.lb, info.extent))
+}
+
+/// Bytes of `cnt` elements of `dtype` that must be contiguous, for the
+/// collectives which don't go through the datatype engine.
+pub(crate) fn contiguous_len(
+    dtype: MPI_Datatype,
+    cnt: i32,
+    comm: MPI_Comm,
+) -> Result<usize, MpiError> {
+    check_type(dtype, comm)?;
+    if layout(dtype) != MPI_BYTE {
+        return Err(Context::err_handler().call(comm, MPI_ERR_TYPE));
+    }
+    if cnt < 0 {
+        return Err(Context::err_handler().call(comm, MPI_ERR_COUNT));
+    }
+
+    Ok(type_info(dtype).unwrap().size as usize * cnt as usize)
+}
+
+/// Committing a predefined datatype does nothing.
+pub(crate) fn type_commit(dtype: MPI_Datatype) -> MpiResult {
+    let valid =
+        (dtype < FIRST_DERIVED && type_info(dtype).is_some()) || Context::types().commit(dtype);
+    if !valid {
+        return Err(Context::err_handler().call(MPI_COMM_WORLD, MPI_ERR_TYPE));
+    }
+    Ok(())
+}
 
-    Ok(dtype)
+/// Pending communication with `dtype` still completes normally.
+pub(crate) fn type_free(dtype: MPI_Datatype) -> MpiResult {
+    // The slot is released here, not inside `MPI_CHECK!` which release
+    // builds skip.
+    if dtype < FIRST_DERIVED || !Context::types().free(dtype) {
+        return Err(Context::err_handler().call(MPI_COMM_WORLD, MPI_ERR_TYPE));
+    }
+    Ok(())
 }
@@ -0,0 +1,138 @@
+use super::typemap::Typemap;
+use crate::shared::*;
+use std::ptr::{copy, copy_nonoverlapping};
+
+/// Calls `f` with the offset in the user buffer, the offset in the packed
+/// data and the length of every piece of the packed bytes `pos..pos + len`.
+fn walk(map: &Typemap, pos: usize, len: usize, mut f: impl FnMut(isize, usize, usize)) {
+    if len == 0 {
+        return;
+    }
+    let size = map.info.size as usize;
+    let mut elem = (pos / size) as isize;
+    let off = pos % size;
+    let mut idx = map.starts.partition_point(|start| *start <= off) - 1;
+    let mut inner = off - map.starts[idx];
+
+    let mut done = 0;
+    while done < len {
+        let block = map.blocks[idx];
+        let n = (block.len - inner).min(len - done);
+        f(
+            elem * map.info.extent + block.disp + inner as isize,
+            done,
+            n,
+        );
+        done += n;
+        inner = 0;
+        idx += 1;
+        if idx == map.blocks.len() {
+            idx = 0;
+            elem += 1;
+        }
+    }
+}
+
+/// Copies the packed bytes `pos..pos + len` of the elements at `buf` to `dst`.
+/// `MPI_BYTE` stands for a contiguous buffer.
+pub(crate) fn pack(
+    dtype: MPI_Datatype,
+    buf: *const c_void,
+    pos: usize,
+    dst: *mut c_void,
+    len: usize,
+) {
+    let (buf, dst) = (buf as *const u8, dst as *mut u8);
+    let Some(map) = Context::types().map(dtype).filter(|_| dtype != MPI_BYTE) else {
+        unsafe { copy(buf.add(pos), dst, len) };
+        return;
+    };
+    walk(map, pos, len, |user, packed, n| unsafe {
+        copy_nonoverlapping(buf.offset(user), dst.add(packed), n)
+    });
+}
+
+/// Copies `len` packed bytes from `src` into the elements at `buf`, starting
+/// at the packed position `pos`.
+pub(crate) fn unpack(
+    dtype: MPI_Datatype,
+    buf: *mut c_void,
+    pos: usize,
+    src: *const c_void,
+    len: usize,
+) {
+    let (buf, src) = (buf as *mut u8, src as *const u8);
+    let Some(map) = Context::types().map(dtype).filter(|_| dtype != MPI_BYTE) else {
+        unsafe { copy(src, buf.add(pos), len) };
+        return;
+    };
+    walk(map, pos, len, |user, packed, n| unsafe {
+        copy_nonoverlapping(src.add(packed), buf.offset(user), n)
+    });
+}
+
+/// Moves `len` packed bytes between two buffers of any layout.
+pub(crate) fn transfer(
+    dst: *mut c_void,
+    ddtype: MPI_Datatype,
+    src: *const c_void,
+    sdtype: MPI_Datatype,
+    len: usize,
+) {
+    if sdtype == MPI_BYTE {
+        unpack(ddtype, dst, 0, src, len);
+    } else if ddtype == MPI_BYTE {
+        pack(sdtype, src, 0, dst, len);
+    } else {
+        let mut stage = vec![0u8; len];
+        pack(sdtype, src, 0, stage.as_mut_ptr() as *mut c_void, len);
+        unpack(ddtype, dst, 0, stage.as_ptr() as *const c_void, len);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{typemap::Block, TypeInfo, NONE};
+    use super::*;
+
+    #[test]
+    fn walk_test() {
+        // Two ints 8 bytes apart, then a byte at 2, in an extent of 20.
+        let map = Typemap {
+            info: TypeInfo {
+                size: 9,
+                lb: 0,
+                extent: 20,
+                ops: NONE,
+            },
+            blocks: vec![
+                Block {
+                    disp: 0,
+                    len: 4,
+                    base: MPI_INT,
+                },
+                Block {
+                    disp: 8,
+                    len: 4,
+                    base: MPI_INT,
+                },
+                Block {
+                    disp: 2,
+                    len: 1,
+                    base: MPI_BYTE,
+                },
+            ],
+            starts: vec![0, 4, 8],
+            committed: true,
+            freed: false,
+        };
+        let mut pieces = Vec::new();
+        walk(&map, 6, 12, |user, packed, n| {
+            pieces.push((user, packed, n))
+        });
+        assert_eq!(
+            pieces,
+            [(10, 0, 2), (2, 2, 1), (20, 3, 4), (28, 7, 4), (22, 11, 1)]
+        );
+    }
+}
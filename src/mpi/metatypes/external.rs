@@ -0,0 +1,189 @@
+use super::typemap::Block;
+use super::{check_type, type_info};
+use crate::shared::*;
+use std::ffi::{c_long, c_ulong};
+use std::slice::{from_raw_parts, from_raw_parts_mut};
+
+/// Fails with `MPI_ERR_ARG` for any data representation but external32.
+pub(crate) fn check_datarep(datarep: &[u8], comm: MPI_Comm) -> MpiResult {
+    if datarep != b"external32" {
+        return Err(Context::err_handler().call(comm, MPI_ERR_ARG));
+    }
+    Ok(())
+}
+
+/// Bytes an element of the predefined `base` takes in external32 and the
+/// width of the big-endian words it is made of.
+fn encoding(base: MPI_Datatype) -> (usize, usize) {
+    match base {
+        MPI_LONG | MPI_UNSIGNED_LONG => (4, 4),
+        MPI_C_DOUBLE_COMPLEX => (16, 8),
+        _ => {
+            let size = type_info(base).unwrap().size as usize;
+            (size, size)
+        }
+    }
+}
+
+/// The predefined pieces of one element of `dtype`.
+fn blocks(dtype: MPI_Datatype) -> Vec<Block> {
+    match Context::types().get(dtype) {
+        Some(map) => map.blocks.clone(),
+        None => vec![Block {
+            disp: 0,
+            len: type_info(dtype).unwrap().size as usize,
+            base: dtype,
+        }],
+    }
+}
+
+/// Calls `f` with the offset in the user buffer, the offset in the external32
+/// data and the base type of every predefined element of `cnt` elements of
+/// `dtype`.
+fn walk(
+    dtype: MPI_Datatype,
+    cnt: i32,
+    mut f: impl FnMut(isize, usize, MPI_Datatype) -> MpiResult,
+) -> MpiResult {
+    let extent = type_info(dtype).unwrap().extent;
+    let blocks = blocks(dtype);
+    let mut at = 0;
+    for i in 0..cnt as isize {
+        for block in &blocks {
+            let size = type_info(block.base).unwrap().size as usize;
+            for j in 0..block.len / size {
+                let user = i * extent + block.disp + (j * size) as isize;
+                f(user, at, block.base)?;
+                at += encoding(block.base).0;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Copies `src` to `dst` one big-endian word of `base` at a time.
+fn swap(base: MPI_Datatype, src: &[u8], dst: &mut [u8]) {
+    let word = encoding(base).1;
+    for (d, s) in dst.chunks_mut(word).zip(src.chunks(word)) {
+        d.copy_from_slice(s);
+        if cfg!(target_endian = "little") {
+            d.reverse();
+        }
+    }
+}
+
+/// Writes the element of `base` at `src` in external32 to `dst`.
+fn encode(base: MPI_Datatype, src: *const u8, dst: &mut [u8], comm: MPI_Comm) -> MpiResult {
+    let conversion = || Err(Context::err_handler().call(comm, MPI_ERR_CONVERSION));
+    match base {
+        MPI_LONG => {
+            let val = unsafe { (src as *const c_long).read_unaligned() };
+            let Ok(val) = i32::try_from(val) else {
+                return conversion();
+            };
+            dst.copy_from_slice(&val.to_be_bytes());
+        }
+        MPI_UNSIGNED_LONG => {
+            let val = unsafe { (src as *const c_ulong).read_unaligned() };
+            let Ok(val) = u32::try_from(val) else {
+                return conversion();
+            };
+            dst.copy_from_slice(&val.to_be_bytes());
+        }
+        _ => swap(base, unsafe { from_raw_parts(src, dst.len()) }, dst),
+    }
+    Ok(())
+}
+
+/// Reads the external32 element of `base` in `src` into `dst`.
+fn decode(base: MPI_Datatype, src: &[u8], dst: *mut u8) {
+    match base {
+        MPI_LONG => {
+            let val = i32::from_be_bytes(src.try_into().unwrap());
+            unsafe { (dst as *mut c_long).write_unaligned(val as c_long) };
+        }
+        MPI_UNSIGNED_LONG => {
+            let val = u32::from_be_bytes(src.try_into().unwrap());
+            unsafe { (dst as *mut c_ulong).write_unaligned(val as c_ulong) };
+        }
+        _ => {
+            let size = type_info(base).unwrap().size as usize;
+            swap(base, src, unsafe { from_raw_parts_mut(dst, size) });
+        }
+    }
+}
+
+/// Bytes `cnt` elements of `dtype` take in external32.
+pub(crate) fn pack_external_size(
+    cnt: i32,
+    dtype: MPI_Datatype,
+    comm: MPI_Comm,
+) -> Result<MPI_Aint, MpiError> {
+    check_type(dtype, comm)?;
+    if cnt < 0 {
+        return Err(Context::err_handler().call(comm, MPI_ERR_COUNT));
+    }
+
+    let elem: usize = blocks(dtype)
+        .iter()
+        .map(|block| {
+            block.len / type_info(block.base).unwrap().size as usize * encoding(block.base).0
+        })
+        .sum();
+    Ok(cnt as MPI_Aint * elem as MPI_Aint)
+}
+
+/// Range of `len` bytes at `pos` in an external32 buffer of `total` bytes, a
+/// range that doesn't fit fails with `MPI_ERR_TRUNCATE`.
+fn span(pos: MPI_Aint, len: usize, total: usize, comm: MPI_Comm) -> Result<usize, MpiError> {
+    match usize::try_from(pos) {
+        Ok(start) if len <= total.saturating_sub(start) => Ok(start),
+        _ => Err(Context::err_handler().call(comm, MPI_ERR_TRUNCATE)),
+    }
+}
+
+/// Appends `cnt` elements of `dtype` at `buf` to `out` at `pos` in the
+/// external32 representation, `pos` is moved past them.
+pub(crate) fn pack_external(
+    buf: *const c_void,
+    cnt: i32,
+    dtype: MPI_Datatype,
+    out: &mut [u8],
+    pos: &mut MPI_Aint,
+    comm: MPI_Comm,
+) -> MpiResult {
+    let len = pack_external_size(cnt, dtype, comm)? as usize;
+    let start = span(*pos, len, out.len(), comm)?;
+
+    let (buf, out) = (buf as *const u8, &mut out[start..start + len]);
+    walk(dtype, cnt, |user, at, base| {
+        let dst = &mut out[at..at + encoding(base).0];
+        encode(base, unsafe { buf.offset(user) }, dst, comm)
+    })?;
+    *pos += len as MPI_Aint;
+    Ok(())
+}
+
+/// Reads `cnt` elements of `dtype` in the external32 representation from
+/// `input` at `pos` into `buf`, `pos` is moved past them.
+pub(crate) fn unpack_external(
+    input: &[u8],
+    pos: &mut MPI_Aint,
+    buf: *mut c_void,
+    cnt: i32,
+    dtype: MPI_Datatype,
+    comm: MPI_Comm,
+) -> MpiResult {
+    let len = pack_external_size(cnt, dtype, comm)? as usize;
+    let start = span(*pos, len, input.len(), comm)?;
+
+    let (buf, input) = (buf as *mut u8, &input[start..start + len]);
+    walk(dtype, cnt, |user, at, base| {
+        decode(base, &input[at..at + encoding(base).0], unsafe {
+            buf.offset(user)
+        });
+        Ok(())
+    })?;
+    *pos += len as MPI_Aint;
+    Ok(())
+}
@@ -0,0 +1,63 @@
+use super::{check_type, engine, layout, type_info};
+use crate::shared::*;
+
+/// Bytes `cnt` elements of `dtype` take in a pack buffer.
+pub(crate) fn pack_size(cnt: i32, dtype: MPI_Datatype, comm: MPI_Comm) -> Result<i32, MpiError> {
+    check_type(dtype, comm)?;
+    if cnt < 0 {
+        return Err(Context::err_handler().call(comm, MPI_ERR_COUNT));
+    }
+
+    let len = cnt as i64 * type_info(dtype).unwrap().size as i64;
+    if len > i32::MAX as i64 {
+        return Err(Context::err_handler().call(comm, MPI_ERR_COUNT));
+    }
+    Ok(len as i32)
+}
+
+/// Range of `len` bytes at `pos` in a pack buffer of `total` bytes, a range
+/// that doesn't fit fails with `MPI_ERR_TRUNCATE`.
+fn span(pos: i32, len: usize, total: usize, comm: MPI_Comm) -> Result<usize, MpiError> {
+    match usize::try_from(pos) {
+        Ok(start) if len <= total.saturating_sub(start) => Ok(start),
+        _ => Err(Context::err_handler().call(comm, MPI_ERR_TRUNCATE)),
+    }
+}
+
+/// Appends `cnt` elements of `dtype` at `buf` to `out` at `pos`, which is
+/// moved past them.
+pub(crate) fn pack(
+    buf: *const c_void,
+    cnt: i32,
+    dtype: MPI_Datatype,
+    out: &mut [u8],
+    pos: &mut i32,
+    comm: MPI_Comm,
+) -> MpiResult {
+    let len = pack_size(cnt, dtype, comm)? as usize;
+    let start = span(*pos, len, out.len(), comm)?;
+
+    let dst = out[start..start + len].as_mut_ptr() as *mut c_void;
+    engine::pack(layout(dtype), buf, 0, dst, len);
+    *pos += len as i32;
+    Ok(())
+}
+
+/// Reads `cnt` elements of `dtype` from `input` at `pos` into `buf`, `pos` is
+/// moved past them.
+pub(crate) fn unpack(
+    input: &[u8],
+    pos: &mut i32,
+    buf: *mut c_void,
+    cnt: i32,
+    dtype: MPI_Datatype,
+    comm: MPI_Comm,
+) -> MpiResult {
+    let len = pack_size(cnt, dtype, comm)? as usize;
+    let start = span(*pos, len, input.len(), comm)?;
+
+    let src = input[start..start + len].as_ptr() as *const c_void;
+    engine::unpack(layout(dtype), buf, 0, src, len);
+    *pos += len as i32;
+    Ok(())
+}
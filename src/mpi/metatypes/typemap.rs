@@ -0,0 +1,338 @@
+use super::{type_info, TypeInfo, FIRST_DERIVED, NONE};
+use crate::shared::*;
+use std::any::TypeId;
+
+/// `len` bytes of the predefined type `base` at `disp` from the start of an
+/// element.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) struct Block {
+    pub disp: isize,
+    pub len: usize,
+    pub base: MPI_Datatype,
+}
+
+/// Layout of a derived datatype as the blocks of one element in typemap
+/// order, blocks of the same base type that follow each other in memory are
+/// merged.
+pub(crate) struct Typemap {
+    pub info: TypeInfo,
+    pub blocks: Vec<Block>,
+    /// Packed offset of every block within an element.
+    pub starts: Vec<usize>,
+    pub committed: bool,
+    /// Freed by the user, kept until the requests using it are gone.
+    pub freed: bool,
+}
+
+impl Typemap {
+    /// The blocks cover the whole extent in order, no need for the engine.
+    pub fn is_dense(&self) -> bool {
+        self.info.is_contiguous()
+            && self
+                .blocks
+                .iter()
+                .zip(&self.starts)
+                .all(|(block, start)| block.disp == *start as isize)
+    }
+}
+
+/// Collects the copies of the old types a new type is made of.
+struct Builder {
+    blocks: Vec<Block>,
+    starts: Vec<usize>,
+    size: usize,
+    bounds: Option<(isize, isize)>,
+}
+
+impl Builder {
+    fn new() -> Builder {
+        Builder {
+            blocks: Vec::new(),
+            starts: Vec::new(),
+            size: 0,
+            bounds: None,
+        }
+    }
+
+    /// Appends an element of `old` displaced by `shift` bytes.
+    fn place(&mut self, shift: isize, old: MPI_Datatype) {
+        let info = type_info(old).unwrap();
+        let (lb, ub) = (shift + info.lb, shift + info.lb + info.extent);
+        self.bounds = Some(match self.bounds {
+            Some((min, max)) => (min.min(lb), max.max(ub)),
+            None => (lb, ub),
+        });
+
+        let whole = [Block {
+            disp: 0,
+            len: info.size as usize,
+            base: old,
+        }];
+        let blocks = match Context::types().map(old) {
+            Some(map) => &map.blocks[..],
+            None => &whole[..],
+        };
+        for block in blocks.iter().filter(|b| b.len != 0) {
+            let disp = shift + block.disp;
+            match self.blocks.last_mut() {
+                Some(last) if last.disp + last.len as isize == disp && last.base == block.base => {
+                    last.len += block.len
+                }
+                _ => {
+                    self.blocks.push(Block { disp, ..*block });
+                    self.starts.push(self.size);
+                }
+            }
+            self.size += block.len;
+        }
+    }
+
+    fn finish(self, comm: MPI_Comm) -> Result<Typemap, MpiError> {
+        if self.size > i32::MAX as usize {
+            return Err(Context::err_handler().call(comm, MPI_ERR_ARG));
+        }
+        let (lb, ub) = self.bounds.unwrap_or((0, 0));
+        Ok(Typemap {
+            info: TypeInfo {
+                size: self.size as i32,
+                lb,
+                extent: ub - lb,
+                ops: NONE,
+            },
+            blocks: self.blocks,
+            starts: self.starts,
+            committed: false,
+            freed: false,
+        })
+    }
+}
+
+/// The layout of `old`, which must not be freed yet.
+fn check_old(old: MPI_Datatype, comm: MPI_Comm) -> Result<&'static TypeInfo, MpiError> {
+    type_info(old).ok_or_else(|| Context::err_handler().call(comm, MPI_ERR_TYPE))
+}
+
+pub(crate) fn contiguous(cnt: i32, old: MPI_Datatype) -> Result<Typemap, MpiError> {
+    vector(cnt, 1, 1, old)
+}
+
+/// `cnt` blocks of `blocklen` elements, `stride` elements apart.
+pub(crate) fn vector(
+    cnt: i32,
+    blocklen: i32,
+    stride: i32,
+    old: MPI_Datatype,
+) -> Result<Typemap, MpiError> {
+    let extent = check_old(old, MPI_COMM_WORLD)?.extent;
+    hvector(cnt, blocklen, stride as isize * extent, old)
+}
+
+/// `cnt` blocks of `blocklen` elements, `stride` bytes apart.
+pub(crate) fn hvector(
+    cnt: i32,
+    blocklen: i32,
+    stride: isize,
+    old: MPI_Datatype,
+) -> Result<Typemap, MpiError> {
+    let comm = MPI_COMM_WORLD;
+    if cnt < 0 || blocklen < 0 {
+        return Err(Context::err_handler().call(comm, MPI_ERR_COUNT));
+    }
+    let extent = check_old(old, comm)?.extent;
+
+    let mut builder = Builder::new();
+    for i in 0..cnt as isize {
+        for j in 0..blocklen as isize {
+            builder.place(i * stride + j * extent, old);
+        }
+    }
+    builder.finish(comm)
+}
+
+/// Blocks of `blocklens[i]` elements at `disps[i]` elements.
+pub(crate) fn indexed(
+    blocklens: &[i32],
+    disps: &[i32],
+    old: MPI_Datatype,
+) -> Result<Typemap, MpiError> {
+    let comm = MPI_COMM_WORLD;
+    if blocklens.iter().any(|len| *len < 0) {
+        return Err(Context::err_handler().call(comm, MPI_ERR_COUNT));
+    }
+    let extent = check_old(old, comm)?.extent;
+
+    let mut builder = Builder::new();
+    for (len, disp) in blocklens.iter().zip(disps) {
+        for j in 0..*len as isize {
+            builder.place((*disp as isize + j) * extent, old);
+        }
+    }
+    builder.finish(comm)
+}
+
+/// Blocks of `blocklens[i]` elements of `types[i]` at `disps[i]` bytes.
+pub(crate) fn create_struct(
+    blocklens: &[i32],
+    disps: &[MPI_Aint],
+    types: &[MPI_Datatype],
+) -> Result<Typemap, MpiError> {
+    let comm = MPI_COMM_WORLD;
+    if blocklens.iter().any(|len| *len < 0) {
+        return Err(Context::err_handler().call(comm, MPI_ERR_COUNT));
+    }
+    let extents = types
+        .iter()
+        .map(|old| check_old(*old, comm).map(|info| info.extent))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut builder = Builder::new();
+    for (((len, disp), old), extent) in blocklens.iter().zip(disps).zip(types).zip(extents) {
+        for j in 0..*len as isize {
+            builder.place(disp + j * extent, *old);
+        }
+    }
+    builder.finish(comm)
+}
+
+/// The `subsizes` block at `starts` of an array of `sizes` elements, the
+/// extent stays the one of the whole array.
+pub(crate) fn subarray(
+    sizes: &[i32],
+    subsizes: &[i32],
+    starts: &[i32],
+    order: i32,
+    old: MPI_Datatype,
+) -> Result<Typemap, MpiError> {
+    let comm = MPI_COMM_WORLD;
+    let valid = !sizes.is_empty()
+        && (order == MPI_ORDER_C || order == MPI_ORDER_FORTRAN)
+        && sizes.iter().zip(subsizes).zip(starts).all(|((size, sub), start)| {
+            *size > 0 && *sub >= 0 && *start >= 0 && *sub <= size - start
+        });
+    if !valid {
+        return Err(Context::err_handler().call(comm, MPI_ERR_ARG));
+    }
+    let extent = check_old(old, comm)?.extent;
+
+    // Dimensions from the slowest to the fastest varying one.
+    let mut dims: Vec<(i32, i32, i32)> = sizes
+        .iter()
+        .zip(subsizes)
+        .zip(starts)
+        .map(|((size, sub), start)| (*size, *sub, *start))
+        .collect();
+    if order == MPI_ORDER_FORTRAN {
+        dims.reverse();
+    }
+    let mut strides = vec![extent; dims.len()];
+    for d in (0..dims.len() - 1).rev() {
+        strides[d] = strides[d + 1] * dims[d + 1].0 as isize;
+    }
+
+    let mut builder = Builder::new();
+    if dims.iter().all(|(_, sub, _)| *sub > 0) {
+        let mut idx = vec![0; dims.len()];
+        loop {
+            let shift = (0..dims.len())
+                .map(|d| (dims[d].2 + idx[d]) as isize * strides[d])
+                .sum();
+            builder.place(shift, old);
+
+            let Some(d) = (0..dims.len()).rev().find(|d| idx[*d] + 1 < dims[*d].1) else {
+                break;
+            };
+            idx[d] += 1;
+            idx[d + 1..].fill(0);
+        }
+    }
+
+    let mut map = builder.finish(comm)?;
+    map.info.lb = 0;
+    map.info.extent = strides[0] * dims[0].0 as isize;
+    Ok(map)
+}
+
+/// The derived datatypes, handle `FIRST_DERIVED + i` is slot `i`.
+pub(crate) struct TypeTable {
+    types: Vec<Option<Box<Typemap>>>,
+    /// Types built for Rust types by `#[derive(Typed)]`.
+    derived: Vec<(TypeId, MPI_Datatype)>,
+}
+
+impl TypeTable {
+    pub const fn new() -> TypeTable {
+        TypeTable {
+            types: Vec::new(),
+            derived: Vec::new(),
+        }
+    }
+
+    /// Any type still around, including freed ones pending requests use.
+    pub fn map(&self, dtype: MPI_Datatype) -> Option<&Typemap> {
+        let idx = usize::try_from(dtype.checked_sub(FIRST_DERIVED)?).ok()?;
+        self.types.get(idx)?.as_deref()
+    }
+
+    /// A type the user may still refer to.
+    pub fn get(&self, dtype: MPI_Datatype) -> Option<&Typemap> {
+        self.map(dtype).filter(|map| !map.freed)
+    }
+
+    /// Stores `map` in a free slot, a freed type is dropped for good once no
+    /// request refers to it anymore.
+    pub fn insert(&mut self, map: Typemap) -> MPI_Datatype {
+        let idx = (0..self.types.len()).find(|idx| match &self.types[*idx] {
+            None => true,
+            Some(old) => old.freed && !Context::shm().uses_type(FIRST_DERIVED + *idx as i32),
+        });
+        let idx = match idx {
+            Some(idx) => {
+                self.types[idx] = Some(Box::new(map));
+                idx
+            }
+            None => {
+                self.types.push(Some(Box::new(map)));
+                self.types.len() - 1
+            }
+        };
+        FIRST_DERIVED + idx as i32
+    }
+
+    pub fn commit(&mut self, dtype: MPI_Datatype) -> bool {
+        let idx = (dtype - FIRST_DERIVED) as usize;
+        match self.types.get_mut(idx) {
+            Some(Some(map)) if !map.freed => {
+                map.committed = true;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn free(&mut self, dtype: MPI_Datatype) -> bool {
+        let idx = (dtype - FIRST_DERIVED) as usize;
+        match self.types.get_mut(idx) {
+            Some(Some(map)) if !map.freed => {
+                map.freed = true;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// The type built for the Rust type `id`, unless the user freed it.
+    pub fn derived(&self, id: TypeId) -> Option<MPI_Datatype> {
+        let (_, dtype) = self.derived.iter().find(|(key, _)| *key == id)?;
+        self.get(*dtype).map(|_| *dtype)
+    }
+
+    pub fn set_derived(&mut self, id: TypeId, dtype: MPI_Datatype) {
+        self.derived.retain(|(key, _)| *key != id);
+        self.derived.push((id, dtype));
+    }
+
+    pub fn clear(&mut self) {
+        self.types.clear();
+        self.derived.clear();
+    }
+}
@@ -1,7 +1,10 @@
 use crate::debug_objs;
+use crate::metatypes::{pack, typemap};
 use crate::xfer::request::Request;
 use std::alloc::{alloc, dealloc, Layout};
+use std::any::TypeId;
 use std::marker::PhantomData;
+use std::mem::size_of;
 use std::slice::{from_raw_parts, from_raw_parts_mut};
 
 use crate::shared::*;
@@ -38,6 +41,117 @@ impl Typed for f64 {
     }
 }
 
+impl Typed for i16 {
+    fn into_mpi() -> i32 {
+        MPI_INT16_T
+    }
+}
+
+impl Typed for u16 {
+    fn into_mpi() -> i32 {
+        MPI_UINT16_T
+    }
+}
+
+impl Typed for u32 {
+    fn into_mpi() -> i32 {
+        MPI_UINT32_T
+    }
+}
+
+impl Typed for i64 {
+    fn into_mpi() -> i32 {
+        MPI_INT64_T
+    }
+}
+
+impl Typed for u64 {
+    fn into_mpi() -> i32 {
+        MPI_UINT64_T
+    }
+}
+
+impl Typed for isize {
+    fn into_mpi() -> i32 {
+        if size_of::<isize>() == 8 {
+            MPI_INT64_T
+        } else {
+            MPI_INT32_T
+        }
+    }
+}
+
+impl Typed for usize {
+    fn into_mpi() -> i32 {
+        if size_of::<usize>() == 8 {
+            MPI_UINT64_T
+        } else {
+            MPI_UINT32_T
+        }
+    }
+}
+
+impl Typed for f32 {
+    fn into_mpi() -> i32 {
+        MPI_FLOAT
+    }
+}
+
+impl Typed for bool {
+    fn into_mpi() -> i32 {
+        MPI_C_BOOL
+    }
+}
+
+impl<T: Typed + 'static, const N: usize> Typed for [T; N] {
+    const ALIGN: usize = T::ALIGN;
+    fn into_mpi() -> i32 {
+        derived_type::<Self>(|| vec![(0, N as i32, T::into_mpi())])
+    }
+}
+
+impl<T: Typed + 'static, U: Typed + 'static> Typed for (T, U) {
+    const ALIGN: usize = if T::ALIGN > U::ALIGN { T::ALIGN } else { U::ALIGN };
+    fn into_mpi() -> i32 {
+        derived_type::<Self>(|| {
+            vec![
+                (std::mem::offset_of!(Self, 0), 1, T::into_mpi()),
+                (std::mem::offset_of!(Self, 1), 1, U::into_mpi()),
+            ]
+        })
+    }
+}
+
+/// The datatype `#[derive(Typed)]` describes `T` with, built from the
+/// `(offset, count, datatype)` of its fields on first use. The extent is the
+/// size of `T` so the padding is part of every element.
+#[doc(hidden)]
+pub fn derived_type<T: 'static>(
+    fields: impl FnOnce() -> Vec<(usize, i32, MPI_Datatype)>,
+) -> MPI_Datatype {
+    let id = TypeId::of::<T>();
+    if let Some(dtype) = Context::types().derived(id) {
+        return dtype;
+    }
+
+    let fields = fields();
+    let blocklens: Vec<i32> = fields.iter().map(|field| field.1).collect();
+    let disps: Vec<MPI_Aint> = fields.iter().map(|field| field.0 as MPI_Aint).collect();
+    let types: Vec<MPI_Datatype> = fields.iter().map(|field| field.2).collect();
+    let Ok(mut map) = typemap::create_struct(&blocklens, &disps, &types) else {
+        return MPI_DATATYPE_NULL;
+    };
+    map.info.lb = 0;
+    map.info.extent = size_of::<T>() as MPI_Aint;
+    map.committed = true;
+
+    let dtype = Context::types().insert(map);
+    Context::types().set_derived(id, dtype);
+    let name = std::any::type_name::<T>();
+    debug_objs!("Types", "Derived type {dtype} for {name}");
+    dtype
+}
+
 pub struct Data<T: Typed> {
     data: *mut T,
     size: usize,
@@ -49,7 +163,7 @@ impl<T: Typed> Drop for Data<T> {
             unsafe {
                 dealloc(
                     self.data as *mut u8,
-                    Layout::from_size_align_unchecked(self.size * T::into_mpi() as usize, T::ALIGN),
+                    Layout::from_size_align_unchecked(self.size * size_of::<T>(), T::ALIGN),
                 );
             }
         }
@@ -75,7 +189,7 @@ impl<T: Typed> Data<T> {
         Data {
             data: unsafe {
                 alloc(Layout::from_size_align_unchecked(
-                    size * T::into_mpi() as usize,
+                    size * size_of::<T>(),
                     T::ALIGN,
                 )) as *mut T
             },
@@ -111,6 +225,81 @@ impl<T: Typed> Data<T> {
     }
 }
 
+/// Collects values of several types into one buffer, which is sent as
+/// `MPI_PACKED` bytes.
+pub struct Packer {
+    buf: Vec<u8>,
+}
+
+impl Default for Packer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl std::ops::Deref for Packer {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        &self.buf
+    }
+}
+
+impl Packer {
+    pub const fn new() -> Self {
+        Packer { buf: Vec::new() }
+    }
+
+    pub fn pack<T: Typed>(&mut self, data: &[T]) -> Result<&mut Self, MpiError> {
+        let cnt = data.len() as i32;
+        let len = pack::pack_size(cnt, T::into_mpi(), MPI_COMM_WORLD)? as usize;
+        let mut pos = self.buf.len() as i32;
+        self.buf.resize(self.buf.len() + len, 0);
+        pack::pack(
+            data.as_ptr() as *const c_void,
+            cnt,
+            T::into_mpi(),
+            &mut self.buf,
+            &mut pos,
+            MPI_COMM_WORLD,
+        )?;
+        Ok(self)
+    }
+
+    pub fn into_vec(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+/// Reads values back from a packed buffer in the order they were packed.
+pub struct Unpacker<'a> {
+    buf: &'a [u8],
+    pos: i32,
+}
+
+impl<'a> Unpacker<'a> {
+    pub const fn new(buf: &'a [u8]) -> Self {
+        Unpacker { buf, pos: 0 }
+    }
+
+    pub fn unpack<T: Typed>(&mut self, data: &mut [T]) -> Result<&mut Self, MpiError> {
+        pack::unpack(
+            self.buf,
+            &mut self.pos,
+            data.as_mut_ptr() as *mut c_void,
+            data.len() as i32,
+            T::into_mpi(),
+            MPI_COMM_WORLD,
+        )?;
+        Ok(self)
+    }
+
+    /// Bytes read so far.
+    pub const fn position(&self) -> usize {
+        self.pos as usize
+    }
+}
+
 #[macro_export]
 macro_rules! to_string {
     ($name:ident) => {
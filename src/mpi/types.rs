@@ -6,6 +6,7 @@ pub type MPI_Datatype = i32;
 pub type MPI_Comm = i32;
 pub type MPI_Op = i32;
 pub type MPI_Errhandler = i32;
+pub type MPI_Aint = isize;
 
 #[macro_export]
 macro_rules! cstr {
@@ -21,6 +22,7 @@ pub struct MPI_Status {
     pub MPI_TAG: i32,
     pub MPI_ERROR: i32,
     pub cnt: i32,
+    pub cancelled: i32,
 }
 
 impl Default for MPI_Status {
@@ -36,6 +38,18 @@ impl MPI_Status {
             MPI_TAG: 0,
             MPI_ERROR: 0,
             cnt: 0,
+            cancelled: 0,
+        }
+    }
+
+    /// Status returned for null and inactive requests.
+    pub const fn empty() -> Self {
+        MPI_Status {
+            MPI_SOURCE: MPI_ANY_SOURCE,
+            MPI_TAG: MPI_ANY_TAG,
+            MPI_ERROR: 0,
+            cnt: 0,
+            cancelled: 0,
         }
     }
 
@@ -45,17 +59,57 @@ impl MPI_Status {
     }
 }
 
+/// Opaque handle of a request, resolved with `Request::from_handle`.
 pub type MPI_Request = *mut Request;
+/// Opaque handle of a matched message, see `ShmData::message`.
+pub type MPI_Message = *mut Request;
+
+pub const MPI_MESSAGE_NULL: MPI_Message = std::ptr::null_mut();
+pub const MPI_MESSAGE_NO_PROC: MPI_Message = usize::MAX as MPI_Message;
+pub const MPI_REQUEST_NULL: MPI_Request = std::ptr::null_mut();
+
+pub const MPI_STATUS_IGNORE: *mut MPI_Status = std::ptr::null_mut();
+pub const MPI_STATUSES_IGNORE: *mut MPI_Status = std::ptr::null_mut();
 
 pub const MPI_UNDEFINED: i32 = -1;
 
+pub const MPI_ANY_TAG: i32 = -1;
+pub const MPI_ANY_SOURCE: i32 = -2;
+pub const MPI_PROC_NULL: i32 = -3;
+pub const MPI_BSEND_OVERHEAD: i32 = 32;
+
 pub const MPI_COMM_NULL: i32 = MPI_UNDEFINED;
 pub const MPI_COMM_SELF: i32 = 0;
 pub const MPI_COMM_WORLD: i32 = 1;
 
+pub const MPI_DATATYPE_NULL: i32 = 0;
 pub const MPI_BYTE: i32 = 1;
+pub const MPI_CHAR: i32 = 2;
+pub const MPI_SHORT: i32 = 3;
 pub const MPI_INT: i32 = 4;
+pub const MPI_LONG: i32 = 5;
+pub const MPI_LONG_LONG: i32 = 6;
+pub const MPI_FLOAT: i32 = 7;
 pub const MPI_DOUBLE: i32 = 8;
+pub const MPI_UNSIGNED_CHAR: i32 = 9;
+pub const MPI_UNSIGNED_SHORT: i32 = 10;
+pub const MPI_UNSIGNED: i32 = 11;
+pub const MPI_UNSIGNED_LONG: i32 = 12;
+pub const MPI_UNSIGNED_LONG_LONG: i32 = 13;
+pub const MPI_INT8_T: i32 = 14;
+pub const MPI_INT16_T: i32 = 15;
+pub const MPI_INT32_T: i32 = 16;
+pub const MPI_INT64_T: i32 = 17;
+pub const MPI_UINT8_T: i32 = 18;
+pub const MPI_UINT16_T: i32 = 19;
+pub const MPI_UINT32_T: i32 = 20;
+pub const MPI_UINT64_T: i32 = 21;
+pub const MPI_C_BOOL: i32 = 22;
+pub const MPI_C_DOUBLE_COMPLEX: i32 = 23;
+pub const MPI_PACKED: i32 = 24;
+
+pub const MPI_ORDER_C: i32 = 56;
+pub const MPI_ORDER_FORTRAN: i32 = 57;
 
 pub const MPI_MAX: i32 = 0;
 pub const MPI_MIN: i32 = 1;
@@ -85,6 +139,8 @@ pub enum MpiError {
     MPI_ERR_INTERN,
     MPI_ERR_PENDING,
     MPI_ERR_IN_STATUS,
+    MPI_ERR_NO_MEM,
+    MPI_ERR_CONVERSION,
     MPI_ERR_LASTCODE,
 }
 
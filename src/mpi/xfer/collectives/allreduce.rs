@@ -6,7 +6,7 @@ use crate::buffer::DynBuffer;
 use crate::context::Context;
 use crate::debug::DbgEntryExit;
 use crate::xfer::ppp::sendrecv;
-use crate::metatypes::check_type;
+use crate::metatypes::{check_reduce, check_type};
 use crate::{debug_coll, MPI_Comm, MPI_Datatype, MPI_Op, MpiResult};
 
 macro_rules! DbgEnEx {
@@ -45,6 +45,7 @@ pub fn allreduce_tree(
 ) -> MpiResult {
     check_type(dtype, comm)?;
     check_op(op, comm)?;
+    check_reduce(dtype, op, comm)?;
 
     DbgEnEx!("Allreduce");
 
@@ -85,7 +86,7 @@ pub fn allreduce_tree(
         FUNCTIONS[op as usize](sbuf.as_ptr(), rbuf.as_mut_ptr(), blk_size as i32, dtype);
 
         let mut i = 2;
-        let tbuf = DynBuffer::new(sbuf.len());
+        let mut tbuf = DynBuffer::new(sbuf.len());
 
         while i < n {
             debug_coll!("Allreduce", "Sendrecv to: {}", rank ^ i);
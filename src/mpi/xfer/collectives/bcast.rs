@@ -2,8 +2,8 @@ use super::keychanger::KeyChanger;
 use crate::debug::DbgEntryExit;
 use crate::xfer::ppp::recv::recv;
 use crate::xfer::ppp::send::send;
-use crate::xfer::request::Request;
-use crate::{debug_coll, shared::*, MPI_CHECK};
+use crate::xfer::request::{Cursor, Request, SendMode};
+use crate::{debug_coll, shared::*};
 
 pub type BCastFn = fn(&mut [u8], i32, MPI_Comm) -> MpiResult;
 
@@ -17,11 +17,9 @@ const BCAST_TAG: i32 = 1;
 
 pub fn bcast_shm(buf: &mut [u8], root: i32, comm: MPI_Comm) -> MpiResult {
     DbgEnEx!("Broadcast");
-    MPI_CHECK!(
-        root >= 0 && root < Context::comm_size(comm),
-        comm,
-        MPI_ERR_ROOT
-    );
+    if root < 0 || root >= Context::comm_size(comm) {
+        return Err(Context::err_handler().call(comm, MPI_ERR_ROOT));
+    }
 
     if Context::comm_size(comm) == 1 || buf.len() == 0 {
         return Ok(());
@@ -37,6 +35,7 @@ pub fn bcast_shm(buf: &mut [u8], root: i32, comm: MPI_Comm) -> MpiResult {
         if let Some(req) = new_req {
             *req = Request {
                 buf: buf.as_ptr() as *mut c_void,
+                dtype: MPI_BYTE,
                 stat: MPI_Status::new(),
                 comm,
                 flag: 0,
@@ -45,18 +44,23 @@ pub fn bcast_shm(buf: &mut [u8], root: i32, comm: MPI_Comm) -> MpiResult {
                 rank: -1,
                 isColl: true,
                 collRoot: root,
+                mode: SendMode::Standard,
+                seq: 0,
+                cursor: Cursor::new(),
+                persist: None,
+                freed: false,
             };
             req.wait(None)?;
             return Ok(());
         } else {
-            Context::err_handler().call(comm, MPI_ERR_INTERN);
-            return Err(MPI_ERR_INTERN);
+            return Err(Context::err_handler().call(comm, MPI_ERR_NO_MEM));
         }
     } else {
         let new_req = Context::shm().get_recv();
         if let Some(req) = new_req {
             *req = Request {
                 buf: buf.as_ptr() as *mut c_void,
+                dtype: MPI_BYTE,
                 stat: MPI_Status::new(),
                 comm,
                 flag: 0,
@@ -65,22 +69,24 @@ pub fn bcast_shm(buf: &mut [u8], root: i32, comm: MPI_Comm) -> MpiResult {
                 rank: -1,
                 isColl: true,
                 collRoot: root,
+                mode: SendMode::Standard,
+                seq: 0,
+                cursor: Cursor::new(),
+                persist: None,
+                freed: false,
             };
             req.wait(None)?;
             return Ok(());
         } else {
-            Context::err_handler().call(comm, MPI_ERR_INTERN);
-            return Err(MPI_ERR_INTERN);
+            return Err(Context::err_handler().call(comm, MPI_ERR_NO_MEM));
         }
     }
 }
 
 pub fn bcast_binaty_tree(buf: &mut [u8], mut root: i32, comm: MPI_Comm) -> MpiResult {
-    MPI_CHECK!(
-        root >= 0 && root < Context::comm_size(comm),
-        comm,
-        MPI_ERR_ROOT
-    );
+    if root < 0 || root >= Context::comm_size(comm) {
+        return Err(Context::err_handler().call(comm, MPI_ERR_ROOT));
+    }
 
     DbgEnEx!("Broadcast");
 
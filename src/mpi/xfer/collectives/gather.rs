@@ -68,7 +68,7 @@ pub fn gather_ring(sbuf: &[u8], rbuf: &mut [u8], root: i32, comm: MPI_Comm) -> M
                 Some(&mut stat),
             )?;
         } else {
-            let buf = crate::buffer::DynBuffer::new(rbuf.len());
+            let mut buf = crate::buffer::DynBuffer::new(rbuf.len());
             recv(
                 buf.to_slice(),
                 (csize + rank - 1) % csize,
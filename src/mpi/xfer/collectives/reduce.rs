@@ -6,10 +6,10 @@ use crate::debug::DbgEntryExit;
 use crate::types::MpiError::*;
 use crate::xfer::ppp::recv::recv;
 use crate::xfer::ppp::send::send;
-use crate::metatypes::{check_type, type_size};
+use crate::metatypes::{check_reduce, check_type, type_size};
 use crate::{
-    debug_xfer, MPI_Comm, MPI_Datatype, MPI_Op, MpiResult, MPI_CHECK,
-    MPI_MAX, MPI_MIN, MPI_SUM,
+    debug_xfer, MPI_Comm, MPI_Datatype, MPI_Op, MpiResult, MPI_MAX, MPI_MIN,
+    MPI_SUM,
 };
 
 macro_rules! DbgEnEx {
@@ -27,7 +27,10 @@ type FUNC = fn(*const u8, *mut u8, i32, i32);
 pub(super) const FUNCTIONS: [FUNC; 3] = [max, min, sum];
 
 pub(super) fn check_op(op: MPI_Op, comm: MPI_Comm) -> MpiResult {
-    MPI_CHECK!(matches!(op, MPI_MAX | MPI_MIN | MPI_SUM), comm, MPI_ERR_OP)
+    if !matches!(op, MPI_MAX | MPI_MIN | MPI_SUM) {
+        return Err(Context::err_handler().call(comm, MPI_ERR_OP));
+    }
+    Ok(())
 }
 
 pub fn reduce_ring(
@@ -40,6 +43,7 @@ pub fn reduce_ring(
 ) -> MpiResult {
     check_op(op, comm)?;
     check_type(dtype, comm)?;
+    check_reduce(dtype, op, comm)?;
 
     DbgEnEx!("Reduce");
 
@@ -70,7 +74,7 @@ pub fn reduce_ring(
     let diff = (size + rank - root) % size;
 
     let buff: &mut [u8];
-    let _dyn_buffer: DynBuffer;
+    let mut _dyn_buffer: DynBuffer;
     if rank != root {
         _dyn_buffer = DynBuffer::new(sbuf.len());
         buff = _dyn_buffer.to_slice();
@@ -86,7 +90,7 @@ pub fn reduce_ring(
         FUNCTIONS[op as usize](sbuf.as_ptr(), buff.as_mut_ptr(), blk_size as i32, dtype);
     }
 
-    let tbuf: DynBuffer;
+    let mut tbuf: DynBuffer;
     if diff % 4 != 0 {
         if diff % 2 == 0 {
             if diff < size - 1 {
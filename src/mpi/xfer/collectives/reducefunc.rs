@@ -1,4 +1,5 @@
 use std::{
+    ffi::{c_int, c_long, c_longlong, c_short, c_uint, c_ulong, c_ulonglong, c_ushort},
     fmt::Display,
     ops::AddAssign,
     slice::{from_raw_parts, from_raw_parts_mut},
@@ -49,29 +50,60 @@ where
     }
 }
 
-pub fn sum(src: *const u8, dst: *mut u8, len: i32, dtype: MPI_Datatype) {
-    match dtype {
-        MPI_BYTE => sum_proc(src as *const i8, dst as *mut i8, len as usize),
-        MPI_INT => sum_proc(src as *const i32, dst as *mut i32, len as usize),
-        MPI_DOUBLE => sum_proc(src as *const f64, dst as *mut f64, len as usize),
-        _ => unreachable!(),
+/// Layout of `MPI_C_DOUBLE_COMPLEX`.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Complex {
+    re: f64,
+    im: f64,
+}
+
+impl AddAssign for Complex {
+    fn add_assign(&mut self, rhs: Complex) {
+        self.re += rhs.re;
+        self.im += rhs.im;
     }
 }
 
+/// Runs `$proc` on the element type of `$dtype`, the types listed after the
+/// arithmetic ones only support the kernels that list them.
+macro_rules! dispatch {
+    ($proc:ident, $src:ident, $dst:ident, $len:ident, $dtype:ident $(, $name:ident => $ty:ty)*) => {
+        match $dtype {
+            MPI_BYTE | MPI_INT8_T => $proc($src as *const i8, $dst as *mut i8, $len as usize),
+            MPI_SHORT => $proc($src as *const c_short, $dst as *mut c_short, $len as usize),
+            MPI_INT => $proc($src as *const c_int, $dst as *mut c_int, $len as usize),
+            MPI_LONG => $proc($src as *const c_long, $dst as *mut c_long, $len as usize),
+            MPI_LONG_LONG => $proc($src as *const c_longlong, $dst as *mut c_longlong, $len as usize),
+            MPI_FLOAT => $proc($src as *const f32, $dst as *mut f32, $len as usize),
+            MPI_DOUBLE => $proc($src as *const f64, $dst as *mut f64, $len as usize),
+            MPI_UNSIGNED_CHAR | MPI_UINT8_T => $proc($src, $dst, $len as usize),
+            MPI_UNSIGNED_SHORT => $proc($src as *const c_ushort, $dst as *mut c_ushort, $len as usize),
+            MPI_UNSIGNED => $proc($src as *const c_uint, $dst as *mut c_uint, $len as usize),
+            MPI_UNSIGNED_LONG => $proc($src as *const c_ulong, $dst as *mut c_ulong, $len as usize),
+            MPI_UNSIGNED_LONG_LONG => {
+                $proc($src as *const c_ulonglong, $dst as *mut c_ulonglong, $len as usize)
+            }
+            MPI_INT16_T => $proc($src as *const i16, $dst as *mut i16, $len as usize),
+            MPI_INT32_T => $proc($src as *const i32, $dst as *mut i32, $len as usize),
+            MPI_INT64_T => $proc($src as *const i64, $dst as *mut i64, $len as usize),
+            MPI_UINT16_T => $proc($src as *const u16, $dst as *mut u16, $len as usize),
+            MPI_UINT32_T => $proc($src as *const u32, $dst as *mut u32, $len as usize),
+            MPI_UINT64_T => $proc($src as *const u64, $dst as *mut u64, $len as usize),
+            $($name => $proc($src as *const $ty, $dst as *mut $ty, $len as usize),)*
+            _ => unreachable!(),
+        }
+    };
+}
+
+pub fn sum(src: *const u8, dst: *mut u8, len: i32, dtype: MPI_Datatype) {
+    dispatch!(sum_proc, src, dst, len, dtype, MPI_C_DOUBLE_COMPLEX => Complex)
+}
+
 pub fn min(src: *const u8, dst: *mut u8, len: i32, dtype: MPI_Datatype) {
-    match dtype {
-        MPI_BYTE => min_proc(src as *const i8, dst as *mut i8, len as usize),
-        MPI_INT => min_proc(src as *const i32, dst as *mut i32, len as usize),
-        MPI_DOUBLE => min_proc(src as *const f64, dst as *mut f64, len as usize),
-        _ => unreachable!(),
-    }
+    dispatch!(min_proc, src, dst, len, dtype)
 }
 
 pub fn max(src: *const u8, dst: *mut u8, len: i32, dtype: MPI_Datatype) {
-    match dtype {
-        MPI_BYTE => max_proc(src as *const i8, dst as *mut i8, len as usize),
-        MPI_INT => max_proc(src as *const i32, dst as *mut i32, len as usize),
-        MPI_DOUBLE => max_proc(src as *const f64, dst as *mut f64, len as usize),
-        _ => unreachable!(),
-    }
+    dispatch!(max_proc, src, dst, len, dtype)
 }
@@ -1,27 +1,49 @@
-use crate::{uninit, MPI_Comm, MPI_Status, MpiError};
+use crate::{
+    buffer::DynBuffer, metatypes::engine, uninit, MPI_Comm, MPI_Request, MPI_Status, MpiError,
+};
 
-use self::{recv::irecv, send::isend};
+use self::{recv::irecv, send::isend, userbuf::UserBuf};
 
 use super::request::Request;
 
+pub(crate) mod probe;
 pub(crate) mod recv;
 pub(crate) mod send;
+pub(crate) mod userbuf;
 
-pub fn sendrecv(
-    sbuf: &[u8],
+pub fn sendrecv<S: UserBuf + ?Sized, R: UserBuf + ?Sized>(
+    sbuf: &S,
     dest: i32,
     stag: i32,
-    rbuf: &mut [u8],
+    rbuf: &mut R,
     src: i32,
     rtag: i32,
     comm: MPI_Comm,
 ) -> Result<MPI_Status, MpiError> {
-    let mut req: [*mut Request; 2] = uninit();
+    let mut req: [MPI_Request; 2] = uninit();
     let mut stat: [MPI_Status; 2] = uninit();
 
-    req[0] = isend(sbuf, dest, stag, comm)?;
-    req[1] = irecv(rbuf, src, rtag, comm)?;
-    Request::wait_all(&mut req, &mut stat)?;
+    req[0] = Request::handle(isend(sbuf, dest, stag, comm)?)?;
+    req[1] = Request::handle(irecv(rbuf, src, rtag, comm)?)?;
+    Request::wait_all(&mut req, Some(&mut stat))?;
 
     Ok(stat[1])
 }
+
+/// Sends and receives through the same buffer, the outgoing data is packed into
+/// a temporary copy so the receive can overwrite `buf` right away.
+pub fn sendrecv_replace<B: UserBuf + ?Sized>(
+    buf: &mut B,
+    dest: i32,
+    stag: i32,
+    src: i32,
+    rtag: i32,
+    comm: MPI_Comm,
+) -> Result<MPI_Status, MpiError> {
+    let (ptr, len, layout) = buf.parts(comm)?;
+    let mut data = DynBuffer::new((len as usize).max(1));
+    let stage = &mut data.to_slice()[..len as usize];
+    engine::pack(layout, ptr, 0, stage.as_mut_ptr() as *mut _, len as usize);
+
+    sendrecv(&*stage, dest, stag, buf, src, rtag, comm)
+}
@@ -0,0 +1,110 @@
+use crate::backend::wait::Backoff;
+use crate::context::Context;
+use crate::debug::DbgEntryExit;
+use crate::{debug_xfer, shared::*};
+
+macro_rules! DbgEnEx {
+    ($name:literal) => {
+        let _dbgEntryExit = DbgEntryExit::new(|s| debug_xfer!($name, "{s}"));
+    };
+}
+
+fn probe_args(rank: i32, tag: i32, comm: MPI_Comm) -> Result<(i32, i32), MpiError> {
+    let src = if rank == MPI_ANY_SOURCE {
+        MPI_ANY_SOURCE
+    } else {
+        Context::comm().rank_map(comm, rank)?
+    };
+
+    if tag != MPI_ANY_TAG && !(0..=32767).contains(&tag) {
+        return Err(Context::err_handler().call(comm, MPI_ERR_TAG));
+    }
+
+    let tag = Context::comm().tag_map(comm, tag)?;
+    debug_xfer!("Probe", "Probe call from {src} with tag {tag}");
+
+    let code = Context::progress();
+    if let Err(code) = code {
+        return Err(Context::err_handler().call(comm, code));
+    }
+
+    Ok((src, tag))
+}
+
+fn null_stat() -> MPI_Status {
+    MPI_Status {
+        MPI_SOURCE: MPI_PROC_NULL,
+        MPI_TAG: MPI_ANY_TAG,
+        ..MPI_Status::new()
+    }
+}
+
+fn unmap_stat(mut stat: MPI_Status, comm: MPI_Comm) -> MPI_Status {
+    stat.MPI_SOURCE = Context::comm().rank_unmap(comm, stat.MPI_SOURCE);
+    stat.MPI_TAG = Context::comm().tag_unmap(comm, stat.MPI_TAG);
+    stat
+}
+
+pub(crate) fn iprobe(rank: i32, tag: i32, comm: MPI_Comm) -> Result<Option<MPI_Status>, MpiError> {
+    DbgEnEx!("Probe");
+    if rank == MPI_PROC_NULL {
+        return Ok(Some(null_stat()));
+    }
+
+    let (src, tag) = probe_args(rank, tag, comm)?;
+
+    match Context::shm().probe(src, tag) {
+        Ok(stat) => Ok(stat.map(|stat| unmap_stat(stat, comm))),
+        Err(code) => Err(Context::err_handler().call(comm, code)),
+    }
+}
+
+pub(crate) fn probe(rank: i32, tag: i32, comm: MPI_Comm) -> Result<MPI_Status, MpiError> {
+    let mut backoff = Backoff::new();
+    loop {
+        if let Some(stat) = iprobe(rank, tag, comm)? {
+            return Ok(stat);
+        }
+        backoff.snooze();
+    }
+}
+
+pub(crate) fn improbe(
+    rank: i32,
+    tag: i32,
+    comm: MPI_Comm,
+) -> Result<Option<(MPI_Message, MPI_Status)>, MpiError> {
+    DbgEnEx!("Mprobe");
+    if rank == MPI_PROC_NULL {
+        return Ok(Some((MPI_MESSAGE_NO_PROC, null_stat())));
+    }
+
+    let (src, tag) = probe_args(rank, tag, comm)?;
+
+    match Context::shm().mprobe(src, tag) {
+        Ok(Some(msg)) => unsafe {
+            (*msg).comm = comm;
+            let stat = unmap_stat((*msg).stat, comm);
+            match Context::shm().message(msg) {
+                Ok(handle) => Ok(Some((handle, stat))),
+                Err(code) => Err(Context::err_handler().call(comm, code)),
+            }
+        },
+        Ok(None) => Ok(None),
+        Err(code) => Err(Context::err_handler().call(comm, code)),
+    }
+}
+
+pub(crate) fn mprobe(
+    rank: i32,
+    tag: i32,
+    comm: MPI_Comm,
+) -> Result<(MPI_Message, MPI_Status), MpiError> {
+    let mut backoff = Backoff::new();
+    loop {
+        if let Some(res) = improbe(rank, tag, comm)? {
+            return Ok(res);
+        }
+        backoff.snooze();
+    }
+}
@@ -1,10 +1,8 @@
+use super::userbuf::UserBuf;
 use crate::context::Context;
 use crate::debug::DbgEntryExit;
-use crate::object::types::Typed;
-use crate::xfer::request::Request;
-use crate::{debug_xfer, shared::*, MPI_CHECK};
-use std::ffi::c_void;
-use crate::metatypes::type_size;
+use crate::xfer::request::{Cursor, Persist, Request, SendMode};
+use crate::{debug_xfer, shared::*};
 
 macro_rules! DbgEnEx {
     ($name:literal) => {
@@ -12,76 +10,131 @@ macro_rules! DbgEnEx {
     };
 }
 
-pub(crate) fn irecv<T: Typed>(
-    buf: &mut [T],
+fn recv_buffered<B: UserBuf + ?Sized>(
+    buf: &mut B,
+    msg: *mut Request,
+    comm: MPI_Comm,
+) -> Result<&'_ mut Request, MpiError> {
+    let (data, cnt, dtype) = buf.parts(comm)?;
+    let Some(r) = Context::shm().get_recv() else {
+        return Err(Context::err_handler().call(comm, MPI_ERR_NO_MEM));
+    };
+
+    *r = Request {
+        buf: data,
+        dtype,
+        stat: MPI_Status::new(),
+        comm,
+        flag: 0,
+        tag: 0,
+        cnt,
+        rank: 0,
+        isColl: false,
+        collRoot: -1,
+        mode: SendMode::Standard,
+        seq: 0,
+        cursor: Cursor::new(),
+        persist: None,
+        freed: false,
+    };
+    Context::shm().complete_from(r, msg);
+    Ok(r)
+}
+
+pub(crate) fn irecv<B: UserBuf + ?Sized>(
+    buf: &mut B,
     rank: i32,
     tag: i32,
     comm: MPI_Comm,
+) -> Result<&'_ mut Request, MpiError> {
+    post_recv(buf, rank, tag, comm, false)
+}
+
+/// Creates an inactive receive request which is reused by every `MPI_Start`.
+pub(crate) fn recv_init<B: UserBuf + ?Sized>(
+    buf: &mut B,
+    rank: i32,
+    tag: i32,
+    comm: MPI_Comm,
+) -> Result<&'_ mut Request, MpiError> {
+    post_recv(buf, rank, tag, comm, true)
+}
+
+fn post_recv<B: UserBuf + ?Sized>(
+    buf: &mut B,
+    rank: i32,
+    tag: i32,
+    comm: MPI_Comm,
+    persistent: bool,
 ) -> Result<&'_ mut Request, MpiError> {
     DbgEnEx!("Recv");
 
-    let src = Context::comm().rank_map(comm, rank);
+    let src = if rank == MPI_ANY_SOURCE || rank == MPI_PROC_NULL {
+        rank
+    } else {
+        Context::comm().rank_map(comm, rank)?
+    };
 
-    MPI_CHECK!(rank != Context::rank(), comm, MPI_ERR_INTERN);
-    MPI_CHECK!(tag >= 0 && tag <= 32767, comm, MPI_ERR_INTERN);
+    if tag != MPI_ANY_TAG && !(0..=32767).contains(&tag) {
+        return Err(Context::err_handler().call(comm, MPI_ERR_TAG));
+    }
 
-    let tag = Context::comm().tag_map(comm, tag);
+    let tag = Context::comm().tag_map(comm, tag)?;
     debug_xfer!("Recv", "Recv call from {src} with tag {tag}");
+    let (data, cnt, dtype) = buf.parts(comm)?;
 
     let code = Context::progress();
     if let Err(code) = code {
         return Err(Context::err_handler().call(comm, code));
     }
 
-    if let Some(r) = Context::shm().find_unexp(src, tag) {
-        debug_xfer!("Recv", "Unexpected rank: {}, tag: {}", r.rank, r.tag);
-        if r.cnt > buf.len() as i32 * T::into_mpi() {
-            debug_xfer!("Recv", "Error truncate for unexpected data");
-            return Err(Context::err_handler().call(comm, MPI_ERR_TRUNCATE));
-        }
+    let unexp = if persistent || src == MPI_PROC_NULL {
+        None
+    } else {
+        Context::shm().find_unexp(src, tag)
+    };
 
-        unsafe {
-            (buf.as_mut_ptr() as *mut c_void).copy_from(r.buf, r.cnt as usize);
-            let layout = std::alloc::Layout::from_size_align_unchecked(r.cnt as usize, 1);
-            std::alloc::dealloc(r.buf as *mut u8, layout);
-        }
-        *r = Request {
-            buf: buf.as_ptr() as *mut T as *mut c_void,
-            stat: MPI_Status::new(),
-            comm,
-            flag: 1,
-            tag,
-            cnt: buf.len() as i32 * type_size(T::into_mpi())?,
-            rank: src,
-            isColl: false,
-            collRoot: -1
-        };
-        return Ok(r);
+    if let Some(r) = unexp {
+        debug_xfer!("Recv", "Unexpected rank: {}, tag: {}", r.rank, r.tag);
+        return recv_buffered(buf, r, comm);
     } else {
         debug_xfer!("Recv", "Create new request");
         let rreq = Context::shm().get_recv();
         if let Some(req) = rreq {
             *req = Request {
-                buf: buf.as_ptr() as *mut T as *mut c_void,
+                buf: data,
+                dtype,
                 stat: MPI_Status::new(),
                 comm,
                 flag: 0,
                 tag,
-                cnt: buf.len() as i32 * type_size(T::into_mpi())?,
+                cnt,
                 rank: src,
                 isColl: false,
-                collRoot: -1
+                collRoot: -1,
+                mode: SendMode::Standard,
+                seq: 0,
+                cursor: Cursor::new(),
+                persist: persistent.then_some(Persist {
+                    rank: src,
+                    tag,
+                    cnt,
+                    active: false,
+                }),
+                freed: false,
             };
+            if src == MPI_PROC_NULL {
+                req.complete_null();
+            }
             return Ok(req);
         } else {
-            Context::err_handler().call(comm, MPI_ERR_INTERN);
-            return Err(MPI_ERR_INTERN);
+            return Err(Context::err_handler().call(comm, MPI_ERR_NO_MEM));
         }
     }
 }
 
-pub(crate) fn recv<T: Typed>(
-    buf: &mut [T],
+pub(crate) fn recv<B: UserBuf + ?Sized>(
+    buf: &mut B,
     rank: i32,
     tag: i32,
     comm: MPI_Comm,
@@ -93,3 +146,33 @@ pub(crate) fn recv<T: Typed>(
 
     Ok(())
 }
+
+pub(crate) fn imrecv<B: UserBuf + ?Sized>(
+    buf: &mut B,
+    msg: MPI_Message,
+) -> Result<&'_ mut Request, MpiError> {
+    DbgEnEx!("Mrecv");
+    if msg.is_null() {
+        return Err(Context::err_handler().call(MPI_COMM_WORLD, MPI_ERR_REQUEST));
+    }
+    if msg == MPI_MESSAGE_NO_PROC {
+        return post_recv(buf, MPI_PROC_NULL, MPI_ANY_TAG, MPI_COMM_WORLD, false);
+    }
+
+    let Some(msg) = Context::shm().resolve_message(msg) else {
+        debug_xfer!("Mrecv", "Invalid message handle {msg:?}");
+        return Err(Context::err_handler().call(MPI_COMM_WORLD, MPI_ERR_REQUEST));
+    };
+    recv_buffered(buf, msg, unsafe { (*msg).comm })
+}
+
+pub(crate) fn mrecv<B: UserBuf + ?Sized>(
+    buf: &mut B,
+    msg: MPI_Message,
+    pstat: Option<&mut MPI_Status>,
+) -> MpiResult {
+    let req = imrecv(buf, msg)?;
+    req.wait(pstat)?;
+
+    Ok(())
+}
@@ -1,10 +1,11 @@
+use super::userbuf::UserBuf;
+use crate::backend::wait::Backoff;
 use crate::context::Context;
 use crate::debug::DbgEntryExit;
-use crate::object::types::Typed;
-use crate::xfer::request::Request;
-use crate::{debug_xfer, shared::*, MPI_CHECK};
+use crate::metatypes::engine;
+use crate::xfer::request::{Cursor, Persist, Request, SendMode};
+use crate::{debug_xfer, shared::*};
 use std::ffi::c_void;
-use crate::metatypes::type_size;
 
 macro_rules! DbgEnEx {
     ($name:literal) => {
@@ -12,19 +13,35 @@ macro_rules! DbgEnEx {
     };
 }
 
-pub(crate) fn isend<T: Typed>(
-    buf: &[T],
+pub(crate) fn isend<B: UserBuf + ?Sized>(
+    buf: &B,
     rank: i32,
     tag: i32,
     comm: MPI_Comm,
+) -> Result<&'_ mut Request, MpiError> {
+    isend_mode(buf, rank, tag, comm, SendMode::Standard)
+}
+
+fn isend_mode<B: UserBuf + ?Sized>(
+    buf: &B,
+    rank: i32,
+    tag: i32,
+    comm: MPI_Comm,
+    mode: SendMode,
 ) -> Result<&'_ mut Request, MpiError> {
     DbgEnEx!("Send");
 
-    let dest = Context::comm().rank_map(comm, rank);
+    let dest = if rank == MPI_PROC_NULL {
+        MPI_PROC_NULL
+    } else {
+        Context::comm().rank_map(comm, rank)?
+    };
 
-    MPI_CHECK!(dest != Context::rank(), comm, MPI_ERR_INTERN)?;
-    MPI_CHECK!(tag >= 0 && tag <= 32767, comm, MPI_ERR_TAG)?;
-    let tag = Context::comm().tag_map(comm, tag);
+    if !(0..=32767).contains(&tag) {
+        return Err(Context::err_handler().call(comm, MPI_ERR_TAG));
+    }
+    let tag = Context::comm().tag_map(comm, tag)?;
+    let (data, cnt, dtype) = buf.parts(comm)?;
     debug_xfer!("Send", "Send call to {dest} with tag {tag}");
 
     let code = Context::progress();
@@ -35,26 +52,191 @@ pub(crate) fn isend<T: Typed>(
     let new_req = Context::shm().get_send();
     if let Some(req) = new_req {
         *req = Request {
-            buf: buf.as_ptr() as *mut T as *mut c_void,
+            buf: data,
+            dtype,
             stat: MPI_Status::new(),
             comm,
             flag: 0,
             tag,
-            cnt: buf.len() as i32 * type_size(T::into_mpi())?,
+            cnt,
             rank: dest,
             isColl: false,
-            collRoot: -1
+            collRoot: -1,
+            mode,
+            seq: 0,
+            cursor: Cursor::new(),
+            persist: None,
+            freed: false,
         };
+        if dest == MPI_PROC_NULL {
+            req.complete_null();
+        }
         return Ok(req);
     } else {
-        Context::err_handler().call(comm, MPI_ERR_INTERN);
-        return Err(MPI_ERR_INTERN);
+        return Err(Context::err_handler().call(comm, MPI_ERR_NO_MEM));
     }
 }
 
-pub(crate) fn send<T: Typed>(buf: &[T], rank: i32, tag: i32, comm: MPI_Comm) -> MpiResult {
+pub(crate) fn send<B: UserBuf + ?Sized>(buf: &B, rank: i32, tag: i32, comm: MPI_Comm) -> MpiResult {
     let req = isend(buf, rank, tag, comm)?;
     req.wait(None)?;
 
     Ok(())
 }
+
+/// Creates an inactive send request which is reused by every `MPI_Start`.
+pub(crate) fn send_init<B: UserBuf + ?Sized>(
+    buf: &B,
+    rank: i32,
+    tag: i32,
+    comm: MPI_Comm,
+) -> Result<&'_ mut Request, MpiError> {
+    let req = isend(buf, rank, tag, comm)?;
+    req.persist = Some(Persist {
+        rank: req.rank,
+        tag: req.tag,
+        cnt: req.cnt,
+        active: false,
+    });
+    Ok(req)
+}
+
+/// Completes once the receiver has matched the message.
+pub(crate) fn issend<B: UserBuf + ?Sized>(
+    buf: &B,
+    rank: i32,
+    tag: i32,
+    comm: MPI_Comm,
+) -> Result<&'_ mut Request, MpiError> {
+    isend_mode(buf, rank, tag, comm, SendMode::Synchronous)
+}
+
+pub(crate) fn ssend<B: UserBuf + ?Sized>(
+    buf: &B,
+    rank: i32,
+    tag: i32,
+    comm: MPI_Comm,
+) -> MpiResult {
+    let req = issend(buf, rank, tag, comm)?;
+    req.wait(None)?;
+
+    Ok(())
+}
+
+/// The matching receive is required to be posted already, so the message
+/// is transferred the same way as in the standard mode.
+pub(crate) fn irsend<B: UserBuf + ?Sized>(
+    buf: &B,
+    rank: i32,
+    tag: i32,
+    comm: MPI_Comm,
+) -> Result<&'_ mut Request, MpiError> {
+    isend_mode(buf, rank, tag, comm, SendMode::Ready)
+}
+
+pub(crate) fn rsend<B: UserBuf + ?Sized>(
+    buf: &B,
+    rank: i32,
+    tag: i32,
+    comm: MPI_Comm,
+) -> MpiResult {
+    let req = irsend(buf, rank, tag, comm)?;
+    req.wait(None)?;
+
+    Ok(())
+}
+
+/// Packs the data into the attached buffer and completes immediately, the copy
+/// is sent by the progress engine and released once it is pushed out.
+pub(crate) fn ibsend<'a, B: UserBuf + ?Sized>(
+    buf: &B,
+    rank: i32,
+    tag: i32,
+    comm: MPI_Comm,
+) -> Result<&'a mut Request, MpiError> {
+    let (ptr, len, layout) = buf.parts(comm)?;
+    let len = len as usize;
+    let data = match Context::bsend().alloc(len) {
+        Some(data) => data,
+        None => return Err(Context::err_handler().call(comm, MPI_ERR_BUFFER)),
+    };
+    engine::pack(layout, ptr, 0, data as *mut c_void, len);
+
+    let copy = unsafe { std::slice::from_raw_parts(data, len) };
+    let res = isend_mode(copy, rank, tag, comm, SendMode::Buffered);
+    let inner = match res {
+        Ok(req) => req as *mut Request,
+        Err(code) => {
+            Context::bsend().release(data);
+            return Err(code);
+        }
+    };
+
+    let new_req = Context::shm().get_send();
+    if let Some(req) = new_req {
+        *req = Request {
+            buf: data as *mut c_void,
+            dtype: MPI_BYTE,
+            stat: MPI_Status::new(),
+            comm,
+            flag: 1,
+            tag: unsafe { (*inner).tag },
+            cnt: len as i32,
+            rank: unsafe { (*inner).rank },
+            isColl: false,
+            collRoot: -1,
+            mode: SendMode::Standard,
+            seq: 0,
+            cursor: Cursor::new(),
+            persist: None,
+            freed: false,
+        };
+        req.stat.MPI_SOURCE = req.rank;
+        req.stat.MPI_TAG = req.tag;
+        req.stat.cnt = req.cnt;
+        return Ok(req);
+    } else {
+        return Err(Context::err_handler().call(comm, MPI_ERR_NO_MEM));
+    }
+}
+
+pub(crate) fn bsend<B: UserBuf + ?Sized>(
+    buf: &B,
+    rank: i32,
+    tag: i32,
+    comm: MPI_Comm,
+) -> MpiResult {
+    let req = ibsend(buf, rank, tag, comm)?;
+    req.wait(None)?;
+
+    Ok(())
+}
+
+pub(crate) fn buffer_attach(buf: *mut c_void, size: i32) -> MpiResult {
+    if Context::bsend().is_attached() {
+        return Err(Context::err_handler().call(MPI_COMM_WORLD, MPI_ERR_BUFFER));
+    }
+    if buf.is_null() || size < 0 {
+        return Err(Context::err_handler().call(MPI_COMM_WORLD, MPI_ERR_ARG));
+    }
+    Context::bsend().attach(buf as *mut u8, size as usize);
+
+    Ok(())
+}
+
+/// Waits until every buffered message is sent before giving the buffer back.
+pub(crate) fn buffer_detach() -> Result<(*mut c_void, i32), MpiError> {
+    if !Context::bsend().is_attached() {
+        return Err(Context::err_handler().call(MPI_COMM_WORLD, MPI_ERR_BUFFER));
+    }
+    let mut backoff = Backoff::new();
+    while !Context::bsend().is_empty() {
+        if let Err(code) = Context::progress() {
+            return Err(Context::err_handler().call(MPI_COMM_WORLD, code));
+        }
+        backoff.snooze();
+    }
+    let (buf, size) = Context::bsend().detach();
+
+    Ok((buf as *mut c_void, size as i32))
+}
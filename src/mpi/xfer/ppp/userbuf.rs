@@ -0,0 +1,56 @@
+use crate::metatypes::{check_type, layout, type_info, type_size};
+use crate::object::types::Typed;
+use crate::shared::*;
+
+/// A buffer handed to point-to-point communication.
+pub(crate) trait UserBuf {
+    /// Start of the data, its packed length in bytes and the layout the
+    /// datatype engine walks it with.
+    fn parts(&self, comm: MPI_Comm) -> Result<(*mut c_void, i32, MPI_Datatype), MpiError>;
+}
+
+impl<T: Typed> UserBuf for [T] {
+    fn parts(&self, _: MPI_Comm) -> Result<(*mut c_void, i32, MPI_Datatype), MpiError> {
+        let dtype = T::into_mpi();
+        let len = self.len() as i32 * type_size(dtype)?;
+        Ok((self.as_ptr() as *mut c_void, len, layout(dtype)))
+    }
+}
+
+impl<T: Typed, const N: usize> UserBuf for [T; N] {
+    fn parts(&self, comm: MPI_Comm) -> Result<(*mut c_void, i32, MPI_Datatype), MpiError> {
+        self[..].parts(comm)
+    }
+}
+
+/// `cnt` elements of any committed datatype, as passed through the C API.
+pub(crate) struct TypedBuf {
+    buf: *mut c_void,
+    cnt: i32,
+    dtype: MPI_Datatype,
+}
+
+impl TypedBuf {
+    pub fn new(buf: *const c_void, cnt: i32, dtype: MPI_Datatype) -> TypedBuf {
+        TypedBuf {
+            buf: buf as *mut c_void,
+            cnt,
+            dtype,
+        }
+    }
+}
+
+impl UserBuf for TypedBuf {
+    fn parts(&self, comm: MPI_Comm) -> Result<(*mut c_void, i32, MPI_Datatype), MpiError> {
+        check_type(self.dtype, comm)?;
+        if self.cnt < 0 {
+            return Err(Context::err_handler().call(comm, MPI_ERR_COUNT));
+        }
+
+        let len = self.cnt as i64 * type_info(self.dtype).unwrap().size as i64;
+        if len > i32::MAX as i64 {
+            return Err(Context::err_handler().call(comm, MPI_ERR_COUNT));
+        }
+        Ok((self.buf, len as i32, layout(self.dtype)))
+    }
+}
@@ -1,3 +1,5 @@
+use crate::backend::wait::Backoff;
+use crate::communicator::group::CommGroup;
 use crate::debug::DbgEntryExit;
 use crate::debug_xfer;
 use crate::shared::*;
@@ -8,9 +10,56 @@ macro_rules! DbgEnEx {
     };
 }
 
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum SendMode {
+    Standard,
+    Synchronous,
+    Ready,
+    Buffered,
+}
+
+/// Position of a transfer spanning several cells, kept between progress calls.
+/// `cell` is the ring index of the next cell to copy, or -1 while idle. A
+/// rendezvous send instead keeps the cell of its header until the receiver
+/// has pulled the data. A receive sets `rndv` when it could not pull the data
+/// and gets it through the cells after the header.
+#[derive(Clone, Copy)]
+pub struct Cursor {
+    pub done: i32,
+    pub cell: i32,
+    pub rndv: bool,
+}
+
+impl Cursor {
+    pub const fn new() -> Self {
+        Cursor {
+            done: 0,
+            cell: -1,
+            rndv: false,
+        }
+    }
+
+    #[inline(always)]
+    pub fn is_idle(&self) -> bool {
+        self.cell < 0
+    }
+}
+
+/// Matching arguments of a persistent request, restored on every start since
+/// completion overwrites them with the ones of the received message.
+#[derive(Clone, Copy)]
+pub struct Persist {
+    pub rank: i32,
+    pub tag: i32,
+    pub cnt: i32,
+    pub active: bool,
+}
+
 #[derive(Clone, Copy)]
 pub struct Request {
     pub buf: *mut c_void,
+    /// Layout of `buf` for the datatype engine, `MPI_BYTE` if contiguous.
+    pub dtype: MPI_Datatype,
     pub stat: MPI_Status,
     pub comm: MPI_Comm,
     pub flag: i32,
@@ -19,6 +68,12 @@ pub struct Request {
     pub rank: i32,
     pub isColl: bool,
     pub collRoot: i32,
+    pub mode: SendMode,
+    pub seq: u32,
+    pub cursor: Cursor,
+    pub persist: Option<Persist>,
+    /// Freed by the user before it completed, released by the progress engine.
+    pub freed: bool,
 }
 
 impl Default for Request {
@@ -31,6 +86,7 @@ impl Request {
     pub const fn new() -> Self {
         Request {
             buf: std::ptr::null_mut(),
+            dtype: MPI_BYTE,
             stat: MPI_Status::new(),
             comm: 0,
             flag: 0,
@@ -38,8 +94,135 @@ impl Request {
             cnt: 0,
             rank: 0,
             isColl: false,
-            collRoot: -1
+            collRoot: -1,
+            mode: SendMode::Standard,
+            seq: 0,
+            cursor: Cursor::new(),
+            persist: None,
+            freed: false,
+        }
+    }
+
+    #[inline(always)]
+    pub fn matches(&self, rank: i32, tag: i32) -> bool {
+        (self.rank == MPI_ANY_SOURCE || self.rank == rank) && CommGroup::tag_match(self.tag, tag)
+    }
+
+    /// Persistent requests are active between a start and their completion, any
+    /// other request is active until it is freed.
+    #[inline(always)]
+    pub fn is_active(&self) -> bool {
+        self.persist.map_or(true, |p| p.active)
+    }
+
+    /// A point-to-point receive which still waits for a message to match.
+    #[inline(always)]
+    pub fn is_posted(&self) -> bool {
+        !self.isColl && self.flag == 0 && self.is_active() && self.cursor.is_idle()
+    }
+
+    /// Communication with `MPI_PROC_NULL` completes at once without any data.
+    pub fn complete_null(&mut self) {
+        self.stat = MPI_Status {
+            MPI_SOURCE: MPI_PROC_NULL,
+            MPI_TAG: MPI_ANY_TAG,
+            ..MPI_Status::new()
+        };
+        self.flag = 1;
+    }
+
+    /// User handle of the request, see `ShmData::handle`.
+    pub fn handle(req: *const Self) -> Result<MPI_Request, MpiError> {
+        Context::shm().handle(req).map_err(|code| {
+            debug_xfer!("Request", "No handle for request {req:?}");
+            Context::err_handler().call(MPI_COMM_WORLD, code)
+        })
+    }
+
+    /// The request behind a user handle, null handles and handles of released
+    /// requests are rejected with `MPI_ERR_REQUEST`.
+    pub fn from_handle(req: MPI_Request) -> Result<*mut Self, MpiError> {
+        Context::shm().resolve(req).ok_or_else(|| {
+            debug_xfer!("Request", "Invalid request handle {req:?}");
+            Context::err_handler().call(MPI_COMM_WORLD, MPI_ERR_REQUEST)
+        })
+    }
+
+    pub fn start(req: MPI_Request) -> MpiResult {
+        DbgEnEx!("Start");
+        let req = Self::from_handle(req)?;
+        let r = unsafe { &mut *req };
+
+        let Some(persist) = r.persist.filter(|p| !p.active) else {
+            return Err(Context::err_handler().call(r.comm, MPI_ERR_REQUEST));
+        };
+
+        r.rank = persist.rank;
+        r.tag = persist.tag;
+        r.cnt = persist.cnt;
+        r.stat = MPI_Status::new();
+        r.flag = 0;
+        r.seq = 0;
+        r.cursor = Cursor::new();
+        r.persist = Some(Persist {
+            active: true,
+            ..persist
+        });
+
+        if r.rank == MPI_PROC_NULL {
+            r.complete_null();
+        } else if Context::shm().is_recv(req) {
+            if let Some(msg) = Context::shm().find_unexp(r.rank, r.tag) {
+                debug_xfer!("Start", "Unexpected rank: {}, tag: {}", msg.rank, msg.tag);
+                Context::shm().complete_from(r, msg);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Only a receive that is not matched yet or a send whose data has not
+    /// entered a cell can be cancelled, otherwise the request completes as usual.
+    pub fn cancel(req: MPI_Request) -> MpiResult {
+        DbgEnEx!("Cancel");
+        let req = Self::from_handle(req)?;
+        let r = unsafe { &mut *req };
+
+        let shm = Context::shm();
+        if r.isColl || !r.is_active() || !(shm.is_recv(req) || shm.is_send(req)) {
+            return Err(Context::err_handler().call(r.comm, MPI_ERR_REQUEST));
+        }
+
+        if r.flag == 0 && r.seq == 0 && r.cursor.is_idle() {
+            debug_xfer!("Cancel", "Cancel request with tag: {}, rank: {}", r.tag, r.rank);
+            r.stat = MPI_Status {
+                cancelled: 1,
+                ..MPI_Status::empty()
+            };
+            r.flag = 1;
         }
+
+        Ok(())
+    }
+
+    /// Releases the request slot. A pending request is only marked and keeps
+    /// progressing, its slot is released once it completes.
+    pub fn free(req: MPI_Request) -> MpiResult {
+        DbgEnEx!("Free");
+        let req = Self::from_handle(req)?;
+        let r = unsafe { &mut *req };
+
+        if r.is_active() && r.flag == 0 {
+            debug_xfer!("Free", "Defer free of request with tag: {}", r.tag);
+            r.freed = true;
+            Context::shm().defer_free();
+        } else {
+            Context::shm()
+                .free_req(req)
+                .map_err(|code| Context::err_handler().call(r.comm, code))?;
+        }
+
+        Ok(())
     }
 
     pub fn test(req: *mut Self, pflag: &mut i32, pstat: Option<&mut MPI_Status>) -> MpiResult {
@@ -53,17 +236,49 @@ impl Request {
         *pflag = 0;
         let r = &mut unsafe { *req };
 
+        if !r.is_active() {
+            *pflag = 1;
+            if let Some(stat) = pstat {
+                *stat = MPI_Status::empty();
+            }
+            return Ok(());
+        }
+
         if r.flag != 0 {
             debug_xfer!("Test", "Find request with tag: {}, rank: {}", r.tag, r.rank);
             *pflag = 1;
 
+            let truncated = r.stat.MPI_ERROR == MPI_ERR_TRUNCATE as i32;
             if let Some(stat) = pstat {
-                stat.MPI_SOURCE = Context::comm().rank_unmap(r.comm, r.stat.MPI_SOURCE);
-                stat.MPI_TAG = Context::comm().tag_unmap(r.comm, r.stat.MPI_TAG);
-                stat.cnt = r.stat.cnt;
+                if r.stat.cancelled != 0 || r.rank == MPI_PROC_NULL {
+                    *stat = r.stat;
+                } else {
+                    stat.MPI_SOURCE = Context::comm().rank_unmap(r.comm, r.stat.MPI_SOURCE);
+                    stat.MPI_TAG = Context::comm().tag_unmap(r.comm, r.stat.MPI_TAG);
+                    stat.cnt = r.stat.cnt;
+                    stat.cancelled = 0;
+                    if truncated {
+                        stat.MPI_ERROR = r.stat.MPI_ERROR;
+                    }
+                }
+            }
+            if let Some(persist) = r.persist {
+                unsafe {
+                    (*req).flag = 0;
+                    (*req).persist = Some(Persist {
+                        active: false,
+                        ..persist
+                    });
+                }
+            } else {
+                r.flag = 0;
+                Context::shm()
+                    .free_req(req)
+                    .map_err(|code| Context::err_handler().call(r.comm, code))?;
+            }
+            if truncated {
+                return Err(Context::err_handler().call(r.comm, MPI_ERR_TRUNCATE));
             }
-            r.flag = 0;
-            Context::shm().free_req(req);
         }
 
         Ok(())
@@ -72,44 +287,254 @@ impl Request {
     pub fn wait(&mut self, pstat: Option<&mut MPI_Status>) -> MpiResult {
         DbgEnEx!("Wait");
         let mut flag = 0;
+        let mut backoff = Backoff::new();
         if let Some(stat) = pstat {
-            while flag == 0 {
+            loop {
                 Self::test(self, &mut flag, Some(stat))?;
+                if flag != 0 {
+                    break;
+                }
+                backoff.snooze();
             }
         } else {
-            while flag == 0 {
+            loop {
                 Self::test(self, &mut flag, None)?;
+                if flag != 0 {
+                    break;
+                }
+                backoff.snooze();
             }
         }
         Ok(())
     }
 
-    pub fn wait_all(reqs: &mut [*mut Request], pstat: &mut [MPI_Status]) -> MpiResult {
+    /// Fails if a handle is neither null nor refers to a live request, the other
+    /// handle functions rely on this check.
+    fn check_handles(reqs: &[MPI_Request]) -> MpiResult {
+        for &req in reqs.iter().filter(|r| !r.is_null()) {
+            Self::from_handle(req)?;
+        }
+        Ok(())
+    }
+
+    /// The request behind a handle that passed `check_handles`.
+    #[inline(always)]
+    fn get(req: MPI_Request) -> &'static Self {
+        unsafe { &*Context::shm().resolve(req).unwrap() }
+    }
+
+    /// Null handles and inactive persistent requests have nothing to complete.
+    #[inline(always)]
+    fn is_pending(req: MPI_Request) -> bool {
+        !req.is_null() && Self::get(req).is_active()
+    }
+
+    /// Tests the request behind a user handle, completed handles of non
+    /// persistent requests are reset to `MPI_REQUEST_NULL`.
+    pub fn test_handle(
+        preq: &mut MPI_Request,
+        pflag: &mut i32,
+        pstat: Option<&mut MPI_Status>,
+    ) -> MpiResult {
+        if preq.is_null() {
+            *pflag = 1;
+            if let Some(stat) = pstat {
+                *stat = MPI_Status::empty();
+            }
+            return Ok(());
+        }
+
+        let req = Self::from_handle(*preq)?;
+        let persistent = unsafe { (*req).persist.is_some() };
+        let res = Self::test(req, pflag, pstat);
+        if *pflag != 0 && !persistent {
+            *preq = MPI_REQUEST_NULL;
+        }
+        res
+    }
+
+    pub fn wait_handle(preq: &mut MPI_Request, mut pstat: Option<&mut MPI_Status>) -> MpiResult {
+        DbgEnEx!("Wait");
+        let mut flag = 0;
+        let mut backoff = Backoff::new();
+        loop {
+            Self::test_handle(preq, &mut flag, pstat.as_deref_mut())?;
+            if flag != 0 {
+                return Ok(());
+            }
+            backoff.snooze();
+        }
+    }
+
+    pub fn wait_all(reqs: &mut [MPI_Request], mut pstat: Option<&mut [MPI_Status]>) -> MpiResult {
         DbgEnEx!("WaitAll");
-        debug_assert!(reqs.len() == pstat.len());
+        let mut flag = 0;
+        let mut backoff = Backoff::new();
+        loop {
+            Self::test_all(reqs, &mut flag, pstat.as_deref_mut())?;
+            if flag != 0 {
+                return Ok(());
+            }
+            backoff.snooze();
+        }
+    }
+
+    /// Completes every request at once or none of them. Once all have finished
+    /// each one is completed, a failed request does not stop the others and its
+    /// error goes to the `MPI_ERROR` field of its status.
+    pub fn test_all(
+        reqs: &mut [MPI_Request],
+        pflag: &mut i32,
+        mut pstat: Option<&mut [MPI_Status]>,
+    ) -> MpiResult {
+        DbgEnEx!("TestAll");
+        debug_assert!(pstat.as_ref().map_or(true, |s| s.len() == reqs.len()));
+        Self::check_handles(reqs)?;
 
-        for i in pstat.iter_mut() {
-            i.MPI_ERROR = MPI_ERR_PENDING as i32;
+        let code = Context::progress();
+        if let Err(code) = code {
+            return Err(Context::err_handler().call(MPI_COMM_WORLD, code));
+        }
+
+        *pflag = reqs
+            .iter()
+            .all(|&r| !Self::is_pending(r) || Self::get(r).flag != 0) as i32;
+        if *pflag == 0 {
+            return Ok(());
         }
 
+        let mut res = Ok(());
         let mut flag = 0;
-        let mut flags = 0;
-
-        while flags != reqs.len() as i32 {
-            for (stat, req) in pstat.iter_mut().zip(reqs.iter_mut()) {
-                if stat.MPI_ERROR == MPI_ERR_PENDING as i32 {
-                    let ret = Self::test(*req, &mut flag, Some(stat));
-                    if let Err(code) = ret {
-                        stat.MPI_ERROR = code as i32;
-                        return Err(MPI_ERR_IN_STATUS);
-                    }
-                    if flag != 0 {
-                        stat.MPI_ERROR = MPI_SUCCESS;
-                        flags += 1;
-                    }
-                }
+        for (i, req) in reqs.iter_mut().enumerate() {
+            let stat = pstat.as_deref_mut().map(|s| &mut s[i]);
+            let code = Self::test_handle(req, &mut flag, stat);
+            Self::record(&mut res, code, pstat.as_deref_mut().map(|s| &mut s[i]));
+        }
+        res
+    }
+
+    /// Keeps the outcome of one request of a multiple completion call in its
+    /// status, the call itself fails with the first error, or with
+    /// `MPI_ERR_IN_STATUS` when there are statuses to look at.
+    fn record(res: &mut MpiResult, code: MpiResult, pstat: Option<&mut MPI_Status>) {
+        let has_stat = pstat.is_some();
+        if let Some(stat) = pstat {
+            stat.MPI_ERROR = code.err().map_or(MPI_SUCCESS, |code| code as i32);
+        }
+        if let Err(code) = code {
+            if res.is_ok() {
+                *res = Err(if has_stat { MPI_ERR_IN_STATUS } else { code });
             }
         }
+    }
+
+    pub fn wait_any(
+        reqs: &mut [MPI_Request],
+        pidx: &mut i32,
+        mut pstat: Option<&mut MPI_Status>,
+    ) -> MpiResult {
+        DbgEnEx!("WaitAny");
+        let mut flag = 0;
+        let mut backoff = Backoff::new();
+        loop {
+            Self::test_any(reqs, pidx, &mut flag, pstat.as_deref_mut())?;
+            if flag != 0 {
+                return Ok(());
+            }
+            backoff.snooze();
+        }
+    }
+
+    /// Completes at most one request, `pidx` is `MPI_UNDEFINED` when nothing
+    /// completed or when there are no active requests at all.
+    pub fn test_any(
+        reqs: &mut [MPI_Request],
+        pidx: &mut i32,
+        pflag: &mut i32,
+        pstat: Option<&mut MPI_Status>,
+    ) -> MpiResult {
+        DbgEnEx!("TestAny");
+        *pidx = MPI_UNDEFINED;
+        Self::check_handles(reqs)?;
+
+        let pending = reqs.iter().position(|&r| Self::is_pending(r));
+        let Some(first) = pending else {
+            *pflag = 1;
+            if let Some(stat) = pstat {
+                *stat = MPI_Status::empty();
+            }
+            return Ok(());
+        };
+
+        let code = Context::progress();
+        if let Err(code) = code {
+            return Err(Context::err_handler().call(MPI_COMM_WORLD, code));
+        }
+
+        *pflag = 0;
+        let done = reqs[first..]
+            .iter()
+            .position(|&r| Self::is_pending(r) && Self::get(r).flag != 0);
+        if let Some(i) = done {
+            *pidx = (first + i) as i32;
+            Self::test_handle(&mut reqs[first + i], pflag, pstat)?;
+        }
         Ok(())
     }
+
+    pub fn wait_some(
+        reqs: &mut [MPI_Request],
+        pcnt: &mut i32,
+        idxs: &mut [i32],
+        mut pstat: Option<&mut [MPI_Status]>,
+    ) -> MpiResult {
+        DbgEnEx!("WaitSome");
+        *pcnt = 0;
+        let mut backoff = Backoff::new();
+        loop {
+            Self::test_some(reqs, pcnt, idxs, pstat.as_deref_mut())?;
+            if *pcnt != 0 {
+                return Ok(());
+            }
+            backoff.snooze();
+        }
+    }
+
+    /// Completes every finished request, `pcnt` is `MPI_UNDEFINED` when there
+    /// are no active requests. Errors are reported like in `test_all`.
+    pub fn test_some(
+        reqs: &mut [MPI_Request],
+        pcnt: &mut i32,
+        idxs: &mut [i32],
+        mut pstat: Option<&mut [MPI_Status]>,
+    ) -> MpiResult {
+        DbgEnEx!("TestSome");
+        Self::check_handles(reqs)?;
+
+        if !reqs.iter().any(|&r| Self::is_pending(r)) {
+            *pcnt = MPI_UNDEFINED;
+            return Ok(());
+        }
+
+        let code = Context::progress();
+        if let Err(code) = code {
+            return Err(Context::err_handler().call(MPI_COMM_WORLD, code));
+        }
+
+        let mut res = Ok(());
+        let mut cnt = 0;
+        let mut flag = 0;
+        for (i, req) in reqs.iter_mut().enumerate() {
+            if !Self::is_pending(*req) || Self::get(*req).flag == 0 {
+                continue;
+            }
+            let stat = pstat.as_deref_mut().map(|s| &mut s[cnt]);
+            let code = Self::test_handle(req, &mut flag, stat);
+            Self::record(&mut res, code, pstat.as_deref_mut().map(|s| &mut s[cnt]));
+            idxs[cnt] = i as i32;
+            cnt += 1;
+        }
+        *pcnt = cnt as i32;
+        res
+    }
 }
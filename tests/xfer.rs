@@ -2,8 +2,8 @@ use mpi::*;
 use std::{
     alloc::{alloc, dealloc, Layout},
     env::set_var,
-    ffi::CStr,
-    ptr::null_mut,
+    ffi::{CStr, CString},
+    ptr::{null, null_mut},
     slice::{from_raw_parts, from_raw_parts_mut},
 };
 
@@ -152,6 +152,2513 @@ fn test_p2p_unexpect() {
     MPI_Finalize();
 }
 
+#[test]
+fn test_p2p_wildcard() {
+    set_var("MPI_SIZE", "3");
+
+    MPI_Init(null_mut(), null_mut());
+    let mut size: i32 = 0;
+    let mut rank: i32 = 0;
+
+    MPI_Comm_size(MPI_COMM_WORLD, &mut size);
+    MPI_Comm_rank(MPI_COMM_WORLD, &mut rank);
+
+    if rank == 0 {
+        let mut seen = [false; 3];
+        for _ in 1..size {
+            let mut val: i32 = -1;
+            let mut stat = MPI_Status::uninit();
+            MPI_Recv(
+                &mut val as *mut i32 as *mut c_void,
+                1,
+                MPI_INT,
+                MPI_ANY_SOURCE,
+                10,
+                MPI_COMM_WORLD,
+                &mut stat,
+            );
+            assert_eq!(val, stat.MPI_SOURCE * 100);
+            assert_eq!(stat.MPI_TAG, 10);
+            assert!(!seen[stat.MPI_SOURCE as usize]);
+            seen[stat.MPI_SOURCE as usize] = true;
+        }
+
+        let mut val: i32 = -1;
+        let mut stat = MPI_Status::uninit();
+        MPI_Recv(
+            &mut val as *mut i32 as *mut c_void,
+            1,
+            MPI_INT,
+            2,
+            MPI_ANY_TAG,
+            MPI_COMM_WORLD,
+            &mut stat,
+        );
+        assert_eq!((val, stat.MPI_SOURCE, stat.MPI_TAG), (8, 2, 3));
+        MPI_Recv(
+            &mut val as *mut i32 as *mut c_void,
+            1,
+            MPI_INT,
+            MPI_ANY_SOURCE,
+            4,
+            MPI_COMM_WORLD,
+            &mut stat,
+        );
+        assert_eq!((val, stat.MPI_SOURCE, stat.MPI_TAG), (7, 1, 4));
+    } else {
+        let val = rank * 100;
+        MPI_Send(
+            &val as *const i32 as *const c_void,
+            1,
+            MPI_INT,
+            0,
+            10,
+            MPI_COMM_WORLD,
+        );
+        let val = rank + 6;
+        MPI_Send(
+            &val as *const i32 as *const c_void,
+            1,
+            MPI_INT,
+            0,
+            5 - rank,
+            MPI_COMM_WORLD,
+        );
+    }
+    MPI_Barrier(MPI_COMM_WORLD);
+    MPI_Finalize();
+}
+
+#[test]
+fn test_probe() {
+    set_var("MPI_SIZE", "2");
+
+    MPI_Init(null_mut(), null_mut());
+    let mut rank: i32 = 0;
+    MPI_Comm_rank(MPI_COMM_WORLD, &mut rank);
+
+    if rank == 0 {
+        let mut flag = 1;
+        let mut stat = MPI_Status::uninit();
+        MPI_Iprobe(1, 7, MPI_COMM_WORLD, &mut flag, &mut stat);
+        assert_eq!(flag, 0);
+
+        MPI_Barrier(MPI_COMM_WORLD);
+
+        for len in [3, 3000] {
+            MPI_Probe(MPI_ANY_SOURCE, MPI_ANY_TAG, MPI_COMM_WORLD, &mut stat);
+            assert_eq!(stat.MPI_SOURCE, 1);
+            assert_eq!(stat.MPI_TAG, len % 5);
+
+            let mut cnt = 0;
+            MPI_Get_count(&stat, MPI_INT, &mut cnt);
+            assert_eq!(cnt, len);
+
+            let mut buf = vec![0i32; cnt as usize];
+            MPI_Recv(
+                buf.as_mut_ptr() as *mut c_void,
+                cnt,
+                MPI_INT,
+                stat.MPI_SOURCE,
+                stat.MPI_TAG,
+                MPI_COMM_WORLD,
+                &mut stat,
+            );
+            assert!(buf.iter().enumerate().all(|(i, v)| *v == i as i32));
+        }
+
+        MPI_Probe(1, 9, MPI_COMM_WORLD, &mut stat);
+        MPI_Iprobe(1, 9, MPI_COMM_WORLD, &mut flag, &mut stat);
+        assert_eq!(flag, 1);
+        let mut cnt = 0;
+        MPI_Get_count(&stat, MPI_BYTE, &mut cnt);
+        assert_eq!(cnt, 4);
+
+        let mut val = 0i32;
+        MPI_Recv(
+            &mut val as *mut i32 as *mut c_void,
+            1,
+            MPI_INT,
+            1,
+            8,
+            MPI_COMM_WORLD,
+            &mut stat,
+        );
+        assert_eq!(val, 8);
+        MPI_Recv(
+            &mut val as *mut i32 as *mut c_void,
+            1,
+            MPI_INT,
+            1,
+            9,
+            MPI_COMM_WORLD,
+            &mut stat,
+        );
+        assert_eq!(val, 9);
+    } else {
+        MPI_Barrier(MPI_COMM_WORLD);
+
+        for len in [3, 3000] {
+            let buf: Vec<i32> = (0..len).collect();
+            MPI_Send(
+                buf.as_ptr() as *const c_void,
+                len,
+                MPI_INT,
+                0,
+                len % 5,
+                MPI_COMM_WORLD,
+            );
+        }
+        for val in [8i32, 9] {
+            MPI_Send(
+                &val as *const i32 as *const c_void,
+                1,
+                MPI_INT,
+                0,
+                val,
+                MPI_COMM_WORLD,
+            );
+        }
+    }
+    MPI_Barrier(MPI_COMM_WORLD);
+    MPI_Finalize();
+}
+
+#[test]
+fn test_mprobe() {
+    set_var("MPI_SIZE", "2");
+
+    MPI_Init(null_mut(), null_mut());
+    let mut rank: i32 = 0;
+    MPI_Comm_rank(MPI_COMM_WORLD, &mut rank);
+
+    if rank == 0 {
+        let mut msg = MPI_MESSAGE_NULL;
+        let mut stat = MPI_Status::uninit();
+        MPI_Mprobe(MPI_ANY_SOURCE, MPI_ANY_TAG, MPI_COMM_WORLD, &mut msg, &mut stat);
+        assert!(msg != MPI_MESSAGE_NULL);
+        assert_eq!((stat.MPI_SOURCE, stat.MPI_TAG), (1, 1));
+
+        let mut val = 0i32;
+        MPI_Recv(
+            &mut val as *mut i32 as *mut c_void,
+            1,
+            MPI_INT,
+            MPI_ANY_SOURCE,
+            MPI_ANY_TAG,
+            MPI_COMM_WORLD,
+            &mut stat,
+        );
+        assert_eq!((val, stat.MPI_TAG), (20, 2));
+
+        MPI_Mrecv(
+            &mut val as *mut i32 as *mut c_void,
+            1,
+            MPI_INT,
+            &mut msg,
+            &mut stat,
+        );
+        assert!(msg == MPI_MESSAGE_NULL);
+        assert_eq!((val, stat.MPI_SOURCE, stat.MPI_TAG), (10, 1, 1));
+
+        let mut flag = 0;
+        while flag == 0 {
+            MPI_Improbe(1, 3, MPI_COMM_WORLD, &mut flag, &mut msg, &mut stat);
+        }
+        let mut req: MPI_Request = null_mut();
+        MPI_Imrecv(
+            &mut val as *mut i32 as *mut c_void,
+            1,
+            MPI_INT,
+            &mut msg,
+            &mut req,
+        );
+        MPI_Wait(&mut req, &mut stat);
+        assert_eq!((val, stat.MPI_SOURCE, stat.MPI_TAG), (30, 1, 3));
+    } else {
+        for val in [10i32, 20, 30] {
+            MPI_Send(
+                &val as *const i32 as *const c_void,
+                1,
+                MPI_INT,
+                0,
+                val / 10,
+                MPI_COMM_WORLD,
+            );
+        }
+    }
+    MPI_Barrier(MPI_COMM_WORLD);
+    MPI_Finalize();
+}
+
+#[test]
+fn test_send_modes() {
+    set_var("MPI_SIZE", "2");
+
+    MPI_Init(null_mut(), null_mut());
+    let mut rank: i32 = 0;
+    MPI_Comm_rank(MPI_COMM_WORLD, &mut rank);
+
+    let send_int = |val: i32, tag: i32| {
+        MPI_Send(
+            &val as *const i32 as *const c_void,
+            1,
+            MPI_INT,
+            1 - rank,
+            tag,
+            MPI_COMM_WORLD,
+        );
+    };
+    let recv_int = |tag: i32| {
+        let mut val = 0i32;
+        let mut stat = MPI_Status::uninit();
+        MPI_Recv(
+            &mut val as *mut i32 as *mut c_void,
+            1,
+            MPI_INT,
+            1 - rank,
+            tag,
+            MPI_COMM_WORLD,
+            &mut stat,
+        );
+        val
+    };
+
+    if rank == 0 {
+        let val = 10i32;
+        let mut req: MPI_Request = null_mut();
+        let mut stat = MPI_Status::uninit();
+        let mut flag = 1;
+        MPI_Issend(
+            &val as *const i32 as *const c_void,
+            1,
+            MPI_INT,
+            1,
+            1,
+            MPI_COMM_WORLD,
+            &mut req,
+        );
+        MPI_Test(&mut req, &mut flag, &mut stat);
+        assert_eq!(flag, 0);
+        send_int(20, 2);
+        MPI_Wait(&mut req, &mut stat);
+
+        let size = 2 * (4 + MPI_BSEND_OVERHEAD);
+        let layout = Layout::from_size_align(size as usize, 32).unwrap();
+        let pool = unsafe { alloc(layout) };
+        MPI_Buffer_attach(pool as *mut c_void, size);
+        for val in [30i32, 40] {
+            MPI_Bsend(
+                &val as *const i32 as *const c_void,
+                1,
+                MPI_INT,
+                1,
+                val / 10,
+                MPI_COMM_WORLD,
+            );
+        }
+        let mut detached: *mut c_void = null_mut();
+        let mut detached_size = 0;
+        MPI_Buffer_detach(
+            &mut detached as *mut *mut c_void as *mut c_void,
+            &mut detached_size,
+        );
+        assert_eq!((detached as *mut u8, detached_size), (pool, size));
+        unsafe { dealloc(pool, layout) };
+
+        assert_eq!(recv_int(6), 60);
+        let val = 50i32;
+        MPI_Rsend(
+            &val as *const i32 as *const c_void,
+            1,
+            MPI_INT,
+            1,
+            5,
+            MPI_COMM_WORLD,
+        );
+    } else {
+        assert_eq!(recv_int(2), 20);
+        assert_eq!(recv_int(1), 10);
+        assert_eq!(recv_int(3), 30);
+        assert_eq!(recv_int(4), 40);
+
+        let mut val = 0i32;
+        let mut req: MPI_Request = null_mut();
+        let mut stat = MPI_Status::uninit();
+        MPI_Irecv(
+            &mut val as *mut i32 as *mut c_void,
+            1,
+            MPI_INT,
+            0,
+            5,
+            MPI_COMM_WORLD,
+            &mut req,
+        );
+        send_int(60, 6);
+        MPI_Wait(&mut req, &mut stat);
+        assert_eq!(val, 50);
+    }
+    MPI_Barrier(MPI_COMM_WORLD);
+    MPI_Finalize();
+}
+
+#[test]
+fn test_persistent() {
+    set_var("MPI_SIZE", "2");
+
+    MPI_Init(null_mut(), null_mut());
+    let mut rank: i32 = 0;
+    MPI_Comm_rank(MPI_COMM_WORLD, &mut rank);
+
+    let mut sval = 0i32;
+    let mut rval = 0i32;
+    let mut reqs: [MPI_Request; 2] = [null_mut(); 2];
+    MPI_Send_init(
+        &sval as *const i32 as *const c_void,
+        1,
+        MPI_INT,
+        1 - rank,
+        1,
+        MPI_COMM_WORLD,
+        &mut reqs[0],
+    );
+    MPI_Recv_init(
+        &mut rval as *mut i32 as *mut c_void,
+        1,
+        MPI_INT,
+        1 - rank,
+        1,
+        MPI_COMM_WORLD,
+        &mut reqs[1],
+    );
+
+    let mut flag = 0;
+    let mut stat = MPI_Status::uninit();
+    MPI_Test(&mut reqs[1], &mut flag, &mut stat);
+    assert_eq!((flag, stat.MPI_SOURCE, stat.MPI_TAG), (1, MPI_ANY_SOURCE, MPI_ANY_TAG));
+
+    let mut stats = [MPI_Status::uninit(); 2];
+    for i in 0..100 {
+        sval = i * 2 + rank;
+        MPI_Startall(2, reqs.as_mut_ptr());
+        MPI_Waitall(2, reqs.as_mut_ptr(), stats.as_mut_ptr());
+        assert_eq!(rval, i * 2 + 1 - rank);
+        assert_eq!((stats[1].MPI_SOURCE, stats[1].MPI_TAG), (1 - rank, 1));
+    }
+
+    for req in reqs.iter_mut() {
+        MPI_Request_free(req);
+        assert!(req.is_null());
+    }
+    MPI_Barrier(MPI_COMM_WORLD);
+    MPI_Finalize();
+}
+
+#[test]
+fn test_request_free_pending() {
+    set_var("MPI_SIZE", "2");
+
+    MPI_Init(null_mut(), null_mut());
+    let mut rank: i32 = 0;
+    MPI_Comm_rank(MPI_COMM_WORLD, &mut rank);
+
+    let vals = [7i32, 8, 9];
+    if rank == 0 {
+        MPI_Barrier(MPI_COMM_WORLD);
+        for (tag, val) in vals.iter().enumerate() {
+            MPI_Send(
+                val as *const i32 as *const c_void,
+                1,
+                MPI_INT,
+                1,
+                tag as i32 + 1,
+                MPI_COMM_WORLD,
+            );
+        }
+    } else {
+        // Neither receive is matched yet, freeing them must not block.
+        let mut rvals = [0i32; 3];
+        let mut req = MPI_REQUEST_NULL;
+        MPI_Irecv(
+            &mut rvals[0] as *mut i32 as *mut c_void,
+            1,
+            MPI_INT,
+            0,
+            1,
+            MPI_COMM_WORLD,
+            &mut req,
+        );
+        assert_eq!(MPI_Request_free(&mut req), MPI_SUCCESS);
+        assert!(req.is_null());
+
+        MPI_Recv_init(
+            &mut rvals[1] as *mut i32 as *mut c_void,
+            1,
+            MPI_INT,
+            0,
+            2,
+            MPI_COMM_WORLD,
+            &mut req,
+        );
+        MPI_Start(&mut req);
+        assert_eq!(MPI_Request_free(&mut req), MPI_SUCCESS);
+        assert!(req.is_null());
+
+        MPI_Barrier(MPI_COMM_WORLD);
+        MPI_Recv(
+            &mut rvals[2] as *mut i32 as *mut c_void,
+            1,
+            MPI_INT,
+            0,
+            3,
+            MPI_COMM_WORLD,
+            MPI_STATUS_IGNORE,
+        );
+        assert_eq!(rvals, vals);
+    }
+    MPI_Finalize();
+}
+
+#[test]
+fn test_completion() {
+    set_var("MPI_SIZE", "3");
+
+    MPI_Init(null_mut(), null_mut());
+    let mut rank: i32 = 0;
+    MPI_Comm_rank(MPI_COMM_WORLD, &mut rank);
+
+    if rank == 0 {
+        let mut vals = [0i32; 3];
+        let mut reqs = [MPI_REQUEST_NULL; 3];
+        for src in 1..3 {
+            MPI_Irecv(
+                &mut vals[src] as *mut i32 as *mut c_void,
+                1,
+                MPI_INT,
+                src as i32,
+                1,
+                MPI_COMM_WORLD,
+                &mut reqs[src],
+            );
+        }
+
+        let mut flag = 1;
+        MPI_Testall(3, reqs.as_mut_ptr(), &mut flag, MPI_STATUSES_IGNORE);
+        assert_eq!(flag, 0);
+        assert!(reqs[1..].iter().all(|r| !r.is_null()));
+
+        for dest in 1..3 {
+            MPI_Send(
+                &flag as *const i32 as *const c_void,
+                1,
+                MPI_INT,
+                dest,
+                0,
+                MPI_COMM_WORLD,
+            );
+        }
+
+        let mut idx = 0;
+        let mut stat = MPI_Status::uninit();
+        for _ in 1..3 {
+            MPI_Waitany(3, reqs.as_mut_ptr(), &mut idx, &mut stat);
+            assert!(idx == 1 || idx == 2);
+            assert_eq!(stat.MPI_SOURCE, idx);
+            assert_eq!(vals[idx as usize], idx * 10);
+            assert!(reqs[idx as usize] == MPI_REQUEST_NULL);
+        }
+        MPI_Waitany(3, reqs.as_mut_ptr(), &mut idx, &mut stat);
+        assert_eq!(idx, MPI_UNDEFINED);
+        MPI_Wait(&mut reqs[0], MPI_STATUS_IGNORE);
+
+        for src in 1..3 {
+            MPI_Irecv(
+                &mut vals[src] as *mut i32 as *mut c_void,
+                1,
+                MPI_INT,
+                src as i32,
+                2,
+                MPI_COMM_WORLD,
+                &mut reqs[src],
+            );
+        }
+        let mut done = 0;
+        let mut cnt = 0;
+        let mut idxs = [0i32; 3];
+        while done < 2 {
+            MPI_Waitsome(
+                3,
+                reqs.as_mut_ptr(),
+                &mut cnt,
+                idxs.as_mut_ptr(),
+                MPI_STATUSES_IGNORE,
+            );
+            for &i in &idxs[..cnt as usize] {
+                assert_eq!(vals[i as usize], i * 100);
+            }
+            done += cnt;
+        }
+        MPI_Testsome(
+            3,
+            reqs.as_mut_ptr(),
+            &mut cnt,
+            idxs.as_mut_ptr(),
+            MPI_STATUSES_IGNORE,
+        );
+        assert_eq!(cnt, MPI_UNDEFINED);
+    } else {
+        let mut go = 0i32;
+        MPI_Recv(
+            &mut go as *mut i32 as *mut c_void,
+            1,
+            MPI_INT,
+            0,
+            0,
+            MPI_COMM_WORLD,
+            MPI_STATUS_IGNORE,
+        );
+        for val in [rank * 10, rank * 100] {
+            MPI_Send(
+                &val as *const i32 as *const c_void,
+                1,
+                MPI_INT,
+                0,
+                if val < 100 { 1 } else { 2 },
+                MPI_COMM_WORLD,
+            );
+        }
+    }
+    MPI_Barrier(MPI_COMM_WORLD);
+    MPI_Finalize();
+}
+
+#[test]
+fn test_truncate() {
+    set_var("MPI_SIZE", "2");
+
+    MPI_Init(null_mut(), null_mut());
+    MPI_Comm_set_errhandler(MPI_COMM_WORLD, MPI_ERRORS_RETURN);
+    let mut rank: i32 = 0;
+    MPI_Comm_rank(MPI_COMM_WORLD, &mut rank);
+
+    let big: Vec<i32> = (0..50000).collect();
+    let mut stat = MPI_Status::uninit();
+    let mut cnt = 0;
+
+    if rank == 0 {
+        MPI_Barrier(MPI_COMM_WORLD);
+        MPI_Send(
+            big.as_ptr() as *const c_void,
+            big.len() as i32,
+            MPI_INT,
+            1,
+            1,
+            MPI_COMM_WORLD,
+        );
+        MPI_Send(
+            big.as_ptr() as *const c_void,
+            4,
+            MPI_INT,
+            1,
+            3,
+            MPI_COMM_WORLD,
+        );
+        MPI_Barrier(MPI_COMM_WORLD);
+        MPI_Send(
+            big[7..].as_ptr() as *const c_void,
+            1,
+            MPI_INT,
+            1,
+            4,
+            MPI_COMM_WORLD,
+        );
+        MPI_Barrier(MPI_COMM_WORLD);
+        for _ in 0..2 {
+            for tag in 5..7 {
+                MPI_Send(
+                    big.as_ptr() as *const c_void,
+                    4,
+                    MPI_INT,
+                    1,
+                    tag,
+                    MPI_COMM_WORLD,
+                );
+            }
+        }
+    } else {
+        // A posted receive shorter than the message gets its start.
+        let mut vals = [0i32; 10];
+        let mut req = MPI_REQUEST_NULL;
+        MPI_Irecv(
+            vals.as_mut_ptr() as *mut c_void,
+            10,
+            MPI_INT,
+            0,
+            1,
+            MPI_COMM_WORLD,
+            &mut req,
+        );
+        MPI_Barrier(MPI_COMM_WORLD);
+        let code = MPI_Wait(&mut req, &mut stat);
+        assert_eq!(code, MpiError::MPI_ERR_TRUNCATE as i32);
+        assert_eq!(stat.MPI_ERROR, MpiError::MPI_ERR_TRUNCATE as i32);
+        MPI_Get_count(&stat, MPI_INT, &mut cnt);
+        assert_eq!((cnt, &vals[..]), (10, &big[..10]));
+        assert!(req == MPI_REQUEST_NULL);
+
+        // So does a receive of a message that is already buffered.
+        MPI_Barrier(MPI_COMM_WORLD);
+        let code = MPI_Recv(
+            vals.as_mut_ptr() as *mut c_void,
+            2,
+            MPI_INT,
+            0,
+            3,
+            MPI_COMM_WORLD,
+            &mut stat,
+        );
+        assert_eq!(code, MpiError::MPI_ERR_TRUNCATE as i32);
+        MPI_Get_count(&stat, MPI_INT, &mut cnt);
+        assert_eq!(cnt, 2);
+
+        // The sender is not held back by the truncated messages.
+        let mut val = 0i32;
+        let code = MPI_Recv(
+            &mut val as *mut i32 as *mut c_void,
+            1,
+            MPI_INT,
+            0,
+            4,
+            MPI_COMM_WORLD,
+            &mut stat,
+        );
+        assert_eq!((code, val), (MPI_SUCCESS, 7));
+
+        // A truncated request of a multiple completion is reported in its own
+        // status and does not hold back the other one.
+        MPI_Barrier(MPI_COMM_WORLD);
+        let mut vals = [0i32; 6];
+        let mut stats = [MPI_Status::uninit(); 2];
+        let mut idxs = [0i32; 2];
+        for round in 0..2 {
+            let mut reqs = [MPI_REQUEST_NULL; 2];
+            for (i, (req, buf)) in reqs.iter_mut().zip(vals.chunks_mut(4)).enumerate() {
+                MPI_Irecv(
+                    buf.as_mut_ptr() as *mut c_void,
+                    buf.len() as i32,
+                    MPI_INT,
+                    0,
+                    6 - i as i32,
+                    MPI_COMM_WORLD,
+                    req,
+                );
+            }
+            if round == 0 {
+                let code = MPI_Waitall(2, reqs.as_mut_ptr(), stats.as_mut_ptr());
+                assert_eq!(code, MpiError::MPI_ERR_IN_STATUS as i32);
+                assert_eq!(stats[0].MPI_ERROR, MPI_SUCCESS);
+                assert_eq!(stats[1].MPI_ERROR, MpiError::MPI_ERR_TRUNCATE as i32);
+            } else {
+                let mut done = 0;
+                while done < 2 {
+                    let mut out = 0;
+                    let code = MPI_Waitsome(
+                        2,
+                        reqs.as_mut_ptr(),
+                        &mut out,
+                        idxs.as_mut_ptr(),
+                        stats.as_mut_ptr(),
+                    );
+                    for k in 0..out as usize {
+                        let err = if idxs[k] == 1 {
+                            MpiError::MPI_ERR_TRUNCATE as i32
+                        } else {
+                            MPI_SUCCESS
+                        };
+                        assert_eq!(stats[k].MPI_ERROR, err);
+                    }
+                    let truncated = idxs[..out as usize].contains(&1);
+                    let expect = if truncated {
+                        MpiError::MPI_ERR_IN_STATUS as i32
+                    } else {
+                        MPI_SUCCESS
+                    };
+                    assert_eq!(code, expect);
+                    done += out;
+                }
+            }
+            assert!(reqs.iter().all(|&r| r == MPI_REQUEST_NULL));
+            assert_eq!(&vals[..], &[0, 1, 2, 3, 0, 1]);
+        }
+    }
+    MPI_Finalize();
+}
+
+#[test]
+fn test_cancel() {
+    set_var("MPI_SIZE", "2");
+
+    MPI_Init(null_mut(), null_mut());
+    let mut rank: i32 = 0;
+    MPI_Comm_rank(MPI_COMM_WORLD, &mut rank);
+
+    let mut val = 0i32;
+    let mut req = MPI_REQUEST_NULL;
+    let mut stat = MPI_Status::uninit();
+    let mut flag = 0;
+
+    if rank == 0 {
+        for _ in 0..20 {
+            MPI_Irecv(
+                &mut val as *mut i32 as *mut c_void,
+                1,
+                MPI_INT,
+                1,
+                7,
+                MPI_COMM_WORLD,
+                &mut req,
+            );
+            MPI_Cancel(&mut req);
+            MPI_Wait(&mut req, &mut stat);
+            MPI_Test_cancelled(&stat, &mut flag);
+            assert_eq!(flag, 1);
+            assert!(req == MPI_REQUEST_NULL);
+        }
+
+        MPI_Isend(
+            &val as *const i32 as *const c_void,
+            1,
+            MPI_INT,
+            1,
+            2,
+            MPI_COMM_WORLD,
+            &mut req,
+        );
+        MPI_Cancel(&mut req);
+        MPI_Wait(&mut req, &mut stat);
+        MPI_Test_cancelled(&stat, &mut flag);
+        assert_eq!(flag, 1);
+
+        val = 10;
+        MPI_Send(
+            &val as *const i32 as *const c_void,
+            1,
+            MPI_INT,
+            1,
+            1,
+            MPI_COMM_WORLD,
+        );
+        MPI_Barrier(MPI_COMM_WORLD);
+    } else {
+        MPI_Recv(
+            &mut val as *mut i32 as *mut c_void,
+            1,
+            MPI_INT,
+            0,
+            MPI_ANY_TAG,
+            MPI_COMM_WORLD,
+            &mut stat,
+        );
+        assert_eq!((val, stat.MPI_TAG), (10, 1));
+        MPI_Test_cancelled(&stat, &mut flag);
+        assert_eq!(flag, 0);
+
+        MPI_Barrier(MPI_COMM_WORLD);
+        MPI_Iprobe(0, 2, MPI_COMM_WORLD, &mut flag, &mut stat);
+        assert_eq!(flag, 0);
+    }
+    MPI_Barrier(MPI_COMM_WORLD);
+    MPI_Finalize();
+}
+
+#[test]
+fn test_self_proc_null() {
+    set_var("MPI_SIZE", "2");
+
+    MPI_Init(null_mut(), null_mut());
+    let mut rank: i32 = 0;
+    MPI_Comm_rank(MPI_COMM_WORLD, &mut rank);
+
+    let mut val = 0i32;
+    let mut req = MPI_REQUEST_NULL;
+    let mut sreq = MPI_REQUEST_NULL;
+    let mut stat = MPI_Status::uninit();
+    let sval = rank + 5;
+
+    MPI_Send(
+        &sval as *const i32 as *const c_void,
+        1,
+        MPI_INT,
+        rank,
+        1,
+        MPI_COMM_WORLD,
+    );
+    MPI_Recv(
+        &mut val as *mut i32 as *mut c_void,
+        1,
+        MPI_INT,
+        rank,
+        1,
+        MPI_COMM_WORLD,
+        &mut stat,
+    );
+    assert_eq!((val, stat.MPI_SOURCE, stat.MPI_TAG), (rank + 5, rank, 1));
+
+    MPI_Irecv(
+        &mut val as *mut i32 as *mut c_void,
+        1,
+        MPI_INT,
+        MPI_ANY_SOURCE,
+        2,
+        MPI_COMM_WORLD,
+        &mut req,
+    );
+    MPI_Send(
+        &rank as *const i32 as *const c_void,
+        1,
+        MPI_INT,
+        rank,
+        2,
+        MPI_COMM_WORLD,
+    );
+    MPI_Wait(&mut req, &mut stat);
+    assert_eq!((val, stat.MPI_SOURCE), (rank, rank));
+
+    let mut flag = 1;
+    MPI_Issend(
+        &sval as *const i32 as *const c_void,
+        1,
+        MPI_INT,
+        rank,
+        3,
+        MPI_COMM_WORLD,
+        &mut sreq,
+    );
+    MPI_Test(&mut sreq, &mut flag, &mut stat);
+    assert_eq!(flag, 0);
+    MPI_Recv(
+        &mut val as *mut i32 as *mut c_void,
+        1,
+        MPI_INT,
+        rank,
+        3,
+        MPI_COMM_WORLD,
+        &mut stat,
+    );
+    MPI_Wait(&mut sreq, &mut stat);
+    assert_eq!(val, rank + 5);
+
+    let left = if rank == 0 { MPI_PROC_NULL } else { rank - 1 };
+    let right = if rank == 1 { MPI_PROC_NULL } else { rank + 1 };
+    val = -1;
+    MPI_Sendrecv(
+        &sval as *const i32 as *const c_void,
+        1,
+        MPI_INT,
+        right,
+        4,
+        &mut val as *mut i32 as *mut c_void,
+        1,
+        MPI_INT,
+        left,
+        4,
+        MPI_COMM_WORLD,
+        &mut stat,
+    );
+    if rank == 0 {
+        assert_eq!((val, stat.MPI_SOURCE, stat.MPI_TAG), (-1, MPI_PROC_NULL, MPI_ANY_TAG));
+    } else {
+        assert_eq!((val, stat.MPI_SOURCE), (5, 0));
+    }
+
+    // Empty messages need no buffer and the status may be ignored.
+    let code = MPI_Send(null(), 0, MPI_INT, MPI_PROC_NULL, 6, MPI_COMM_WORLD);
+    assert_eq!(code, MPI_SUCCESS);
+    let code = MPI_Sendrecv(
+        null(),
+        0,
+        MPI_INT,
+        right,
+        6,
+        null_mut(),
+        0,
+        MPI_INT,
+        left,
+        6,
+        MPI_COMM_WORLD,
+        MPI_STATUS_IGNORE,
+    );
+    assert_eq!(code, MPI_SUCCESS);
+
+    MPI_Probe(MPI_PROC_NULL, 5, MPI_COMM_WORLD, &mut stat);
+    assert_eq!(stat.MPI_SOURCE, MPI_PROC_NULL);
+    let mut msg = MPI_MESSAGE_NULL;
+    MPI_Mprobe(MPI_PROC_NULL, 5, MPI_COMM_WORLD, &mut msg, &mut stat);
+    assert!(msg == MPI_MESSAGE_NO_PROC);
+    MPI_Mrecv(
+        &mut val as *mut i32 as *mut c_void,
+        1,
+        MPI_INT,
+        &mut msg,
+        &mut stat,
+    );
+    assert!(msg == MPI_MESSAGE_NULL);
+    assert_eq!((stat.MPI_SOURCE, stat.cnt), (MPI_PROC_NULL, 0));
+
+    MPI_Barrier(MPI_COMM_WORLD);
+    MPI_Finalize();
+}
+
+#[test]
+fn test_sendrecv_replace() {
+    set_var("MPI_SIZE", "3");
+
+    MPI_Init(null_mut(), null_mut());
+    let mut rank: i32 = 0;
+    MPI_Comm_rank(MPI_COMM_WORLD, &mut rank);
+
+    let left = (rank + 2) % 3;
+    let right = (rank + 1) % 3;
+    let mut data: Vec<i32> = (0..5000).map(|i| i * 3 + rank).collect();
+    let mut stat = MPI_Status::uninit();
+
+    for shift in 1..=3 {
+        MPI_Sendrecv_replace(
+            data.as_mut_ptr() as *mut c_void,
+            data.len() as i32,
+            MPI_INT,
+            right,
+            shift,
+            left,
+            shift,
+            MPI_COMM_WORLD,
+            &mut stat,
+        );
+        assert_eq!((stat.MPI_SOURCE, stat.MPI_TAG), (left, shift));
+        let owner = (rank + 3 - shift) % 3;
+        assert!(data.iter().enumerate().all(|(i, &v)| v == i as i32 * 3 + owner));
+    }
+
+    MPI_Barrier(MPI_COMM_WORLD);
+    MPI_Finalize();
+}
+
+#[test]
+fn test_nonblocking_progress() {
+    set_var("MPI_SIZE", "3");
+
+    MPI_Init(null_mut(), null_mut());
+    let mut rank: i32 = 0;
+    MPI_Comm_rank(MPI_COMM_WORLD, &mut rank);
+
+    let n = 1 << 18;
+    let mut stat = MPI_Status::uninit();
+    let mut token = 0i32;
+
+    if rank == 0 {
+        let big: Vec<i32> = (0..n).collect();
+        let mut req: MPI_Request = null_mut();
+        let mut flag = 1;
+        MPI_Isend(
+            big.as_ptr() as *const c_void,
+            n,
+            MPI_INT,
+            1,
+            1,
+            MPI_COMM_WORLD,
+            &mut req,
+        );
+        MPI_Test(&mut req, &mut flag, &mut stat);
+        assert_eq!(flag, 0);
+
+        // Rank 1 takes the big message only after this one went around through
+        // rank 2, so the send above must not hold it back.
+        MPI_Send(
+            &token as *const i32 as *const c_void,
+            1,
+            MPI_INT,
+            2,
+            2,
+            MPI_COMM_WORLD,
+        );
+        MPI_Wait(&mut req, &mut stat);
+    } else if rank == 1 {
+        MPI_Recv(
+            &mut token as *mut i32 as *mut c_void,
+            1,
+            MPI_INT,
+            2,
+            3,
+            MPI_COMM_WORLD,
+            &mut stat,
+        );
+        let mut big = vec![0i32; n as usize];
+        MPI_Recv(
+            big.as_mut_ptr() as *mut c_void,
+            n,
+            MPI_INT,
+            0,
+            1,
+            MPI_COMM_WORLD,
+            &mut stat,
+        );
+        assert_eq!((stat.MPI_SOURCE, stat.MPI_TAG), (0, 1));
+        assert!(big.iter().enumerate().all(|(i, &v)| v == i as i32));
+    } else {
+        MPI_Recv(
+            &mut token as *mut i32 as *mut c_void,
+            1,
+            MPI_INT,
+            0,
+            2,
+            MPI_COMM_WORLD,
+            &mut stat,
+        );
+        MPI_Send(
+            &token as *const i32 as *const c_void,
+            1,
+            MPI_INT,
+            1,
+            3,
+            MPI_COMM_WORLD,
+        );
+    }
+
+    MPI_Barrier(MPI_COMM_WORLD);
+    MPI_Finalize();
+}
+
+#[test]
+fn test_many_requests() {
+    set_var("MPI_SIZE", "2");
+
+    MPI_Init(null_mut(), null_mut());
+    let mut rank: i32 = 0;
+    MPI_Comm_rank(MPI_COMM_WORLD, &mut rank);
+
+    let n = 300;
+    let mut data: Vec<i32> = (0..n).map(|i| i * 7).collect();
+    let mut reqs: Vec<MPI_Request> = vec![null_mut(); n as usize];
+    let mut stats = vec![MPI_Status::uninit(); n as usize];
+
+    if rank == 0 {
+        for (i, req) in reqs.iter_mut().enumerate() {
+            MPI_Isend(
+                &data[i] as *const i32 as *const c_void,
+                1,
+                MPI_INT,
+                1,
+                i as i32,
+                MPI_COMM_WORLD,
+                req,
+            );
+        }
+    } else {
+        data.fill(-1);
+        for (i, req) in reqs.iter_mut().enumerate().rev() {
+            MPI_Irecv(
+                &mut data[i] as *mut i32 as *mut c_void,
+                1,
+                MPI_INT,
+                0,
+                i as i32,
+                MPI_COMM_WORLD,
+                req,
+            );
+        }
+    }
+    MPI_Waitall(n, reqs.as_mut_ptr(), stats.as_mut_ptr());
+    assert!(data.iter().enumerate().all(|(i, &v)| v == i as i32 * 7));
+
+    MPI_Barrier(MPI_COMM_WORLD);
+    MPI_Finalize();
+}
+
+#[test]
+fn test_request_limit() {
+    set_var("MPI_SIZE", "1");
+    set_var("MPI_MAX_REQUESTS", "4");
+
+    MPI_Init(null_mut(), null_mut());
+    std::env::remove_var("MPI_MAX_REQUESTS");
+    MPI_Comm_set_errhandler(MPI_COMM_WORLD, MPI_ERRORS_RETURN);
+
+    let mut data = [0i32; 5];
+    let mut reqs: Vec<MPI_Request> = vec![null_mut(); 5];
+    let codes: Vec<i32> = data
+        .iter_mut()
+        .zip(reqs.iter_mut())
+        .map(|(val, req)| {
+            MPI_Irecv(
+                val as *mut i32 as *mut c_void,
+                1,
+                MPI_INT,
+                0,
+                0,
+                MPI_COMM_WORLD,
+                req,
+            )
+        })
+        .collect();
+    assert_eq!(codes, [0, 0, 0, 0, MpiError::MPI_ERR_NO_MEM as i32]);
+
+    for req in &mut reqs[..4] {
+        MPI_Cancel(req);
+        MPI_Wait(req, MPI_STATUS_IGNORE);
+    }
+    MPI_Comm_set_errhandler(MPI_COMM_WORLD, MPI_ERRORS_ARE_FATAL);
+    MPI_Finalize();
+}
+
+#[test]
+fn test_stale_handle() {
+    set_var("MPI_SIZE", "1");
+
+    MPI_Init(null_mut(), null_mut());
+    MPI_Comm_set_errhandler(MPI_COMM_WORLD, MPI_ERRORS_RETURN);
+
+    let sval = 42i32;
+    let mut rval = 0i32;
+    let mut req = MPI_REQUEST_NULL;
+    let mut flag = 0;
+    let mut stat = MPI_Status::uninit();
+    MPI_Irecv(
+        &mut rval as *mut i32 as *mut c_void,
+        1,
+        MPI_INT,
+        0,
+        0,
+        MPI_COMM_WORLD,
+        &mut req,
+    );
+    let mut stale = req;
+    MPI_Send(
+        &sval as *const i32 as *const c_void,
+        1,
+        MPI_INT,
+        0,
+        0,
+        MPI_COMM_WORLD,
+    );
+    assert_eq!(MPI_Wait(&mut req, &mut stat), MPI_SUCCESS);
+    assert_eq!(rval, 42);
+
+    // The slot of the completed receive is reused by the next request.
+    MPI_Irecv(
+        &mut rval as *mut i32 as *mut c_void,
+        1,
+        MPI_INT,
+        0,
+        1,
+        MPI_COMM_WORLD,
+        &mut req,
+    );
+    let err = MpiError::MPI_ERR_REQUEST as i32;
+    assert_eq!(MPI_Test(&mut stale, &mut flag, &mut stat), err);
+    assert_eq!(MPI_Wait(&mut stale, &mut stat), err);
+    let mut reqs = [req, stale];
+    assert_eq!(MPI_Waitall(2, reqs.as_mut_ptr(), MPI_STATUSES_IGNORE), err);
+    assert_eq!(MPI_Cancel(&mut stale), err);
+
+    assert_eq!(MPI_Cancel(&mut req), MPI_SUCCESS);
+    assert_eq!(MPI_Wait(&mut req, &mut stat), MPI_SUCCESS);
+
+    MPI_Send_init(
+        &sval as *const i32 as *const c_void,
+        1,
+        MPI_INT,
+        0,
+        2,
+        MPI_COMM_WORLD,
+        &mut req,
+    );
+    let mut copy = req;
+    assert_eq!(MPI_Request_free(&mut req), MPI_SUCCESS);
+    assert_eq!(MPI_Request_free(&mut copy), err);
+    assert_eq!(MPI_Start(&mut copy), err);
+
+    // A matched message can only be received once.
+    let mut msg = MPI_MESSAGE_NULL;
+    for tag in 3..5 {
+        MPI_Send(
+            &sval as *const i32 as *const c_void,
+            1,
+            MPI_INT,
+            0,
+            tag,
+            MPI_COMM_WORLD,
+        );
+    }
+    MPI_Mprobe(0, 3, MPI_COMM_WORLD, &mut msg, &mut stat);
+    let mut stale = msg;
+    let rbuf = &mut rval as *mut i32 as *mut c_void;
+    assert_eq!(
+        MPI_Mrecv(rbuf, 1, MPI_INT, &mut msg, &mut stat),
+        MPI_SUCCESS
+    );
+    MPI_Mprobe(0, 4, MPI_COMM_WORLD, &mut msg, &mut stat);
+    assert_eq!(MPI_Mrecv(rbuf, 1, MPI_INT, &mut stale, &mut stat), err);
+    assert_eq!(MPI_Imrecv(rbuf, 1, MPI_INT, &mut stale, &mut req), err);
+    assert_eq!(
+        MPI_Mrecv(rbuf, 1, MPI_INT, &mut msg, &mut stat),
+        MPI_SUCCESS
+    );
+
+    MPI_Comm_set_errhandler(MPI_COMM_WORLD, MPI_ERRORS_ARE_FATAL);
+    MPI_Finalize();
+}
+
+#[test]
+fn test_rendezvous() {
+    set_var("MPI_SIZE", "2");
+    set_var("MPI_CMA_THRESHOLD", "4096");
+
+    MPI_Init(null_mut(), null_mut());
+    std::env::remove_var("MPI_CMA_THRESHOLD");
+    let mut rank: i32 = 0;
+    MPI_Comm_rank(MPI_COMM_WORLD, &mut rank);
+
+    let big: Vec<i32> = (0..100000).collect();
+    let mid: Vec<i32> = (0..3000).map(|i| -i).collect();
+    let small = [7i32; 10];
+    let mut stat = MPI_Status::uninit();
+
+    if rank == 0 {
+        let mut req = MPI_REQUEST_NULL;
+        MPI_Isend(
+            big.as_ptr() as *const c_void,
+            big.len() as i32,
+            MPI_INT,
+            1,
+            1,
+            MPI_COMM_WORLD,
+            &mut req,
+        );
+        MPI_Ssend(
+            mid.as_ptr() as *const c_void,
+            mid.len() as i32,
+            MPI_INT,
+            1,
+            2,
+            MPI_COMM_WORLD,
+        );
+        MPI_Send(
+            small.as_ptr() as *const c_void,
+            small.len() as i32,
+            MPI_INT,
+            1,
+            3,
+            MPI_COMM_WORLD,
+        );
+        MPI_Wait(&mut req, &mut stat);
+    } else {
+        let mut rbig = vec![0i32; big.len()];
+        let mut rmid = vec![0i32; mid.len()];
+        let mut rsmall = [0i32; 10];
+        let mut req = MPI_REQUEST_NULL;
+        MPI_Irecv(
+            rmid.as_mut_ptr() as *mut c_void,
+            rmid.len() as i32,
+            MPI_INT,
+            0,
+            2,
+            MPI_COMM_WORLD,
+            &mut req,
+        );
+        // The big message arrives first and is buffered as unexpected.
+        MPI_Recv(
+            rsmall.as_mut_ptr() as *mut c_void,
+            10,
+            MPI_INT,
+            0,
+            3,
+            MPI_COMM_WORLD,
+            &mut stat,
+        );
+        MPI_Wait(&mut req, &mut stat);
+        assert_eq!((stat.MPI_TAG, stat.cnt), (2, mid.len() as i32 * 4));
+        MPI_Recv(
+            rbig.as_mut_ptr() as *mut c_void,
+            rbig.len() as i32,
+            MPI_INT,
+            0,
+            1,
+            MPI_COMM_WORLD,
+            &mut stat,
+        );
+        assert_eq!(stat.cnt, big.len() as i32 * 4);
+        assert_eq!((rbig, rmid, rsmall), (big, mid, small));
+    }
+
+    MPI_Barrier(MPI_COMM_WORLD);
+    MPI_Finalize();
+}
+
+#[test]
+fn test_cell_geometry() {
+    set_var("MPI_SIZE", "3");
+    set_var("MPI_CELL_COUNT", "3");
+    set_var("MPI_CELL_SIZE", "100");
+    set_var("MPI_CMA_THRESHOLD", "0");
+
+    MPI_Init(null_mut(), null_mut());
+    for var in ["MPI_CELL_COUNT", "MPI_CELL_SIZE", "MPI_CMA_THRESHOLD"] {
+        std::env::remove_var(var);
+    }
+    let mut rank: i32 = 0;
+    MPI_Comm_rank(MPI_COMM_WORLD, &mut rank);
+
+    let left = (rank + 2) % 3;
+    let right = (rank + 1) % 3;
+    let sbuf: Vec<i32> = (0..10000).map(|i| i * 3 + rank).collect();
+    let mut rbuf = vec![0i32; sbuf.len()];
+    let mut stat = MPI_Status::uninit();
+    MPI_Sendrecv(
+        sbuf.as_ptr() as *const c_void,
+        sbuf.len() as i32,
+        MPI_INT,
+        right,
+        0,
+        rbuf.as_mut_ptr() as *mut c_void,
+        rbuf.len() as i32,
+        MPI_INT,
+        left,
+        0,
+        MPI_COMM_WORLD,
+        &mut stat,
+    );
+    assert!(rbuf.iter().enumerate().all(|(i, &v)| v == i as i32 * 3 + left));
+
+    let mut data: Vec<u8> = if rank == 1 {
+        (0..5000).map(|i| i as u8).collect()
+    } else {
+        vec![0; 5000]
+    };
+    MPI_Bcast(
+        data.as_mut_ptr() as *mut c_void,
+        data.len() as i32,
+        MPI_BYTE,
+        1,
+        MPI_COMM_WORLD,
+    );
+    assert!(data.iter().enumerate().all(|(i, &v)| v == i as u8));
+
+    MPI_Barrier(MPI_COMM_WORLD);
+    MPI_Finalize();
+}
+
+#[test]
+fn test_cell_pool() {
+    set_var("MPI_SIZE", "3");
+    set_var("MPI_CELL_COUNT", "8");
+    set_var("MPI_CELL_SIZE", "128");
+    set_var("MPI_CMA_THRESHOLD", "0");
+
+    MPI_Init(null_mut(), null_mut());
+    for var in ["MPI_CELL_COUNT", "MPI_CELL_SIZE", "MPI_CMA_THRESHOLD"] {
+        std::env::remove_var(var);
+    }
+    let mut rank: i32 = 0;
+    MPI_Comm_rank(MPI_COMM_WORLD, &mut rank);
+
+    // The message from 0 to 1 takes all cells of rank 0 while 1 waits for 2,
+    // which in turn waits for a message from 0.
+    let mut big: Vec<i32> = (0..5000).collect();
+    let mut token = [rank];
+    let mut stat = MPI_Status::uninit();
+    match rank {
+        0 => {
+            let mut req: MPI_Request = null_mut();
+            MPI_Isend(
+                big.as_ptr() as *const c_void,
+                big.len() as i32,
+                MPI_INT,
+                1,
+                0,
+                MPI_COMM_WORLD,
+                &mut req,
+            );
+            MPI_Send(
+                token.as_ptr() as *const c_void,
+                1,
+                MPI_INT,
+                2,
+                1,
+                MPI_COMM_WORLD,
+            );
+            MPI_Wait(&mut req, &mut stat);
+        }
+        1 => {
+            big.fill(-1);
+            MPI_Recv(
+                token.as_mut_ptr() as *mut c_void,
+                1,
+                MPI_INT,
+                2,
+                1,
+                MPI_COMM_WORLD,
+                &mut stat,
+            );
+            assert_eq!(token[0], 2);
+            MPI_Recv(
+                big.as_mut_ptr() as *mut c_void,
+                big.len() as i32,
+                MPI_INT,
+                0,
+                0,
+                MPI_COMM_WORLD,
+                &mut stat,
+            );
+            assert!(big.iter().enumerate().all(|(i, &v)| v == i as i32));
+        }
+        _ => {
+            MPI_Recv(
+                token.as_mut_ptr() as *mut c_void,
+                1,
+                MPI_INT,
+                0,
+                1,
+                MPI_COMM_WORLD,
+                &mut stat,
+            );
+            assert_eq!(token[0], 0);
+            token[0] = rank;
+            MPI_Send(
+                token.as_ptr() as *const c_void,
+                1,
+                MPI_INT,
+                1,
+                1,
+                MPI_COMM_WORLD,
+            );
+        }
+    }
+
+    MPI_Barrier(MPI_COMM_WORLD);
+    MPI_Finalize();
+}
+
+#[test]
+fn test_named_segment() {
+    std::env::remove_var("MPI_SIZE");
+    let key = std::process::id();
+    let name = CString::new(format!("/mympi-{}-{key}.2", unsafe { libc::getuid() })).unwrap();
+    let other = CString::new(format!("/mympi-{}-{key}.1", unsafe { libc::getuid() })).unwrap();
+
+    // Left behind by a job with the same key that crashed, and the segment of
+    // another step of the job that is still running.
+    unsafe {
+        for name in [&name, &other] {
+            let fd = libc::shm_open(name.as_ptr(), libc::O_CREAT | libc::O_RDWR, 0o600);
+            assert!(fd != -1);
+            libc::ftruncate(fd, 4096);
+            libc::close(fd);
+        }
+        libc::signal(libc::SIGCHLD, libc::SIG_DFL);
+    }
+    set_var("SLURM_JOBID", key.to_string());
+    set_var("SLURM_STEP_ID", "2");
+    set_var("SLURM_NTASKS_PER_NODE", "2");
+    let child = unsafe { libc::fork() };
+    set_var("SLURM_PROCID", if child == 0 { "1" } else { "0" });
+
+    MPI_Init(null_mut(), null_mut());
+    for var in [
+        "SLURM_JOBID",
+        "SLURM_STEP_ID",
+        "SLURM_NTASKS_PER_NODE",
+        "SLURM_PROCID",
+    ] {
+        std::env::remove_var(var);
+    }
+    let mut rank: i32 = 0;
+    MPI_Comm_rank(MPI_COMM_WORLD, &mut rank);
+    assert_eq!(rank, (child == 0) as i32);
+
+    // Both ranks are attached, nothing is left to clean up.
+    let fd = unsafe { libc::shm_open(name.as_ptr(), libc::O_RDWR, 0) };
+    assert_eq!(fd, -1);
+    let fd = unsafe { libc::shm_open(other.as_ptr(), libc::O_RDWR, 0) };
+    assert!(fd != -1);
+    unsafe { libc::close(fd) };
+
+    let mut data = [rank; 4];
+    let mut stat = MPI_Status::uninit();
+    if rank == 0 {
+        MPI_Recv(
+            data.as_mut_ptr() as *mut c_void,
+            4,
+            MPI_INT,
+            1,
+            0,
+            MPI_COMM_WORLD,
+            &mut stat,
+        );
+        assert_eq!(data, [1; 4]);
+    } else {
+        MPI_Send(
+            data.as_ptr() as *const c_void,
+            4,
+            MPI_INT,
+            0,
+            0,
+            MPI_COMM_WORLD,
+        );
+    }
+
+    MPI_Finalize();
+    let mut status = -1;
+    assert_eq!(unsafe { libc::waitpid(child, &mut status, 0) }, child);
+    assert_eq!(status, 0);
+    unsafe { libc::shm_unlink(other.as_ptr()) };
+}
+
+#[test]
+fn test_wait_policy() {
+    set_var("MPI_SIZE", "4");
+    set_var("MPI_WAIT_POLICY", "futex");
+    set_var("MPI_SPIN_COUNT", "0");
+
+    MPI_Init(null_mut(), null_mut());
+    for var in ["MPI_WAIT_POLICY", "MPI_SPIN_COUNT"] {
+        std::env::remove_var(var);
+    }
+    let mut rank: i32 = 0;
+    MPI_Comm_rank(MPI_COMM_WORLD, &mut rank);
+
+    // The others are asleep by the time the last rank starts sending.
+    if rank == 3 {
+        std::thread::sleep(std::time::Duration::from_millis(50));
+    }
+    let sbuf: Vec<i32> = (0..20000).map(|i| i + rank).collect();
+    let mut rbuf = vec![0i32; sbuf.len()];
+    let mut stat = MPI_Status::uninit();
+    let left = (rank + 3) % 4;
+    MPI_Sendrecv(
+        sbuf.as_ptr() as *const c_void,
+        sbuf.len() as i32,
+        MPI_INT,
+        (rank + 1) % 4,
+        0,
+        rbuf.as_mut_ptr() as *mut c_void,
+        rbuf.len() as i32,
+        MPI_INT,
+        left,
+        0,
+        MPI_COMM_WORLD,
+        &mut stat,
+    );
+    assert!(rbuf.iter().enumerate().all(|(i, &v)| v == i as i32 + left));
+
+    let mut val = [rank * 10];
+    MPI_Bcast(
+        val.as_mut_ptr() as *mut c_void,
+        1,
+        MPI_INT,
+        3,
+        MPI_COMM_WORLD,
+    );
+    assert_eq!(val[0], 30);
+
+    // The readers of a collective cell sleep while they wait for each other.
+    for i in 0..1000 {
+        val[0] = if rank == 0 { i } else { -1 };
+        MPI_Bcast(
+            val.as_mut_ptr() as *mut c_void,
+            1,
+            MPI_INT,
+            0,
+            MPI_COMM_WORLD,
+        );
+        assert_eq!(val[0], i);
+    }
+
+    MPI_Barrier(MPI_COMM_WORLD);
+    MPI_Finalize();
+}
+
+#[test]
+fn test_copy_variant() {
+    set_var("MPI_SIZE", "2");
+    set_var("MPI_USE_NT", "1");
+    // Falls back to the detected variant on CPUs without it.
+    set_var("MPI_COPY", "avx512");
+
+    MPI_Init(null_mut(), null_mut());
+    for var in ["MPI_USE_NT", "MPI_COPY"] {
+        std::env::remove_var(var);
+    }
+    let mut rank: i32 = 0;
+    MPI_Comm_rank(MPI_COMM_WORLD, &mut rank);
+
+    // Odd offsets and lengths go through the unaligned head and tail.
+    let data: Vec<u8> = (0..20011).map(|i| (i % 251) as u8).collect();
+    for (off, len) in [(1, 3), (3, 20001), (7, 8163)] {
+        if rank == 0 {
+            MPI_Send(
+                data[off..].as_ptr() as *const c_void,
+                len,
+                MPI_BYTE,
+                1,
+                0,
+                MPI_COMM_WORLD,
+            );
+        } else {
+            let mut buf = vec![0u8; len as usize + 8];
+            MPI_Recv(
+                buf[5..].as_mut_ptr() as *mut c_void,
+                len,
+                MPI_BYTE,
+                0,
+                0,
+                MPI_COMM_WORLD,
+                null_mut(),
+            );
+            assert_eq!(buf[5..5 + len as usize], data[off..off + len as usize]);
+            assert!(buf[..5]
+                .iter()
+                .chain(&buf[5 + len as usize..])
+                .all(|&v| v == 0));
+        }
+    }
+
+    MPI_Finalize();
+}
+
+#[test]
+fn test_nt_threshold() {
+    set_var("MPI_SIZE", "2");
+    set_var("MPI_NT_THRESHOLD", "4096");
+    set_var("MPI_CMA_THRESHOLD", "0");
+
+    MPI_Init(null_mut(), null_mut());
+    for var in ["MPI_NT_THRESHOLD", "MPI_CMA_THRESHOLD"] {
+        std::env::remove_var(var);
+    }
+    let mut rank: i32 = 0;
+    MPI_Comm_rank(MPI_COMM_WORLD, &mut rank);
+
+    // Below, at and above the threshold, the last one spans several cells.
+    for cnt in [1000, 1024, 5000] {
+        let sbuf: Vec<i32> = (0..cnt).map(|i| i * 2 + rank).collect();
+        let mut rbuf = vec![0i32; cnt as usize];
+        let mut stat = MPI_Status::uninit();
+        MPI_Sendrecv(
+            sbuf.as_ptr() as *const c_void,
+            cnt,
+            MPI_INT,
+            1 - rank,
+            0,
+            rbuf.as_mut_ptr() as *mut c_void,
+            cnt,
+            MPI_INT,
+            1 - rank,
+            0,
+            MPI_COMM_WORLD,
+            &mut stat,
+        );
+        assert!(rbuf
+            .iter()
+            .enumerate()
+            .all(|(i, &v)| v == i as i32 * 2 + 1 - rank));
+    }
+
+    MPI_Finalize();
+}
+
+#[test]
+fn test_datatypes() {
+    set_var("MPI_SIZE", "3");
+
+    MPI_Init(null_mut(), null_mut());
+    let mut rank: i32 = 0;
+    MPI_Comm_rank(MPI_COMM_WORLD, &mut rank);
+
+    for (dtype, expect) in [
+        (MPI_CHAR, 1),
+        (MPI_SHORT, 2),
+        (MPI_LONG, 8),
+        (MPI_LONG_LONG, 8),
+        (MPI_FLOAT, 4),
+        (MPI_UNSIGNED, 4),
+        (MPI_UINT16_T, 2),
+        (MPI_INT64_T, 8),
+        (MPI_C_BOOL, 1),
+        (MPI_C_DOUBLE_COMPLEX, 16),
+    ] {
+        let (mut size, mut lb, mut extent) = (0, -1, 0);
+        assert_eq!(MPI_Type_size(dtype, &mut size), MPI_SUCCESS);
+        assert_eq!(
+            MPI_Type_get_extent(dtype, &mut lb, &mut extent),
+            MPI_SUCCESS
+        );
+        assert_eq!((size, lb, extent), (expect, 0, expect as MPI_Aint));
+    }
+
+    let sbuf: Vec<u16> = (0..1000).map(|i| i + rank as u16).collect();
+    let mut rbuf = vec![0u16; sbuf.len()];
+    MPI_Allreduce(
+        sbuf.as_ptr() as *const c_void,
+        rbuf.as_mut_ptr() as *mut c_void,
+        sbuf.len() as i32,
+        MPI_UINT16_T,
+        MPI_SUM,
+        MPI_COMM_WORLD,
+    );
+    assert!(rbuf.iter().enumerate().all(|(i, &v)| v == i as u16 * 3 + 3));
+
+    let sbuf = [rank as f32 * 0.5, -(rank as f32)];
+    let mut rbuf = [0f32; 2];
+    MPI_Allreduce(
+        sbuf.as_ptr() as *const c_void,
+        rbuf.as_mut_ptr() as *mut c_void,
+        2,
+        MPI_FLOAT,
+        MPI_MAX,
+        MPI_COMM_WORLD,
+    );
+    assert_eq!(rbuf, [1.0, 0.0]);
+
+    let sbuf = [rank as f64, 1.5];
+    let mut rbuf = [0f64; 2];
+    MPI_Allreduce(
+        sbuf.as_ptr() as *const c_void,
+        rbuf.as_mut_ptr() as *mut c_void,
+        1,
+        MPI_C_DOUBLE_COMPLEX,
+        MPI_SUM,
+        MPI_COMM_WORLD,
+    );
+    assert_eq!(rbuf, [3.0, 4.5]);
+
+    // Neither characters nor complex numbers are ordered.
+    MPI_Comm_set_errhandler(MPI_COMM_WORLD, MPI_ERRORS_RETURN);
+    for (dtype, op) in [(MPI_CHAR, MPI_SUM), (MPI_C_DOUBLE_COMPLEX, MPI_MIN)] {
+        let code = MPI_Allreduce(
+            sbuf.as_ptr() as *const c_void,
+            rbuf.as_mut_ptr() as *mut c_void,
+            1,
+            dtype,
+            op,
+            MPI_COMM_WORLD,
+        );
+        assert_eq!(code, MpiError::MPI_ERR_OP as i32);
+    }
+    MPI_Comm_set_errhandler(MPI_COMM_WORLD, MPI_ERRORS_ARE_FATAL);
+
+    if rank == 0 {
+        let vals: [i64; 3] = [-1, i64::MAX, 7];
+        MPI_Send(
+            vals.as_ptr() as *const c_void,
+            3,
+            MPI_INT64_T,
+            1,
+            0,
+            MPI_COMM_WORLD,
+        );
+    } else if rank == 1 {
+        let mut vals = [0i64; 3];
+        let mut stat = MPI_Status::uninit();
+        MPI_Recv(
+            vals.as_mut_ptr() as *mut c_void,
+            3,
+            MPI_INT64_T,
+            0,
+            0,
+            MPI_COMM_WORLD,
+            &mut stat,
+        );
+        let mut cnt = 0;
+        MPI_Get_count(&stat, MPI_INT64_T, &mut cnt);
+        assert_eq!((vals, cnt), ([-1, i64::MAX, 7], 3));
+    }
+
+    MPI_Finalize();
+}
+
+#[test]
+fn test_derived_types() {
+    set_var("MPI_SIZE", "2");
+
+    MPI_Init(null_mut(), null_mut());
+    let mut rank: i32 = 0;
+    MPI_Comm_rank(MPI_COMM_WORLD, &mut rank);
+    let peer = 1 - rank;
+
+    // A column of a 6x5 matrix, exchanged as the halo of the neighbour.
+    let mut column = MPI_DATATYPE_NULL;
+    MPI_Type_vector(6, 1, 5, MPI_DOUBLE, &mut column);
+    MPI_Type_commit(&mut column);
+    let (mut size, mut lb, mut extent) = (0, -1, 0);
+    MPI_Type_size(column, &mut size);
+    MPI_Type_get_extent(column, &mut lb, &mut extent);
+    assert_eq!((size, lb, extent), (48, 0, 208));
+
+    let mut matrix: Vec<f64> = (0..30).map(|i| (rank * 100 + i) as f64).collect();
+    let mut stat = MPI_Status::uninit();
+    let scol = if rank == 0 { 3 } else { 1 };
+    let rcol = if rank == 0 { 4 } else { 0 };
+    MPI_Sendrecv(
+        matrix[scol..].as_ptr() as *const c_void,
+        1,
+        column,
+        peer,
+        0,
+        matrix[rcol..].as_mut_ptr() as *mut c_void,
+        1,
+        column,
+        peer,
+        0,
+        MPI_COMM_WORLD,
+        &mut stat,
+    );
+    let mut cnt = 0;
+    MPI_Get_count(&stat, column, &mut cnt);
+    assert_eq!(cnt, 1);
+    let pcol = if rank == 0 { 1 } else { 3 };
+    for (i, val) in matrix.iter().enumerate() {
+        let expect = if i % 5 == rcol {
+            (peer * 100) as usize + i - rcol + pcol
+        } else {
+            rank as usize * 100 + i
+        };
+        assert_eq!(*val, expect as f64);
+    }
+
+    // Indexed and subarray layouts arrive as plain ints.
+    let mut indexed = MPI_DATATYPE_NULL;
+    MPI_Type_indexed(2, [2, 1].as_ptr(), [3, 0].as_ptr(), MPI_INT, &mut indexed);
+    let mut sub = MPI_DATATYPE_NULL;
+    MPI_Type_create_subarray(
+        2,
+        [4, 6].as_ptr(),
+        [2, 3].as_ptr(),
+        [1, 2].as_ptr(),
+        MPI_ORDER_C,
+        MPI_INT,
+        &mut sub,
+    );
+    MPI_Type_commit(&mut indexed);
+    MPI_Type_commit(&mut sub);
+    MPI_Type_get_extent(sub, &mut lb, &mut extent);
+    assert_eq!((lb, extent), (0, 96));
+
+    let ints: Vec<i32> = (0..24).collect();
+    if rank == 0 {
+        MPI_Send(
+            ints.as_ptr() as *const c_void,
+            2,
+            indexed,
+            1,
+            1,
+            MPI_COMM_WORLD,
+        );
+        MPI_Send(ints.as_ptr() as *const c_void, 1, sub, 1, 2, MPI_COMM_WORLD);
+    } else {
+        let mut rbuf = [0i32; 6];
+        MPI_Recv(
+            rbuf.as_mut_ptr() as *mut c_void,
+            6,
+            MPI_INT,
+            0,
+            1,
+            MPI_COMM_WORLD,
+            &mut stat,
+        );
+        assert_eq!(rbuf, [3, 4, 0, 8, 9, 5]);
+        MPI_Recv(
+            rbuf.as_mut_ptr() as *mut c_void,
+            6,
+            MPI_INT,
+            0,
+            2,
+            MPI_COMM_WORLD,
+            &mut stat,
+        );
+        assert_eq!(rbuf, [8, 9, 10, 14, 15, 16]);
+
+        // 24 bytes are two indexed elements but no whole five-int or
+        // zero-size element.
+        let (mut five, mut empty) = (MPI_DATATYPE_NULL, MPI_DATATYPE_NULL);
+        MPI_Type_contiguous(5, MPI_INT, &mut five);
+        MPI_Type_contiguous(0, MPI_INT, &mut empty);
+        MPI_Type_commit(&mut five);
+        MPI_Type_commit(&mut empty);
+        let mut cnt = 0;
+        MPI_Get_count(&stat, indexed, &mut cnt);
+        assert_eq!(cnt, 2);
+        MPI_Get_count(&stat, five, &mut cnt);
+        assert_eq!(cnt, MPI_UNDEFINED);
+        MPI_Get_count(&stat, empty, &mut cnt);
+        assert_eq!(cnt, MPI_UNDEFINED);
+        MPI_Type_free(&mut five);
+        MPI_Type_free(&mut empty);
+    }
+
+    MPI_Comm_set_errhandler(MPI_COMM_WORLD, MPI_ERRORS_RETURN);
+    let mut bad = MPI_DATATYPE_NULL;
+    let code = MPI_Type_create_subarray(
+        2,
+        [4, 6].as_ptr(),
+        [2, 3].as_ptr(),
+        [3, 2].as_ptr(),
+        MPI_ORDER_C,
+        MPI_INT,
+        &mut bad,
+    );
+    assert_eq!(code, MpiError::MPI_ERR_ARG as i32);
+    MPI_Comm_set_errhandler(MPI_COMM_WORLD, MPI_ERRORS_ARE_FATAL);
+
+    // A struct with padding, received in place without touching the gaps.
+    #[repr(C)]
+    #[derive(Clone, Copy, PartialEq, Debug)]
+    struct Pair {
+        id: i32,
+        pad: i32,
+        val: f64,
+    }
+    let mut pair = MPI_DATATYPE_NULL;
+    MPI_Type_create_struct(
+        2,
+        [1, 1].as_ptr(),
+        [0, 8].as_ptr(),
+        [MPI_INT, MPI_DOUBLE].as_ptr(),
+        &mut pair,
+    );
+    MPI_Type_commit(&mut pair);
+    MPI_Type_size(pair, &mut size);
+    MPI_Type_get_extent(pair, &mut lb, &mut extent);
+    assert_eq!((size, lb, extent), (12, 0, 16));
+
+    let pairs: Vec<Pair> = (0..3)
+        .map(|i| Pair {
+            id: i,
+            pad: -1,
+            val: i as f64 / 2.0,
+        })
+        .collect();
+    let mut rpairs = vec![
+        Pair {
+            id: 0,
+            pad: 7,
+            val: 0.0,
+        };
+        3
+    ];
+    MPI_Sendrecv(
+        pairs.as_ptr() as *const c_void,
+        3,
+        pair,
+        peer,
+        3,
+        rpairs.as_mut_ptr() as *mut c_void,
+        3,
+        pair,
+        peer,
+        3,
+        MPI_COMM_WORLD,
+        &mut stat,
+    );
+    for (i, p) in rpairs.iter().enumerate() {
+        assert_eq!((p.id, p.pad, p.val), (i as i32, 7, i as f64 / 2.0));
+    }
+
+    // Large enough for the rendezvous protocol on the contiguous side.
+    let mut strided = MPI_DATATYPE_NULL;
+    MPI_Type_vector(40000, 1, 2, MPI_DOUBLE, &mut strided);
+    MPI_Type_commit(&mut strided);
+    let dense: Vec<f64> = (0..40000).map(|i| i as f64).collect();
+    let mut sparse = vec![-1f64; 80000];
+    if rank == 0 {
+        MPI_Send(
+            dense.as_ptr() as *const c_void,
+            40000,
+            MPI_DOUBLE,
+            1,
+            4,
+            MPI_COMM_WORLD,
+        );
+        MPI_Recv(
+            sparse.as_mut_ptr() as *mut c_void,
+            1,
+            strided,
+            1,
+            5,
+            MPI_COMM_WORLD,
+            &mut stat,
+        );
+    } else {
+        MPI_Recv(
+            sparse.as_mut_ptr() as *mut c_void,
+            1,
+            strided,
+            0,
+            4,
+            MPI_COMM_WORLD,
+            &mut stat,
+        );
+        MPI_Send(
+            sparse.as_ptr() as *const c_void,
+            1,
+            strided,
+            0,
+            5,
+            MPI_COMM_WORLD,
+        );
+    }
+    for (i, val) in sparse.iter().enumerate() {
+        let expect = if i % 2 == 0 { (i / 2) as f64 } else { -1.0 };
+        assert_eq!(*val, expect);
+    }
+
+    // Types have to be committed before use and are gone once freed.
+    let mut raw = MPI_DATATYPE_NULL;
+    MPI_Type_contiguous(4, MPI_INT, &mut raw);
+    MPI_Comm_set_errhandler(MPI_COMM_WORLD, MPI_ERRORS_RETURN);
+    let code = MPI_Send(
+        ints.as_ptr() as *const c_void,
+        1,
+        raw,
+        peer,
+        6,
+        MPI_COMM_WORLD,
+    );
+    assert_eq!(code, MpiError::MPI_ERR_TYPE as i32);
+    MPI_Comm_set_errhandler(MPI_COMM_WORLD, MPI_ERRORS_ARE_FATAL);
+    for dtype in [
+        &mut column,
+        &mut indexed,
+        &mut sub,
+        &mut pair,
+        &mut strided,
+        &mut raw,
+    ] {
+        assert_eq!(MPI_Type_free(dtype), MPI_SUCCESS);
+        assert_eq!(*dtype, MPI_DATATYPE_NULL);
+    }
+
+    MPI_Finalize();
+}
+
+#[test]
+fn test_pack() {
+    set_var("MPI_SIZE", "2");
+
+    MPI_Init(null_mut(), null_mut());
+    let mut rank: i32 = 0;
+    MPI_Comm_rank(MPI_COMM_WORLD, &mut rank);
+
+    let mut every_other = MPI_DATATYPE_NULL;
+    MPI_Type_vector(3, 1, 2, MPI_DOUBLE, &mut every_other);
+    MPI_Type_commit(&mut every_other);
+
+    let mut total = 0;
+    for (cnt, dtype) in [(1, MPI_INT), (3, MPI_DOUBLE), (1, every_other)] {
+        let mut size = 0;
+        MPI_Pack_size(cnt, dtype, MPI_COMM_WORLD, &mut size);
+        total += size;
+    }
+    assert_eq!(total, 52);
+
+    let mut packed = vec![0u8; 64];
+    if rank == 0 {
+        let (cnt, vals, sparse) = (3i32, [1.5f64, 2.5, 3.5], [0.0f64, -1.0, 2.0, -1.0, 4.0]);
+        let mut pos = 0;
+        MPI_Pack(
+            &cnt as *const i32 as *const c_void,
+            1,
+            MPI_INT,
+            packed.as_mut_ptr() as *mut c_void,
+            64,
+            &mut pos,
+            MPI_COMM_WORLD,
+        );
+        MPI_Pack(
+            vals.as_ptr() as *const c_void,
+            3,
+            MPI_DOUBLE,
+            packed.as_mut_ptr() as *mut c_void,
+            64,
+            &mut pos,
+            MPI_COMM_WORLD,
+        );
+        MPI_Pack(
+            sparse.as_ptr() as *const c_void,
+            1,
+            every_other,
+            packed.as_mut_ptr() as *mut c_void,
+            64,
+            &mut pos,
+            MPI_COMM_WORLD,
+        );
+        assert_eq!(pos, 52);
+
+        // The buffer has no room left for another three doubles.
+        MPI_Comm_set_errhandler(MPI_COMM_WORLD, MPI_ERRORS_RETURN);
+        let code = MPI_Pack(
+            vals.as_ptr() as *const c_void,
+            3,
+            MPI_DOUBLE,
+            packed.as_mut_ptr() as *mut c_void,
+            64,
+            &mut pos,
+            MPI_COMM_WORLD,
+        );
+        assert_eq!((code, pos), (MpiError::MPI_ERR_TRUNCATE as i32, 52));
+        MPI_Comm_set_errhandler(MPI_COMM_WORLD, MPI_ERRORS_ARE_FATAL);
+
+        MPI_Send(
+            packed.as_ptr() as *const c_void,
+            pos,
+            MPI_PACKED,
+            1,
+            0,
+            MPI_COMM_WORLD,
+        );
+
+        let mut packer = Packer::new();
+        packer.pack(&[7i32, 8]).unwrap().pack(&[0.25f64]).unwrap();
+        assert_eq!(packer.len(), 16);
+        MPI_Send(
+            packer.as_ptr() as *const c_void,
+            packer.len() as i32,
+            MPI_PACKED,
+            1,
+            1,
+            MPI_COMM_WORLD,
+        );
+    } else {
+        let mut stat = MPI_Status::uninit();
+        MPI_Probe(0, 0, MPI_COMM_WORLD, &mut stat);
+        let mut len = 0;
+        MPI_Get_count(&stat, MPI_PACKED, &mut len);
+        assert_eq!(len, 52);
+        MPI_Recv(
+            packed.as_mut_ptr() as *mut c_void,
+            len,
+            MPI_PACKED,
+            0,
+            0,
+            MPI_COMM_WORLD,
+            &mut stat,
+        );
+
+        let (mut cnt, mut vals, mut sparse) = (0i32, [0f64; 3], [9f64; 5]);
+        let mut pos = 0;
+        MPI_Unpack(
+            packed.as_ptr() as *const c_void,
+            len,
+            &mut pos,
+            &mut cnt as *mut i32 as *mut c_void,
+            1,
+            MPI_INT,
+            MPI_COMM_WORLD,
+        );
+        MPI_Unpack(
+            packed.as_ptr() as *const c_void,
+            len,
+            &mut pos,
+            vals.as_mut_ptr() as *mut c_void,
+            cnt,
+            MPI_DOUBLE,
+            MPI_COMM_WORLD,
+        );
+        MPI_Unpack(
+            packed.as_ptr() as *const c_void,
+            len,
+            &mut pos,
+            sparse.as_mut_ptr() as *mut c_void,
+            1,
+            every_other,
+            MPI_COMM_WORLD,
+        );
+        assert_eq!(pos, 52);
+        assert_eq!(vals, [1.5, 2.5, 3.5]);
+        assert_eq!(sparse, [0.0, 9.0, 2.0, 9.0, 4.0]);
+
+        MPI_Recv(
+            packed.as_mut_ptr() as *mut c_void,
+            64,
+            MPI_PACKED,
+            0,
+            1,
+            MPI_COMM_WORLD,
+            &mut stat,
+        );
+        let (mut ints, mut val) = ([0i32; 2], [0f64]);
+        let mut unpacker = Unpacker::new(&packed[..16]);
+        unpacker
+            .unpack(&mut ints)
+            .unwrap()
+            .unpack(&mut val)
+            .unwrap();
+        assert_eq!((ints, val, unpacker.position()), ([7, 8], [0.25], 16));
+    }
+
+    MPI_Type_free(&mut every_other);
+    MPI_Finalize();
+}
+
+#[test]
+fn test_pack_external() {
+    set_var("MPI_SIZE", "1");
+
+    MPI_Init(null_mut(), null_mut());
+    let datarep = CString::new("external32").unwrap();
+
+    // Native bytes of one element and their external32 encoding.
+    macro_rules! case {
+        ($dtype:expr, $val:expr) => {
+            case!($dtype, $val, $val)
+        };
+        ($dtype:expr, $native:expr, $external:expr) => {
+            (
+                $dtype,
+                $native.to_ne_bytes().to_vec(),
+                $external.to_be_bytes().to_vec(),
+            )
+        };
+    }
+    let cases = [
+        case!(MPI_BYTE, 0xabu8),
+        case!(MPI_CHAR, b'z'),
+        case!(MPI_SHORT, -2i16),
+        case!(MPI_INT, 0x1234567i32),
+        case!(MPI_LONG, -5i64, -5i32),
+        case!(MPI_LONG_LONG, -7i64 << 40),
+        case!(MPI_FLOAT, 1.25f32),
+        case!(MPI_DOUBLE, -0.1f64),
+        case!(MPI_UNSIGNED_CHAR, 0xfeu8),
+        case!(MPI_UNSIGNED_SHORT, 0xbeefu16),
+        case!(MPI_UNSIGNED, 0xdeadbeefu32),
+        case!(MPI_UNSIGNED_LONG, 0xfeedu64, 0xfeedu32),
+        case!(MPI_UNSIGNED_LONG_LONG, 3u64 << 60),
+        case!(MPI_INT8_T, -128i8),
+        case!(MPI_INT16_T, -300i16),
+        case!(MPI_INT32_T, -70000i32),
+        case!(MPI_INT64_T, -1i64 << 33),
+        case!(MPI_UINT8_T, 0x7fu8),
+        case!(MPI_UINT16_T, 0x102u16),
+        case!(MPI_UINT32_T, 0x1020304u32),
+        case!(MPI_UINT64_T, 5u64 << 50),
+        case!(MPI_C_BOOL, 1u8),
+        (
+            MPI_C_DOUBLE_COMPLEX,
+            [1.5f64.to_ne_bytes(), (-2.0f64).to_ne_bytes()].concat(),
+            [1.5f64.to_be_bytes(), (-2.0f64).to_be_bytes()].concat(),
+        ),
+        case!(MPI_PACKED, 0x42u8),
+    ];
+    for (dtype, native, external) in &cases {
+        let mut size = 0;
+        MPI_Pack_external_size(datarep.as_ptr(), 1, *dtype, &mut size);
+        assert_eq!(size, external.len() as MPI_Aint);
+
+        let mut packed = vec![0u8; external.len()];
+        let mut pos = 0;
+        MPI_Pack_external(
+            datarep.as_ptr(),
+            native.as_ptr() as *const c_void,
+            1,
+            *dtype,
+            packed.as_mut_ptr() as *mut c_void,
+            size,
+            &mut pos,
+        );
+        assert_eq!((&packed, pos), (external, size));
+
+        let mut unpacked = vec![0u8; native.len()];
+        pos = 0;
+        MPI_Unpack_external(
+            datarep.as_ptr(),
+            packed.as_ptr() as *const c_void,
+            size,
+            &mut pos,
+            unpacked.as_mut_ptr() as *mut c_void,
+            1,
+            *dtype,
+        );
+        assert_eq!((&unpacked, pos), (native, size));
+    }
+
+    // Every other long of a derived type, each one shrunk to four bytes.
+    let mut every_other = MPI_DATATYPE_NULL;
+    MPI_Type_vector(2, 1, 2, MPI_LONG, &mut every_other);
+    MPI_Type_commit(&mut every_other);
+    let sparse = [1i64, -1, -2, -1];
+    let mut packed = [0u8; 8];
+    let mut pos = 0;
+    MPI_Pack_external(
+        datarep.as_ptr(),
+        sparse.as_ptr() as *const c_void,
+        1,
+        every_other,
+        packed.as_mut_ptr() as *mut c_void,
+        8,
+        &mut pos,
+    );
+    assert_eq!(packed, [0, 0, 0, 1, 0xff, 0xff, 0xff, 0xfe]);
+    let mut dense = [0i64; 3];
+    pos = 0;
+    MPI_Unpack_external(
+        datarep.as_ptr(),
+        packed.as_ptr() as *const c_void,
+        8,
+        &mut pos,
+        dense.as_mut_ptr() as *mut c_void,
+        1,
+        every_other,
+    );
+    assert_eq!(dense, [1, 0, -2]);
+    MPI_Type_free(&mut every_other);
+
+    MPI_Comm_set_errhandler(MPI_COMM_WORLD, MPI_ERRORS_RETURN);
+    let big = [1i64 << 40];
+    pos = 0;
+    let code = MPI_Pack_external(
+        datarep.as_ptr(),
+        big.as_ptr() as *const c_void,
+        1,
+        MPI_LONG,
+        packed.as_mut_ptr() as *mut c_void,
+        8,
+        &mut pos,
+    );
+    assert_eq!((code, pos), (MpiError::MPI_ERR_CONVERSION as i32, 0));
+
+    let native = CString::new("native").unwrap();
+    let mut size = 0;
+    let code = MPI_Pack_external_size(native.as_ptr(), 1, MPI_INT, &mut size);
+    assert_eq!(code, MpiError::MPI_ERR_ARG as i32);
+    MPI_Comm_set_errhandler(MPI_COMM_WORLD, MPI_ERRORS_ARE_FATAL);
+    MPI_Finalize();
+}
+
+#[derive(Typed, Clone, Copy, PartialEq, Debug)]
+#[repr(C)]
+struct Particle {
+    id: i32,
+    pos: [f64; 3],
+    charge: u8,
+}
+
+#[derive(Typed, Clone, Copy, PartialEq, Debug)]
+#[repr(C)]
+struct Grid([[i32; 2]; 3]);
+
+#[test]
+fn test_derive_typed() {
+    set_var("MPI_SIZE", "2");
+
+    let mut obj = MpiObject::new();
+    let rank = MpiObject::rank();
+    let comm = obj.get_comm(MPI_COMM_WORLD).unwrap();
+
+    // The padding after `id` and `charge` is not part of the data.
+    let dtype = Particle::into_mpi();
+    let (mut size, mut lb, mut extent) = (0, -1, 0);
+    MPI_Type_size(dtype, &mut size);
+    MPI_Type_get_extent(dtype, &mut lb, &mut extent);
+    assert_eq!((size, lb, extent), (29, 0, 40));
+    assert_eq!(Particle::into_mpi(), dtype);
+
+    let particles: Vec<Particle> = (0..4)
+        .map(|i| Particle {
+            id: i,
+            pos: [i as f64, 0.5, -1.0],
+            charge: i as u8 * 2,
+        })
+        .collect();
+    let mut packer = Packer::new();
+    packer.pack(&particles).unwrap();
+    assert_eq!(packer.len(), 4 * 29);
+
+    let grid = Grid([[1, 2], [3, 4], [5, 6]]);
+    if rank == 0 {
+        comm.send_slice(&particles, 1, 0).unwrap();
+        comm.send_slice(&[grid], 1, 1).unwrap();
+    } else {
+        let mut recvd = [Particle {
+            id: -1,
+            pos: [0.0; 3],
+            charge: 0,
+        }; 4];
+        comm.recv_slice(&mut recvd, 0, 0).unwrap();
+        assert_eq!(recvd[..], particles[..]);
+
+        let mut grids = [Grid([[0; 2]; 3])];
+        comm.recv_slice(&mut grids, 0, 1).unwrap();
+        assert_eq!(grids, [grid]);
+    }
+}
+
+#[test]
+fn test_typed_primitives() {
+    set_var("MPI_SIZE", "2");
+
+    let mut obj = MpiObject::new();
+    let rank = MpiObject::rank();
+    let comm = obj.get_comm(MPI_COMM_WORLD).unwrap();
+
+    assert_eq!(u64::into_mpi(), MPI_UINT64_T);
+    assert_eq!(i16::into_mpi(), MPI_INT16_T);
+    assert_eq!(f32::into_mpi(), MPI_FLOAT);
+    assert_eq!(bool::into_mpi(), MPI_C_BOOL);
+    let (mut size, mut lb, mut extent) = (0, -1, 0);
+    MPI_Type_size(<(u8, f64)>::into_mpi(), &mut size);
+    MPI_Type_get_extent(<(u8, f64)>::into_mpi(), &mut lb, &mut extent);
+    assert_eq!((size, lb, extent), (9, 0, 16));
+
+    let longs = vec![u64::MAX, 0, 1 << 40];
+    let shorts = [-3i16, 300];
+    let words = [0xdeadbeefu32, 7];
+    let signed = [i64::MIN, -1];
+    let sizes = [usize::MAX, 42];
+    let floats = [0.5f32, -8.25];
+    let flags = [true, false, true];
+    let rows = [[1i32, 2, 3], [4, 5, 6]];
+    let pairs = [(1u8, 0.5f64), (2, -1.5), (3, 1e10)];
+    if rank == 0 {
+        comm.send(&longs, 1, 0).unwrap();
+        comm.send_slice(&shorts, 1, 1).unwrap();
+        comm.send_slice(&words, 1, 2).unwrap();
+        comm.send_slice(&signed, 1, 3).unwrap();
+        comm.send_slice(&sizes, 1, 4).unwrap();
+        comm.send_slice(&floats, 1, 5).unwrap();
+        comm.send_slice(&flags, 1, 6).unwrap();
+        comm.send_slice(&rows, 1, 7).unwrap();
+        comm.send_slice(&pairs, 1, 8).unwrap();
+    } else {
+        let mut rlongs: Data<u64> = Data::new(3);
+        comm.recv(&mut rlongs, 0, 0).unwrap();
+        assert_eq!(rlongs.into_slice(), longs);
+        let mut rshorts = [0i16; 2];
+        comm.recv_slice(&mut rshorts, 0, 1).unwrap();
+        assert_eq!(rshorts, shorts);
+        let mut rwords = [0u32; 2];
+        comm.recv_slice(&mut rwords, 0, 2).unwrap();
+        assert_eq!(rwords, words);
+        let mut rsigned = [0i64; 2];
+        comm.recv_slice(&mut rsigned, 0, 3).unwrap();
+        assert_eq!(rsigned, signed);
+        let mut rsizes = [0usize; 2];
+        comm.recv_slice(&mut rsizes, 0, 4).unwrap();
+        assert_eq!(rsizes, sizes);
+        let mut rfloats = [0f32; 2];
+        comm.recv_slice(&mut rfloats, 0, 5).unwrap();
+        assert_eq!(rfloats, floats);
+        let mut rflags = [false; 3];
+        comm.recv_slice(&mut rflags, 0, 6).unwrap();
+        assert_eq!(rflags, flags);
+        let mut rrows = [[0i32; 3]; 2];
+        comm.recv_slice(&mut rrows, 0, 7).unwrap();
+        assert_eq!(rrows, rows);
+        let mut rpairs = [(0u8, 0f64); 3];
+        comm.recv_slice(&mut rpairs, 0, 8).unwrap();
+        assert_eq!(rpairs, pairs);
+    }
+}
+
 #[test]
 fn test_obj() {
     set_var("MPI_SIZE", "2");